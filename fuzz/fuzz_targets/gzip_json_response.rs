@@ -0,0 +1,20 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use replication_game::config::GameConfig;
+use replication_game::encoding::{self, Encoding};
+use replication_game::models::proof;
+
+const LIMIT: u64 = 1 << 20;
+
+// Mirrors the `Compressed<Json<proof::Envelope>>` data guard, and the routes
+// behind it opening the envelope.
+fuzz_target!(|data: &[u8]| {
+    let max_ratio = GameConfig::default().max_ratio;
+    if let Ok(envelope) =
+        encoding::decode::<proof::Envelope, _>(Encoding::Gzip, data, LIMIT, max_ratio)
+    {
+        let _ = envelope.open();
+    }
+});
@@ -0,0 +1,93 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use byteorder::{ByteOrder, LittleEndian};
+use lazy_static::lazy_static;
+
+use replication_game::config::GameConfig;
+use replication_game::models::proof;
+use replication_game::models::seed::Seed;
+use replication_game::proofs;
+
+lazy_static! {
+    // One valid proof per algorithm, the fuzzer swaps in arbitrary params.
+    static ref RESPONSES: Vec<proof::Response> = {
+        let seed = Seed {
+            timestamp: 0,
+            seed: "00".repeat(32),
+        };
+        let drgporep = proof::Params {
+            typ: proof::ProofType::DrgPoRep,
+            hasher: proof::HasherType::Pedersen,
+            size: 1024,
+            challenge_count: 1,
+            vde: 1,
+            degree: 3,
+            replicas: 1,
+            zigzag: None,
+        };
+        let zigzag = proof::Params {
+            typ: proof::ProofType::Zigzag,
+            hasher: proof::HasherType::Pedersen,
+            size: 1024,
+            challenge_count: 1,
+            vde: 1,
+            degree: 3,
+            replicas: 1,
+            zigzag: Some(proof::ZigZagParams {
+                expansion_degree: 8,
+                layers: 2,
+                is_tapered: false,
+                taper_layers: 0,
+                taper: 0.,
+                partitions: 1,
+            }),
+        };
+
+        vec![
+            proofs::porep_work("fuzz".into(), drgporep, seed.clone()),
+            proofs::zigzag_work("fuzz".into(), zigzag, seed),
+        ]
+    };
+}
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 20 {
+        return;
+    }
+
+    let mut res = RESPONSES[(data[0] & 1) as usize].clone();
+
+    res.proof_params = proof::Params {
+        typ: if data[0] & 2 == 0 {
+            proof::ProofType::DrgPoRep
+        } else {
+            proof::ProofType::Zigzag
+        },
+        hasher: match (data[0] >> 2) & 3 {
+            1 => proof::HasherType::Blake2s,
+            2 => proof::HasherType::Sha256,
+            _ => proof::HasherType::Pedersen,
+        },
+        size: LittleEndian::read_u32(&data[1..5]) as usize,
+        challenge_count: data[5] as usize,
+        vde: data[6] as usize,
+        degree: data[7] as usize,
+        replicas: 1 + ((data[0] >> 4) & 3) as usize,
+        zigzag: if data[8] & 1 == 0 {
+            None
+        } else {
+            Some(proof::ZigZagParams {
+                expansion_degree: data[9] as usize,
+                layers: data[10] as usize,
+                is_tapered: data[8] & 2 != 0,
+                taper_layers: data[11] as usize,
+                taper: f64::from_bits(LittleEndian::read_u64(&data[12..20])),
+                partitions: (data[8] >> 2) as usize,
+            })
+        },
+    };
+
+    let _ = proofs::validate(&res, &GameConfig::default());
+});
@@ -0,0 +1,10 @@
+CREATE TABLE submissions (
+  hash TEXT NOT NULL,
+  prover TEXT NOT NULL,
+  valid BOOLEAN NOT NULL,
+  code TEXT,
+  message TEXT,
+  flagged BOOLEAN NOT NULL DEFAULT FALSE,
+  created_at BIGINT NOT NULL,
+  PRIMARY KEY (hash, prover)
+);
@@ -0,0 +1,4 @@
+ALTER TABLE params
+DROP COLUMN hasher;
+
+DROP TYPE hasher_type;
@@ -0,0 +1,4 @@
+CREATE TYPE hasher_type as ENUM ('pedersen', 'blake2s', 'sha256');
+
+ALTER TABLE params
+ADD COLUMN hasher hasher_type NOT NULL DEFAULT 'pedersen';
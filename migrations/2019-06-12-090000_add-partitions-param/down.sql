@@ -0,0 +1,2 @@
+ALTER TABLE params
+DROP COLUMN partitions;
@@ -0,0 +1,2 @@
+ALTER TABLE params
+ADD COLUMN partitions INT;
@@ -0,0 +1,11 @@
+DELETE FROM leaderboard
+WHERE params_id IN (SELECT id FROM params WHERE typ = 'zigzag_snark');
+DELETE FROM params WHERE typ = 'zigzag_snark';
+
+ALTER TYPE proof_type RENAME TO proof_type_old;
+CREATE TYPE proof_type as ENUM ('zigzag', 'drgporep');
+
+ALTER TABLE params
+ALTER COLUMN typ TYPE proof_type USING typ::text::proof_type;
+
+DROP TYPE proof_type_old;
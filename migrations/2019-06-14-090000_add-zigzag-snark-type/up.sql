@@ -0,0 +1,8 @@
+-- ALTER TYPE ... ADD VALUE cannot run inside the migration's transaction.
+ALTER TYPE proof_type RENAME TO proof_type_old;
+CREATE TYPE proof_type as ENUM ('zigzag', 'drgporep', 'zigzag_snark');
+
+ALTER TABLE params
+ALTER COLUMN typ TYPE proof_type USING typ::text::proof_type;
+
+DROP TYPE proof_type_old;
@@ -0,0 +1,6 @@
+CREATE TABLE extractions (
+  id SERIAL PRIMARY KEY,
+  prover TEXT NOT NULL,
+  extraction_time_ms BIGINT NOT NULL,
+  params_id BIGINT NOT NULL
+);
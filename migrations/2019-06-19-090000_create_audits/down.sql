@@ -0,0 +1,4 @@
+ALTER TABLE leaderboard
+DROP COLUMN missed_audits;
+
+DROP TABLE audits;
@@ -0,0 +1,12 @@
+CREATE TABLE audits (
+  id SERIAL PRIMARY KEY,
+  prover TEXT NOT NULL,
+  params_id BIGINT NOT NULL,
+  comm_r TEXT NOT NULL,
+  issued_at BIGINT NOT NULL,
+  deadline BIGINT NOT NULL,
+  passed BOOLEAN
+);
+
+ALTER TABLE leaderboard
+ADD COLUMN missed_audits INT NOT NULL DEFAULT 0;
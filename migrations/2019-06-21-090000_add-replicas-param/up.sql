@@ -0,0 +1,2 @@
+ALTER TABLE params
+ADD COLUMN replicas INT NOT NULL DEFAULT 1;
@@ -0,0 +1,9 @@
+CREATE TABLE extraction_challenges (
+  id SERIAL PRIMARY KEY,
+  prover TEXT NOT NULL,
+  params_id BIGINT NOT NULL,
+  seed TEXT NOT NULL,
+  comm_d TEXT NOT NULL,
+  issued_at_ms BIGINT NOT NULL,
+  answered BOOLEAN NOT NULL DEFAULT FALSE
+);
@@ -0,0 +1,3 @@
+ALTER TABLE leaderboard
+DROP COLUMN synthesis_time_ms,
+DROP COLUMN proving_time_ms;
@@ -0,0 +1,3 @@
+ALTER TABLE leaderboard
+ADD COLUMN synthesis_time_ms BIGINT,
+ADD COLUMN proving_time_ms BIGINT;
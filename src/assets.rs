@@ -0,0 +1,286 @@
+//! Static assets, served with precompressed `.gz` and `.br` sidecars and
+//! strong `ETag`s.
+//!
+//! The asset directory is indexed once at startup. Sidecars are written next
+//! to every compressible file that is missing them or has changed since they
+//! were generated, so that responses never have to be compressed on the fly.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use blake2::{Blake2b, Digest};
+use rocket::http::{ContentType, Status};
+use rocket::request::{self, FromRequest};
+use rocket::response::{Responder, Response};
+use rocket::{Outcome, Request};
+
+use crate::encoding::{self, Encoding};
+
+/// Precompressed encodings, most preferred first, with their file extension.
+const SIDECARS: [(Encoding, &str); 2] = [(Encoding::Brotli, "br"), (Encoding::Gzip, "gz")];
+
+#[derive(Debug)]
+pub struct Asset {
+    path: PathBuf,
+    content_type: Option<ContentType>,
+    hash: String,
+    sidecars: Vec<Encoding>,
+}
+
+impl Asset {
+    fn load(path: PathBuf) -> io::Result<Asset> {
+        let body = fs::read(&path)?;
+        let hash = hex::encode(&Blake2b::digest(&body)[..16]);
+
+        let content_type = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(ContentType::from_extension);
+
+        let compressible = body.len() >= encoding::MIN_COMPRESS_SIZE
+            && content_type
+                .as_ref()
+                .map(encoding::is_compressible)
+                .unwrap_or(false);
+
+        let mut sidecars = Vec::new();
+        if compressible {
+            for &(encoding, ext) in SIDECARS.iter() {
+                match write_sidecar(&path, &body, encoding, ext) {
+                    Ok(()) => sidecars.push(encoding),
+                    Err(err) => println!(
+                        "Warning: could not write {} sidecar for {}: {}",
+                        ext,
+                        path.display(),
+                        err
+                    ),
+                }
+            }
+        }
+
+        Ok(Asset {
+            path,
+            content_type,
+            hash,
+            sidecars,
+        })
+    }
+
+    /// The strong `ETag` of the given representation of this asset.
+    pub fn etag(&self, encoding: Option<Encoding>) -> String {
+        match encoding {
+            Some(encoding) => format!("\"{}-{}\"", self.hash, encoding),
+            None => format!("\"{}\"", self.hash),
+        }
+    }
+
+    fn file(&self, encoding: Option<Encoding>) -> PathBuf {
+        match encoding.and_then(extension) {
+            Some(ext) => sidecar_path(&self.path, ext),
+            None => self.path.clone(),
+        }
+    }
+}
+
+fn extension(encoding: Encoding) -> Option<&'static str> {
+    SIDECARS
+        .iter()
+        .find(|(e, _)| *e == encoding)
+        .map(|(_, ext)| *ext)
+}
+
+fn sidecar_path(path: &Path, ext: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(ext);
+    path.with_file_name(name)
+}
+
+fn is_sidecar(path: &Path) -> bool {
+    let ext = path.extension().and_then(|ext| ext.to_str());
+    SIDECARS.iter().any(|(_, e)| Some(*e) == ext)
+}
+
+/// Writes the sidecar of `path`, unless one exists that is newer than the
+/// file itself.
+fn write_sidecar(path: &Path, body: &[u8], encoding: Encoding, ext: &str) -> io::Result<()> {
+    let sidecar = sidecar_path(path, ext);
+
+    let fresh = match (fs::metadata(path), fs::metadata(&sidecar)) {
+        (Ok(orig), Ok(side)) => side.modified()? >= orig.modified()?,
+        _ => false,
+    };
+
+    if !fresh {
+        fs::write(&sidecar, encoding.encode_best(body)?)?;
+    }
+
+    Ok(())
+}
+
+/// All files of the asset directory, keyed by their path relative to it.
+#[derive(Debug, Default)]
+pub struct StaticAssets {
+    assets: HashMap<PathBuf, Asset>,
+}
+
+impl StaticAssets {
+    /// Indexes the files below `root`, writing missing or stale sidecars.
+    pub fn load<P: AsRef<Path>>(root: P) -> io::Result<StaticAssets> {
+        let root = root.as_ref();
+        let mut assets = HashMap::new();
+
+        let mut dirs = vec![root.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else if !is_sidecar(&path) {
+                    let rel = path.strip_prefix(root).expect("below root").to_path_buf();
+                    assets.insert(rel, Asset::load(path)?);
+                }
+            }
+        }
+
+        Ok(StaticAssets { assets })
+    }
+
+    pub fn len(&self) -> usize {
+        self.assets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.assets.is_empty()
+    }
+
+    /// Looks up an asset by its request path. Directories resolve to their
+    /// `index.html`.
+    pub fn get(&self, path: &Path) -> Option<&Asset> {
+        let normal = path.components().all(|c| match c {
+            Component::Normal(_) => true,
+            _ => false,
+        });
+        if !normal {
+            return None;
+        }
+
+        self.assets
+            .get(path)
+            .or_else(|| self.assets.get(&path.join("index.html")))
+    }
+
+    /// Picks the representation of the asset at `path` to send in response
+    /// to `req`.
+    pub fn respond(&self, path: &Path, req: &AssetRequest) -> Option<AssetResponse> {
+        let asset = self.get(path)?;
+
+        let encoding = Encoding::negotiate_among(&req.accept_encoding, &asset.sidecars);
+        let etag = asset.etag(encoding);
+
+        if req.matches(&etag) {
+            return Some(AssetResponse::NotModified(etag));
+        }
+
+        match File::open(asset.file(encoding)) {
+            Ok(file) => Some(AssetResponse::File {
+                file,
+                content_type: asset.content_type.clone(),
+                encoding,
+                etag,
+                vary: !asset.sidecars.is_empty(),
+            }),
+            Err(err) => {
+                println!("Error: could not open {}: {}", asset.path.display(), err);
+                None
+            }
+        }
+    }
+}
+
+/// The request headers that select an asset representation.
+#[derive(Debug)]
+pub struct AssetRequest {
+    accept_encoding: String,
+    if_none_match: Vec<String>,
+}
+
+impl AssetRequest {
+    fn matches(&self, etag: &str) -> bool {
+        self.if_none_match
+            .iter()
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for AssetRequest {
+    type Error = ();
+
+    fn from_request(r: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        let accept_encoding = r
+            .headers()
+            .get("Accept-Encoding")
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let if_none_match = r
+            .headers()
+            .get("If-None-Match")
+            .flat_map(|value| value.split(','))
+            .map(|tag| tag.trim().to_string())
+            .filter(|tag| !tag.is_empty())
+            .collect();
+
+        Outcome::Success(AssetRequest {
+            accept_encoding,
+            if_none_match,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum AssetResponse {
+    NotModified(String),
+    File {
+        file: File,
+        content_type: Option<ContentType>,
+        encoding: Option<Encoding>,
+        etag: String,
+        vary: bool,
+    },
+}
+
+impl<'r> Responder<'r> for AssetResponse {
+    fn respond_to(self, _: &Request) -> Result<Response<'r>, Status> {
+        match self {
+            AssetResponse::NotModified(etag) => Response::build()
+                .status(Status::NotModified)
+                .raw_header("ETag", etag)
+                .ok(),
+            AssetResponse::File {
+                file,
+                content_type,
+                encoding,
+                etag,
+                vary,
+            } => {
+                let mut response = Response::build();
+                response.raw_header("ETag", etag).sized_body(file);
+
+                if let Some(content_type) = content_type {
+                    response.header(content_type);
+                }
+                if let Some(encoding) = encoding {
+                    response.raw_header("Content-Encoding", encoding.as_str());
+                }
+                if vary {
+                    response.raw_header("Vary", "Accept-Encoding");
+                }
+
+                response.ok()
+            }
+        }
+    }
+}
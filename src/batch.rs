@@ -0,0 +1,229 @@
+//! Batches of proof responses, submitted in a single request.
+//!
+//! A batch is either newline delimited JSON, one response per line, or a tar
+//! archive with one response per file. Files ending in `.cbor` use the compact
+//! encoding, anything else is JSON. Both can be sent with a
+//! `Content-Encoding`. Items are decoded independently, so one broken item
+//! does not fail the others, and one at a time while the batch is read, so a
+//! batch is never held in memory as a whole.
+
+use std::io::{BufRead, BufReader, Read};
+
+use failure::format_err;
+use rocket::data::{FromDataSimple, Outcome};
+use rocket::http::ContentType;
+use rocket::{Data, Outcome::*, Request, State};
+use serde::{Deserialize, Serialize};
+
+use crate::compact;
+use crate::config::GameConfig;
+use crate::encoding::{Body, Encoding};
+use crate::error::{guard_failure, ApiError, ApiResult, ErrorCode, ResultExt};
+use crate::models::proof;
+
+pub fn ndjson_type() -> ContentType {
+    ContentType::new("application", "x-ndjson")
+}
+
+pub fn tar_type() -> ContentType {
+    ContentType::new("application", "x-tar")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Ndjson,
+    Tar,
+}
+
+/// A batch, decoded one item at a time while it is read.
+pub struct Batch {
+    format: Format,
+    body: Body<'static>,
+    size_limit: u64,
+    max_items: usize,
+}
+
+impl Batch {
+    /// Hands every item of the batch to `f` as soon as it is decoded, so only
+    /// one item is held in memory at a time. Reading stops at the first error
+    /// of the batch as a whole, which is handed to `f` in place of the next
+    /// item.
+    pub fn each<F: FnMut(ApiResult<proof::Response>)>(mut self, mut f: F) {
+        let read = match self.format {
+            Format::Ndjson => read_ndjson(&mut self.body, self.max_items, &mut f),
+            Format::Tar => read_tar(&mut self.body, self.max_items, &mut f),
+        };
+
+        if let Err(err) = read {
+            // Reading stops early when a limit is hit, which surfaces as a
+            // broken stream.
+            f(Err(if self.body.exceeded() {
+                ApiError::new(
+                    ErrorCode::PayloadTooLarge,
+                    format_err!(
+                        "The batch exceeds the size limit of {} bytes",
+                        self.size_limit
+                    ),
+                )
+            } else {
+                err
+            }));
+        }
+    }
+}
+
+/// The outcome of a single item of a batch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ItemResult {
+    pub index: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prover: Option<String>,
+    #[serde(flatten)]
+    pub result: serde_json::Value,
+}
+
+impl ItemResult {
+    pub fn new(index: usize, prover: Option<String>, result: ApiResult<()>) -> ItemResult {
+        let result = match result {
+            Ok(()) => serde_json::json!({ "status": "success" }),
+            Err(err) => {
+                println!("Error [{}] batch item {}: {}", err.code(), index, err);
+                err.to_json().0
+            }
+        };
+
+        ItemResult {
+            index,
+            prover,
+            result,
+        }
+    }
+}
+
+/// Counts an item, failing once there are more than `max_items`.
+fn count(items: &mut usize, max_items: usize) -> ApiResult<()> {
+    if *items == max_items {
+        return Err(ApiError::new(
+            ErrorCode::PayloadTooLarge,
+            format_err!("Batches are limited to {} items", max_items),
+        ));
+    }
+
+    *items += 1;
+    Ok(())
+}
+
+fn json_error(err: serde_json::Error) -> ApiError {
+    let code = if err.is_data() {
+        ErrorCode::InvalidProof
+    } else {
+        ErrorCode::MalformedRequest
+    };
+
+    ApiError::new(code, err)
+}
+
+fn read_ndjson<R, F>(input: R, max_items: usize, f: &mut F) -> ApiResult<()>
+where
+    R: Read,
+    F: FnMut(ApiResult<proof::Response>),
+{
+    let mut input = BufReader::new(input);
+    let mut items = 0;
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        let n = input
+            .read_until(b'\n', &mut line)
+            .with_code(ErrorCode::MalformedRequest)?;
+        if n == 0 {
+            return Ok(());
+        }
+
+        if line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+
+        count(&mut items, max_items)?;
+        f(serde_json::from_slice(&line)
+            .map_err(json_error)
+            .and_then(proof::Envelope::open));
+    }
+}
+
+fn read_tar<R, F>(input: R, max_items: usize, f: &mut F) -> ApiResult<()>
+where
+    R: Read,
+    F: FnMut(ApiResult<proof::Response>),
+{
+    let mut archive = tar::Archive::new(input);
+    let mut items = 0;
+
+    let entries = archive.entries().with_code(ErrorCode::MalformedRequest)?;
+    for entry in entries {
+        let entry = entry.with_code(ErrorCode::MalformedRequest)?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        count(&mut items, max_items)?;
+
+        let cbor = entry
+            .path()
+            .map(|p| p.extension().map(|e| e == "cbor").unwrap_or(false))
+            .unwrap_or(false);
+
+        let item = if cbor {
+            compact::decode(entry).map_err(|e| ApiError::new(e.code(), format_err!("{}", e)))
+        } else {
+            serde_json::from_reader(entry).map_err(json_error)
+        };
+        f(item.and_then(proof::Envelope::open));
+    }
+
+    Ok(())
+}
+
+fn read_batch(r: &Request, data: Data) -> ApiResult<Batch> {
+    let encoding =
+        Encoding::of_request(r).map_err(|e| ApiError::new(e.code(), format_err!("{}", e)))?;
+
+    let content_type = r.content_type().cloned().unwrap_or(ContentType::Any);
+    let format = if content_type == ndjson_type() {
+        Format::Ndjson
+    } else if content_type == tar_type() {
+        Format::Tar
+    } else {
+        return Err(ApiError::new(
+            ErrorCode::UnsupportedMediaType,
+            format_err!("Batches are sent as application/x-ndjson or application/x-tar"),
+        ));
+    };
+
+    let config = r
+        .guard::<State<GameConfig>>()
+        .expect("game config is managed");
+
+    let size_limit = config.limits.batch;
+    let body = Body::new(encoding, data.open(), size_limit, config.max_ratio)
+        .with_code(ErrorCode::MalformedRequest)?;
+
+    Ok(Batch {
+        format,
+        body,
+        size_limit,
+        max_items: config.batch_max_items,
+    })
+}
+
+impl FromDataSimple for Batch {
+    type Error = ApiError;
+
+    fn from_data(r: &Request, data: Data) -> Outcome<Self, Self::Error> {
+        match read_batch(r, data) {
+            Ok(batch) => Success(batch),
+            Err(err) => Failure((guard_failure(r, err.code(), &err), err)),
+        }
+    }
+}
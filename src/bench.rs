@@ -0,0 +1,196 @@
+//! Measurements of the stages of sealing, for `replication-game bench`.
+//!
+//! The schemes run their stages through `stage`, which costs nothing unless
+//! a benchmark is `record`ing on the same thread.
+
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::fs;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::proof;
+use crate::proofs::millis;
+
+/// The measurements of one stage.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Stage {
+    pub name: String,
+    pub wall_ms: u64,
+    /// User and system time of all threads of the process.
+    pub cpu_ms: u64,
+    /// The peak resident memory of the process during the stage, in bytes,
+    /// where the platform reports it.
+    pub peak_memory: Option<u64>,
+    /// Whether the stage ran in a pass of its own, outside of sealing, which
+    /// the total leaves out.
+    #[serde(default)]
+    pub separate: bool,
+}
+
+/// The stages of sealing with the given params, as `replication-game bench`
+/// reports them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Report {
+    pub params: proof::Params,
+    pub stages: Vec<Stage>,
+}
+
+thread_local! {
+    static RECORDED: RefCell<Option<Vec<Stage>>> = RefCell::new(None);
+}
+
+/// Whether a benchmark is recording stages on this thread.
+pub fn recording() -> bool {
+    RECORDED.with(|recorded| recorded.borrow().is_some())
+}
+
+/// Runs `f` and returns the stages it went through, followed by a `total`
+/// over all of them except the separate ones.
+pub fn record<T>(f: impl FnOnce() -> T) -> (T, Vec<Stage>) {
+    RECORDED.with(|recorded| *recorded.borrow_mut() = Some(Vec::new()));
+
+    let (out, mut total) = measure("total", f);
+
+    let mut stages = RECORDED
+        .with(|recorded| recorded.borrow_mut().take())
+        .unwrap_or_default();
+    for stage in stages.iter().filter(|stage| stage.separate) {
+        total.wall_ms = total.wall_ms.saturating_sub(stage.wall_ms);
+        total.cpu_ms = total.cpu_ms.saturating_sub(stage.cpu_ms);
+    }
+    // The stages reset the peak, the total is the highest of theirs.
+    total.peak_memory = stages
+        .iter()
+        .filter(|stage| !stage.separate)
+        .filter_map(|stage| stage.peak_memory)
+        .chain(total.peak_memory)
+        .max();
+    stages.push(total);
+
+    (out, stages)
+}
+
+/// Runs `f` as the stage `name`, measuring it while recording.
+pub fn stage<T>(name: &str, f: impl FnOnce() -> T) -> T {
+    push(name, false, f)
+}
+
+/// Runs `f` as the stage `name` of a pass outside of sealing, which only
+/// runs to measure it, while recording.
+pub fn separate_stage<T>(name: &str, f: impl FnOnce() -> T) -> T {
+    push(name, true, f)
+}
+
+fn push<T>(name: &str, separate: bool, f: impl FnOnce() -> T) -> T {
+    if !recording() {
+        return f();
+    }
+
+    let (out, mut stage) = measure(name, f);
+    stage.separate = separate;
+    RECORDED.with(|recorded| {
+        if let Some(ref mut stages) = *recorded.borrow_mut() {
+            stages.push(stage);
+        }
+    });
+
+    out
+}
+
+fn measure<T>(name: &str, f: impl FnOnce() -> T) -> (T, Stage) {
+    reset_peak_memory();
+    let start = Instant::now();
+    let cpu_start = cpu_time();
+
+    let out = f();
+
+    let stage = Stage {
+        name: name.to_string(),
+        wall_ms: millis(start.elapsed()),
+        cpu_ms: millis(cpu_time().checked_sub(cpu_start).unwrap_or_default()),
+        peak_memory: peak_memory(),
+        separate: false,
+    };
+
+    (out, stage)
+}
+
+fn cpu_time() -> Duration {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return Duration::default();
+    }
+
+    let time = |tv: libc::timeval| {
+        Duration::from_secs(tv.tv_sec as u64) + Duration::from_micros(tv.tv_usec as u64)
+    };
+    time(usage.ru_utime) + time(usage.ru_stime)
+}
+
+/// Resets the peak to the current resident memory. Without it, as on
+/// kernels before 4.0, peaks are those of the process so far.
+fn reset_peak_memory() {
+    let _ = fs::write("/proc/self/clear_refs", "5");
+}
+
+fn peak_memory() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let kb: u64 = status
+        .lines()
+        .find(|line| line.starts_with("VmHWM:"))?
+        .split_whitespace()
+        .nth(1)?
+        .parse()
+        .ok()?;
+
+    Some(kb * 1024)
+}
+
+/// The stages as a table, one row per stage.
+pub fn table(stages: &[Stage]) -> String {
+    let width = stages
+        .iter()
+        .map(|stage| stage.name.len() + if stage.separate { 2 } else { 0 })
+        .max()
+        .unwrap_or(0)
+        .max("stage".len());
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{:<width$}  {:>10}  {:>10}  {:>12}",
+        "stage",
+        "wall ms",
+        "cpu ms",
+        "peak memory",
+        width = width
+    );
+    for stage in stages {
+        let name = if stage.separate {
+            format!("{} *", stage.name)
+        } else {
+            stage.name.clone()
+        };
+        let peak = stage
+            .peak_memory
+            .map(|bytes| format!("{:.1} MiB", bytes as f64 / f64::from(1 << 20)))
+            .unwrap_or_else(|| "-".to_string());
+        let _ = writeln!(
+            out,
+            "{:<width$}  {:>10}  {:>10}  {:>12}",
+            name,
+            stage.wall_ms,
+            stage.cpu_ms,
+            peak,
+            width = width
+        );
+    }
+
+    if stages.iter().any(|stage| stage.separate) {
+        let _ = writeln!(out, "* timed in a separate pass, not part of the total");
+    }
+
+    out
+}
@@ -0,0 +1,32 @@
+use std::io;
+use std::path::Path;
+
+use clap::{App, Arg};
+
+use replication_game::models::audit::AuditChallenge;
+use replication_game::proofs::audit_work;
+
+fn main() {
+    let matches = App::new("Replication Game Audit CLI")
+        .version("1.0")
+        .about("Answers an audit read from stdin, as returned by /api/audits")
+        .arg(
+            Arg::with_name("replica")
+                .long("replica")
+                .help("The replica kept with --keep-replica")
+                .required(true)
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let challenge: AuditChallenge =
+        serde_json::from_reader(io::stdin()).expect("failed to read the audit");
+    let replica = Path::new(matches.value_of("replica").unwrap());
+
+    let proofs = audit_work(&challenge, replica);
+
+    println!(
+        "{}",
+        serde_json::to_string(&proofs).expect("failed to serialize")
+    );
+}
@@ -1,47 +1,488 @@
-use clap::{value_t, App, AppSettings, Arg, SubCommand};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
 
+use clap::{value_t, App, AppSettings, Arg, ArgMatches, Error, ErrorKind, SubCommand};
+
+use replication_game::bench;
+use replication_game::client::{ClientConfig, Game, Http, Rejection, DEFAULT_SERVER};
+use replication_game::compact;
+use replication_game::config::{GameConfig, DEFAULT_GRAPH_SEED};
 use replication_game::models::proof;
 use replication_game::models::seed::Seed;
+use replication_game::presets;
 use replication_game::proofs::*;
+use replication_game::schemes::{self, GameScheme};
+use replication_game::units::parse_cli_size;
 
-fn main() {
-    let matches = App::new(stringify!("Replication Game CLI"))
-        .version("1.0")
-        .arg(
-            Arg::with_name("size")
-                .required(true)
-                .long("size")
-                .help("The data size in KB")
-                .takes_value(true),
+/// The arguments that make up the params of a proof.
+fn params_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("preset")
+            .help("Use the params of an official track, like zigzag-1gib")
+            .long("preset")
+            .takes_value(true),
+        Arg::with_name("params")
+            .help("Read the params from a TOML or JSON file")
+            .long("params")
+            .conflicts_with("preset")
+            .takes_value(true),
+        Arg::with_name("size")
+            .required_unless_one(&["preset", "params"])
+            .long("size")
+            .help("The data size, with a binary unit like 16GiB, or in KiB")
+            .takes_value(true),
+        Arg::with_name("degree")
+            .help("The degree")
+            .long("degree")
+            .default_value("6")
+            .takes_value(true),
+        Arg::with_name("vde")
+            .help("The VDE difficulty")
+            .long("vde")
+            .default_value("0")
+            .takes_value(true),
+        Arg::with_name("expansion-degree")
+            .help("The expansion degree for Zigzag")
+            .long("expansion-degree")
+            .default_value("6")
+            .takes_value(true),
+        Arg::with_name("layers")
+            .help("The layers for Zigzag")
+            .long("layers")
+            .default_value("10")
+            .takes_value(true),
+        Arg::with_name("challenges")
+            .help("The number of challenges")
+            .long("challenges")
+            .default_value("200")
+            .takes_value(true),
+        Arg::with_name("no-taper")
+            .help("Challenge every Zigzag layer equally, instead of tapering them")
+            .long("no-taper"),
+        Arg::with_name("taper-layers")
+            .help("The number of tapered Zigzag layers")
+            .long("taper-layers")
+            .default_value("7")
+            .takes_value(true),
+        Arg::with_name("taper")
+            .help("The ratio challenges are tapered by, as a number or a fraction like 1/3")
+            .long("taper")
+            .default_value("1/3")
+            .takes_value(true),
+        Arg::with_name("partitions")
+            .help("The number of partition proofs for Zigzag")
+            .long("partitions")
+            .default_value("1")
+            .takes_value(true),
+        Arg::with_name("replicas")
+            .help("The number of replicas to seal from the seed")
+            .long("replicas")
+            .default_value("1")
+            .takes_value(true),
+        Arg::with_name("hasher")
+            .help("The hasher replicas are committed to with")
+            .long("hasher")
+            .possible_values(&["pedersen", "blake2s", "sha256"])
+            .default_value("pedersen")
+            .takes_value(true),
+        Arg::with_name("snark-params")
+            .help("The directory with the circuit parameters of the zigzag-snark tracks")
+            .long("snark-params")
+            .default_value("./snark-params")
+            .takes_value(true),
+    ]
+}
+
+/// The arguments of subcommands that talk to the server.
+fn server_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("server")
+            .long("server")
+            .help("The URL of the game server")
+            .takes_value(true),
+        Arg::with_name("config")
+            .long("config")
+            .help("A TOML file with the `prover` name, and optionally the `server`")
+            .takes_value(true),
+        Arg::with_name("prover")
+            .long("prover")
+            .help("The prover name to submit the proof under")
+            .takes_value(true),
+    ]
+}
+
+/// The argument with the graph seed of the server, for commands that don't
+/// ask the server for it.
+fn graph_seed_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("graph-seed")
+        .long("graph-seed")
+        .help("The graph seed of the server, as returned by /api/graph-seed, like [0,0,0,0,0,0,0]")
+        .takes_value(true)
+}
+
+/// One subcommand per scheme.
+fn scheme_subcommands<'a, 'b>() -> Vec<App<'a, 'b>> {
+    schemes::all()
+        .iter()
+        .map(|scheme| SubCommand::with_name(scheme.name()))
+        .collect()
+}
+
+/// The arguments `--preset` and `--params` replace.
+const PARAMS_FLAGS: [&str; 12] = [
+    "size",
+    "degree",
+    "vde",
+    "expansion-degree",
+    "layers",
+    "challenges",
+    "no-taper",
+    "taper-layers",
+    "taper",
+    "partitions",
+    "replicas",
+    "hasher",
+];
+
+/// The scheme of the subcommand of `matches`, if there is one.
+fn scheme(matches: &ArgMatches) -> Option<&'static dyn GameScheme> {
+    schemes::by_name(matches.subcommand().0)
+}
+
+fn invalid(message: &str) -> ! {
+    Error::with_description(message, ErrorKind::InvalidValue).exit()
+}
+
+/// The params given by `--preset`, `--params` or the params arguments.
+fn params(matches: &ArgMatches) -> proof::Params {
+    let base = match (matches.value_of("preset"), matches.value_of("params")) {
+        (Some(name), _) => Some(presets::get(name).unwrap_or_else(|| {
+            invalid(&format!(
+                "unknown preset {}, expected one of {}",
+                name,
+                presets::names().join(", ")
+            ))
+        })),
+        (None, Some(path)) => {
+            Some(presets::load(Path::new(path)).unwrap_or_else(|err| invalid(&err.to_string())))
+        }
+        (None, None) => None,
+    };
+
+    let params = match base {
+        Some(params) => {
+            if let Some(flag) = PARAMS_FLAGS
+                .iter()
+                .find(|flag| matches.occurrences_of(flag) > 0)
+            {
+                invalid(&format!(
+                    "--{} can't be combined with --preset or --params",
+                    flag
+                ));
+            }
+            if let Some(scheme) = scheme(matches) {
+                if scheme.typ() != params.typ {
+                    invalid(&format!("the params are not for {}", scheme.name()));
+                }
+            }
+            params
+        }
+        None => {
+            let scheme = scheme(matches).unwrap_or_else(|| {
+                Error::with_description(
+                    "a scheme subcommand, --preset or --params is required",
+                    ErrorKind::MissingSubcommand,
+                )
+                .exit()
+            });
+            flag_params(matches, scheme)
+        }
+    };
+
+    // The same rules the server checks submissions against, so a proof
+    // isn't computed just to be rejected. Servers can be configured with
+    // other limits, the defaults are those of the public game.
+    if let Err(err) = params.check() {
+        invalid(&format!("invalid params: {}", err));
+    }
+    if let Err(err) = GameConfig::default().check_params(&params) {
+        invalid(&format!("params not allowed: {}", err));
+    }
+
+    schemes::use_snark_parameters(Path::new(matches.value_of("snark-params").unwrap()));
+
+    params
+}
+
+fn flag_params(matches: &ArgMatches, scheme: &dyn GameScheme) -> proof::Params {
+    let is_tapered = !matches.is_present("no-taper");
+    let zigzag = proof::ZigZagParams {
+        expansion_degree: value_t!(matches, "expansion-degree", usize).unwrap_or_else(|e| e.exit()),
+        layers: value_t!(matches, "layers", usize).unwrap_or_else(|e| e.exit()),
+        is_tapered,
+        // Left at zero without tapering, so the params don't differ in what
+        // plays no part.
+        taper_layers: if is_tapered {
+            value_t!(matches, "taper-layers", usize).unwrap_or_else(|e| e.exit())
+        } else {
+            0
+        },
+        taper: if is_tapered {
+            ratio(matches.value_of("taper").unwrap())
+        } else {
+            0.
+        },
+        partitions: value_t!(matches, "partitions", usize).unwrap_or_else(|e| e.exit()),
+    };
+
+    scheme.params(
+        proof::Params {
+            typ: scheme.typ(),
+            hasher: matches
+                .value_of("hasher")
+                .and_then(proof::HasherType::from_name)
+                .unwrap(),
+            size: parse_cli_size(required(matches, "size"))
+                .unwrap_or_else(|err| invalid(&err.to_string())) as usize,
+            degree: value_t!(matches, "degree", usize).unwrap_or_else(|e| e.exit()),
+            vde: value_t!(matches, "vde", usize).unwrap_or_else(|e| e.exit()),
+            challenge_count: value_t!(matches, "challenges", usize).unwrap_or_else(|e| e.exit()),
+            replicas: value_t!(matches, "replicas", usize).unwrap_or_else(|e| e.exit()),
+            zigzag: None,
+        },
+        zigzag,
+    )
+}
+
+/// Parses a ratio given as a number, or as a fraction like `1/3`.
+fn ratio(raw: &str) -> f64 {
+    let parsed = match raw.find('/') {
+        Some(i) => match (
+            raw[..i].trim().parse::<f64>(),
+            raw[i + 1..].trim().parse::<f64>(),
+        ) {
+            (Ok(n), Ok(d)) => Ok(n / d),
+            _ => Err(()),
+        },
+        None => raw.trim().parse().map_err(|_| ()),
+    };
+
+    parsed.unwrap_or_else(|()| invalid(&format!("invalid ratio: {}", raw)))
+}
+
+/// The value of an argument clap can't require by itself, as it is only
+/// required for some subcommands.
+fn required<'a>(matches: &'a ArgMatches, name: &str) -> &'a str {
+    matches.value_of(name).unwrap_or_else(|| {
+        Error::with_description(
+            &format!(
+                "The following required argument was not provided: --{}",
+                name
+            ),
+            ErrorKind::MissingRequiredArgument,
         )
-        .arg(
-            Arg::with_name("degree")
-                .help("The degree")
-                .long("degree")
-                .default_value("6")
-                .takes_value(true),
+        .exit()
+    })
+}
+
+/// The seed given by `--graph-seed`, seven numbers separated by commas,
+/// optionally in brackets.
+fn graph_seed(matches: &ArgMatches) -> [u32; 7] {
+    let raw = match matches.value_of("graph-seed") {
+        Some(raw) => raw,
+        None => return DEFAULT_GRAPH_SEED,
+    };
+
+    let numbers = raw
+        .trim()
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .split(',')
+        .map(|n| n.trim().parse())
+        .collect::<Result<Vec<u32>, _>>();
+    match numbers {
+        Ok(ref numbers) if numbers.len() == 7 => {
+            let mut seed = [0; 7];
+            seed.copy_from_slice(numbers);
+            seed
+        }
+        _ => invalid(&format!("invalid graph seed: {}", raw)),
+    }
+}
+
+/// The prover name and a client of the server, from the arguments and the
+/// `--config` file.
+fn connect(matches: &ArgMatches) -> (String, Game<Http>) {
+    let config = match matches.value_of("config") {
+        Some(path) => ClientConfig::load(Path::new(path)).unwrap_or_else(|err| {
+            eprintln!("error: {}", err);
+            process::exit(1)
+        }),
+        None => ClientConfig::default(),
+    };
+
+    let prover = matches
+        .value_of("prover")
+        .map(str::to_string)
+        .or(config.prover)
+        .unwrap_or_else(|| {
+            eprintln!("error: no prover name, pass --prover or set it in the --config file");
+            process::exit(1)
+        });
+    let server = matches
+        .value_of("server")
+        .map(str::to_string)
+        .or(config.server)
+        .unwrap_or_else(|| DEFAULT_SERVER.to_string());
+
+    let transport = Http::new(&server).unwrap_or_else(|err| {
+        eprintln!("error: {}", err);
+        process::exit(1)
+    });
+
+    (prover, Game::new(transport))
+}
+
+fn fail(err: failure::Error) -> ! {
+    match err.downcast_ref::<Rejection>() {
+        Some(rejection) => eprintln!("error: {}: {}", rejection.hint(), rejection),
+        None => eprintln!("error: {}", err),
+    }
+    process::exit(1)
+}
+
+/// Plays a round against the server: fetches a seed, proves, submits and
+/// prints the verdict.
+fn play(matches: &ArgMatches) {
+    let params = params(matches);
+    let (prover, game) = connect(matches);
+
+    match game.play(&prover, params) {
+        Ok(outcome) => {
+            println!("proof accepted");
+            println!(
+                "{} is ranked {} of {} with a replication time of {}s",
+                prover, outcome.rank, outcome.of, outcome.entry.repl_time
+            );
+        }
+        Err(err) => fail(err),
+    }
+}
+
+/// Plays the unsealing race against the server, and prints the extraction
+/// time it measured.
+fn unseal(matches: &ArgMatches) {
+    let params = params(matches);
+    let (prover, game) = connect(matches);
+    let replica = Path::new(matches.value_of("replica").unwrap());
+
+    match game.unseal(&prover, params, replica) {
+        Ok(entry) => println!(
+            "extraction accepted, the best of {} took {}ms",
+            prover, entry.extraction_time_ms
+        ),
+        Err(err) => fail(err),
+    }
+}
+
+/// Seals and proves a replica without a server, and reports where the time
+/// and memory went.
+fn bench(matches: &ArgMatches) {
+    let params = params(matches);
+    // Any replica id works, the proof is thrown away.
+    let seed = Seed {
+        timestamp: 0,
+        seed: "00".repeat(32),
+    };
+
+    let (_, stages) = bench::record(|| {
+        work(
+            "bench".to_string(),
+            params.clone(),
+            seed,
+            DEFAULT_GRAPH_SEED,
         )
+    });
+
+    eprint!("{}", bench::table(&stages));
+    println!(
+        "{}",
+        serde_json::to_string(&bench::Report { params, stages }).expect("failed to serialize")
+    );
+}
+
+/// Generates the circuit parameters of the zigzag-snark tracks.
+fn snark_setup(matches: &ArgMatches) {
+    let dir = Path::new(matches.value_of("out").unwrap());
+    let tracks: Vec<&str> = match matches.values_of("track") {
+        Some(tracks) => tracks.collect(),
+        None => schemes::snark_tracks()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect(),
+    };
+
+    for track in tracks {
+        eprintln!("generating the circuit parameters of {}", track);
+        if let Err(err) = schemes::setup_snark_parameters(dir, track, graph_seed(matches)) {
+            eprintln!("error: {}", err);
+            process::exit(1)
+        }
+    }
+}
+
+fn main() {
+    let bench_command = SubCommand::with_name("bench")
+        .about("Measures the stages of sealing, printing a table to stderr and JSON to stdout")
+        .args(&params_args())
+        .subcommands(scheme_subcommands());
+
+    let play_command = SubCommand::with_name("play")
+        .about("Plays a round against the game server, and prints the verdict")
+        .args(&params_args())
+        .args(&server_args())
+        .subcommands(scheme_subcommands());
+
+    let unseal_command = SubCommand::with_name("unseal")
+        .about("Plays the unsealing race against the game server (Zigzag only)")
+        .args(&params_args())
+        .args(&server_args())
         .arg(
-            Arg::with_name("vde")
-                .help("The VDE difficulty")
-                .long("vde")
-                .default_value("0")
+            Arg::with_name("replica")
+                .long("replica")
+                .help("The file to keep the replica in until the ranges are issued")
+                .required(true)
                 .takes_value(true),
         )
+        .subcommands(scheme_subcommands());
+
+    let tracks: Vec<&str> = schemes::snark_tracks()
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    let snark_setup_command = SubCommand::with_name("snark-setup")
+        .about("Generates the circuit parameters of the zigzag-snark tracks, for a server and its provers")
         .arg(
-            Arg::with_name("expansion-degree")
-                .help("The expansion degree for Zigzag")
-                .long("expansion-degree")
-                .default_value("6")
+            Arg::with_name("out")
+                .long("out")
+                .help("The directory to write the parameters to")
+                .default_value("./snark-params")
                 .takes_value(true),
         )
         .arg(
-            Arg::with_name("layers")
-                .help("The layers for Zigzag")
-                .long("layers")
-                .default_value("10")
+            Arg::with_name("track")
+                .long("track")
+                .help("Only generate the parameters of these tracks")
+                .possible_values(&tracks)
+                .multiple(true)
                 .takes_value(true),
         )
+        .arg(graph_seed_arg());
+
+    let matches = App::new(stringify!("Replication Game CLI"))
+        .version("1.0")
+        .args(&params_args())
         .arg(
             Arg::with_name("seed")
                 .long("seed")
@@ -63,46 +504,69 @@ fn main() {
                 .required(true)
                 .takes_value(true),
         )
-        .setting(AppSettings::SubcommandRequired)
-        .subcommand(SubCommand::with_name("drgporep"))
-        .subcommand(SubCommand::with_name("zigzag"))
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .help("The encoding of the proof written to stdout")
+                .possible_values(&["json", "cbor"])
+                .default_value("json")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("keep-replica")
+                .long("keep-replica")
+                .help(
+                    "Keep the replica in this file, to answer audits with, \
+                     and replica k of multi-replica params in FILE.k",
+                )
+                .takes_value(true),
+        )
+        .arg(graph_seed_arg())
+        // `play`, `unseal`, `bench` and `snark-setup` need no seed, the required arguments are
+        // checked below for the scheme subcommands.
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(play_command)
+        .subcommand(unseal_command)
+        .subcommand(bench_command)
+        .subcommand(snark_setup_command)
+        .subcommands(scheme_subcommands())
         .get_matches();
 
-    let seed = Seed {
-        timestamp: value_t!(matches, "timestamp", i32).unwrap(),
-        seed: value_t!(matches, "seed", String).unwrap(),
-    };
+    match matches.subcommand() {
+        ("play", Some(play_matches)) => return play(play_matches),
+        ("unseal", Some(unseal_matches)) => return unseal(unseal_matches),
+        ("bench", Some(bench_matches)) => return bench(bench_matches),
+        ("snark-setup", Some(setup_matches)) => return snark_setup(setup_matches),
+        _ => {}
+    }
 
-    let (typ, zigzag) = match matches.subcommand().0 {
-        "drgporep" => (proof::ProofType::DrgPoRep, None),
-        "zigzag" => (
-            proof::ProofType::Zigzag,
-            Some(proof::ZigZagParams {
-                expansion_degree: value_t!(matches, "expansion-degree", usize).unwrap(),
-                layers: value_t!(matches, "layers", usize).unwrap(),
-                is_tapered: true,
-                taper_layers: 7,
-                taper: 1.0 / 3.0,
-            }),
-        ),
-        _ => panic!("invalid subcommand: {}", matches.subcommand().0),
+    let seed = Seed {
+        timestamp: required(&matches, "timestamp")
+            .parse()
+            .unwrap_or_else(|_| panic!("invalid timestamp")),
+        seed: required(&matches, "seed").to_string(),
     };
 
-    let params = proof::Params {
-        typ: typ.clone(),
-        size: value_t!(matches, "size", usize).unwrap() * 1024,
-        degree: value_t!(matches, "degree", usize).unwrap(),
-        vde: value_t!(matches, "vde", usize).unwrap(),
-        challenge_count: 200,
-        zigzag,
-    };
+    let params = params(&matches);
 
-    let prover = value_t!(matches, "prover", String).unwrap();
+    let prover = required(&matches, "prover").to_string();
+    let graph_seed = graph_seed(&matches);
 
-    let res = match typ {
-        proof::ProofType::DrgPoRep => porep_work(prover, params, seed),
-        proof::ProofType::Zigzag => zigzag_work(prover, params, seed),
+    let res = match matches.value_of("keep-replica") {
+        Some(path) => seal_work(prover, params, seed, graph_seed, Path::new(path)),
+        None => work(prover, params, seed, graph_seed),
     };
 
-    println!("{}", res);
+    match matches.value_of("format") {
+        Some("cbor") => {
+            let encoded = compact::encode(&res).expect("failed to encode");
+            io::stdout()
+                .write_all(&encoded)
+                .expect("failed to write proof");
+        }
+        _ => println!(
+            "{}",
+            serde_json::to_string(&res).expect("failed to serialize")
+        ),
+    }
 }
@@ -1,5 +1,30 @@
-use replication_game::rocket;
+use std::env;
+use std::process;
+
+use replication_game::assets::StaticAssets;
+use replication_game::{rocket, STATIC_DIR};
 
 fn main() {
-    rocket().0.launch();
+    match env::args().nth(1).as_ref().map(String::as_str) {
+        None => {
+            rocket().0.launch();
+        }
+        // Writes the `.gz` and `.br` sidecars of the static assets ahead of
+        // time, e.g. as part of building the frontend.
+        Some("compress-assets") => {
+            let dir = env::args().nth(2).unwrap_or_else(|| STATIC_DIR.into());
+            match StaticAssets::load(&dir) {
+                Ok(assets) => println!("Indexed {} assets in {}", assets.len(), dir),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        Some(other) => {
+            eprintln!("Unknown command: {}", other);
+            eprintln!("Usage: replication-game-server [compress-assets [DIR]]");
+            process::exit(1);
+        }
+    }
 }
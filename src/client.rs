@@ -0,0 +1,405 @@
+//! A client of the game server, to play a round from the command line.
+//!
+//! Requests go through a `Transport`, which is plain HTTP when playing, and
+//! an in-process server in the tests.
+
+use std::error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Seek, SeekFrom};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use failure::format_err;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Deserialize;
+
+use crate::extraction::Challenge;
+use crate::models::extraction::PrintableExtractionEntry;
+use crate::models::leaderboard::{Params, PrintableEntry};
+use crate::models::proof;
+use crate::models::seed::{GraphSeed, Seed};
+use crate::proofs;
+
+/// The server played against when none is configured.
+pub const DEFAULT_SERVER: &str = "https://replication-game.herokuapp.com";
+
+/// Settings of the client, read from a TOML file like
+///
+/// ```toml
+/// prover = "ReadyPlayerOne"
+/// server = "https://replication-game.herokuapp.com"
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ClientConfig {
+    /// The prover name proofs are submitted under.
+    pub prover: Option<String>,
+    /// The URL of the server, without the `/api` prefix.
+    pub server: Option<String>,
+}
+
+impl ClientConfig {
+    pub fn load(path: &Path) -> Result<ClientConfig, failure::Error> {
+        let raw = fs::read_to_string(path)
+            .map_err(|err| format_err!("failed to read {}: {}", path.display(), err))?;
+        toml::from_str(&raw).map_err(|err| format_err!("invalid {}: {}", path.display(), err))
+    }
+}
+
+/// The parts of an HTTP response the client looks at.
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub status: u16,
+    /// The `Retry-After` header, in seconds.
+    pub retry_after: Option<u64>,
+    pub body: String,
+}
+
+/// The headers of a JSON body.
+const JSON: &[(&str, &str)] = &[("Content-Type", "application/json")];
+
+/// The body of a POST request, sent again as is when it is retried.
+#[derive(Debug, Clone, Copy)]
+pub enum Body<'a> {
+    Bytes(&'a [u8]),
+    /// A body too large to hold in memory, read from the start of the file
+    /// on every attempt.
+    File(&'a File),
+}
+
+/// A handle of `file` reading it from the start, and the length of the file.
+pub fn rewind(file: &File) -> io::Result<(File, u64)> {
+    let mut file = file.try_clone()?;
+    let len = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok((file, len))
+}
+
+/// Sends requests to the server. Paths start with `/api`.
+pub trait Transport {
+    fn get(&self, path: &str) -> Result<Reply, failure::Error>;
+
+    fn post(
+        &self,
+        path: &str,
+        headers: &[(&'static str, &'static str)],
+        body: Body,
+    ) -> Result<Reply, failure::Error>;
+}
+
+/// Talks to a server over HTTP.
+pub struct Http {
+    client: reqwest::Client,
+    server: String,
+}
+
+impl Http {
+    pub fn new(server: &str) -> Result<Http, failure::Error> {
+        // Large proofs take a while to upload.
+        let client = reqwest::Client::builder().timeout(None).build()?;
+
+        Ok(Http {
+            client,
+            server: server.trim_end_matches('/').to_string(),
+        })
+    }
+
+    fn reply(mut res: reqwest::Response) -> Result<Reply, failure::Error> {
+        let retry_after = res
+            .headers()
+            .get("Retry-After")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok());
+
+        Ok(Reply {
+            status: res.status().as_u16(),
+            retry_after,
+            body: res.text()?,
+        })
+    }
+}
+
+impl Transport for Http {
+    fn get(&self, path: &str) -> Result<Reply, failure::Error> {
+        let res = self
+            .client
+            .get(&format!("{}{}", self.server, path))
+            .send()?;
+        Http::reply(res)
+    }
+
+    fn post(
+        &self,
+        path: &str,
+        headers: &[(&'static str, &'static str)],
+        body: Body,
+    ) -> Result<Reply, failure::Error> {
+        let body = match body {
+            Body::Bytes(bytes) => reqwest::Body::from(bytes.to_vec()),
+            // Streamed from the file, the body is never held in memory.
+            Body::File(file) => {
+                let (file, len) = rewind(file)?;
+                reqwest::Body::sized(file, len)
+            }
+        };
+
+        let mut req = self
+            .client
+            .post(&format!("{}{}", self.server, path))
+            .body(body);
+        for &(name, value) in headers {
+            req = req.header(name, value);
+        }
+
+        Http::reply(req.send()?)
+    }
+}
+
+/// An error response of the server.
+#[derive(Debug, Clone)]
+pub struct Rejection {
+    pub status: u16,
+    pub code: String,
+    pub message: String,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({}): {}", self.code, self.status, self.message)
+    }
+}
+
+impl error::Error for Rejection {}
+
+impl Rejection {
+    fn from_reply(reply: &Reply) -> Rejection {
+        #[derive(Deserialize)]
+        struct Body {
+            code: String,
+            message: String,
+        }
+
+        match serde_json::from_str::<Body>(&reply.body) {
+            Ok(body) => Rejection {
+                status: reply.status,
+                code: body.code,
+                message: body.message,
+            },
+            Err(_) => Rejection {
+                status: reply.status,
+                code: "unknown".to_string(),
+                message: reply.body.clone(),
+            },
+        }
+    }
+
+    /// What a player can do about it.
+    pub fn hint(&self) -> &'static str {
+        match self.code.as_str() {
+            "invalid_seed" => "the server did not accept the seed, play again with a new one",
+            "expired_seed" => "the seed expired before the proof was posted, play again",
+            "payload_too_large" => "the proof is too large for the server",
+            "invalid_proof" | "params_not_allowed" | "unsupported_format" => {
+                "the proof was rejected"
+            }
+            "rate_limited" => "too many submissions, try again later",
+            _ => "posting the proof failed",
+        }
+    }
+}
+
+/// Where an accepted proof ended up.
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    /// The entry of the prover with the params of the proof, which keeps
+    /// the best time of all their submissions.
+    pub entry: PrintableEntry,
+    /// The position of the entry among those with the same params, from 1.
+    pub rank: usize,
+    /// The number of entries with the same params.
+    pub of: usize,
+}
+
+/// Plays rounds of the game against a server.
+pub struct Game<T: Transport> {
+    transport: T,
+    retries: usize,
+    backoff: Duration,
+}
+
+impl<T: Transport> Game<T> {
+    pub fn new(transport: T) -> Game<T> {
+        Game {
+            transport,
+            retries: 5,
+            backoff: Duration::from_secs(1),
+        }
+    }
+
+    /// How often failed requests are retried, waiting `backoff` at first and
+    /// twice as long every time after, unless the server asks for longer.
+    pub fn with_retries(mut self, retries: usize, backoff: Duration) -> Game<T> {
+        self.retries = retries;
+        self.backoff = backoff;
+        self
+    }
+
+    /// Fetches a seed, replicates and proves random data for it on the graphs
+    /// of the server, submits the proof and looks up the rank it got.
+    pub fn play(&self, prover: &str, params: proof::Params) -> Result<Outcome, failure::Error> {
+        let graph_seed = self.graph_seed()?;
+        let seed = self.seed()?;
+        eprintln!("playing with seed {} of {}", seed.seed, seed.timestamp);
+
+        let res = proofs::work(prover.to_string(), params, seed, graph_seed);
+        self.submit(&res)?;
+
+        self.rank(&res.prover, &res.proof_params)
+    }
+
+    /// Plays the unsealing race: seals the data of a new seed into the file
+    /// at `replica`, submits the proof, and extracts the ranges the server
+    /// issues for it. Returns the entry of the prover on the extraction
+    /// leaderboard.
+    pub fn unseal(
+        &self,
+        prover: &str,
+        params: proof::Params,
+        replica: &Path,
+    ) -> Result<PrintableExtractionEntry, failure::Error> {
+        let graph_seed = self.graph_seed()?;
+        let seed = self.seed()?;
+        eprintln!("unsealing with seed {} of {}", seed.seed, seed.timestamp);
+
+        let res = proofs::seed_seal_work(prover.to_string(), params, seed, graph_seed, replica);
+        let body = serde_json::to_vec(&res)?;
+        let reply = self.retry(|| {
+            self.transport
+                .post("/api/extraction", JSON, Body::Bytes(&body))
+        })?;
+        let challenge: Challenge = serde_json::from_str(&reply.body)?;
+
+        // The server times from here.
+        let extraction = proofs::extraction_work(&res, &challenge, graph_seed, replica);
+        let body = serde_json::to_vec(&extraction)?;
+        let path = format!("/api/extraction/{}?token={}", challenge.id, challenge.token);
+        self.retry(|| self.transport.post(&path, JSON, Body::Bytes(&body)))?;
+
+        let reply = self.retry(|| self.transport.get("/api/extraction/leaderboard"))?;
+        let entries: Vec<PrintableExtractionEntry> = serde_json::from_str(&reply.body)?;
+        let id = Params::id(&res.proof_params);
+        entries
+            .into_iter()
+            .find(|entry| entry.prover == prover && entry.params.id == id)
+            .ok_or_else(|| format_err!("{} is not on the extraction leaderboard", prover))
+    }
+
+    pub fn seed(&self) -> Result<Seed, failure::Error> {
+        let reply = self.retry(|| self.transport.get("/api/seed"))?;
+        Ok(serde_json::from_str(&reply.body)?)
+    }
+
+    /// The seed the server builds the DRG graphs from.
+    pub fn graph_seed(&self) -> Result<[u32; 7], failure::Error> {
+        let reply = self.retry(|| self.transport.get("/api/graph-seed"))?;
+        let graph_seed: GraphSeed = serde_json::from_str(&reply.body)?;
+        Ok(graph_seed.graph_seed)
+    }
+
+    /// Posts the proof as gzip compressed JSON. Resubmitting a proof is
+    /// harmless, the server answers with the verdict it gave the first time.
+    pub fn submit(&self, res: &proof::Response) -> Result<(), failure::Error> {
+        // Serialized straight through the encoder into a temporary file, so
+        // the proof is never held in memory, and every retry sends the same
+        // compressed body.
+        let mut encoder = GzEncoder::new(tempfile::tempfile()?, Compression::best());
+        serde_json::to_writer(&mut encoder, res)?;
+        let body = encoder.finish()?;
+
+        self.retry(|| {
+            self.transport.post(
+                "/api/proof",
+                &[
+                    ("Content-Type", "application/json"),
+                    ("Content-Encoding", "gzip"),
+                ],
+                Body::File(&body),
+            )
+        })?;
+
+        Ok(())
+    }
+
+    /// The leaderboard entry of `prover` with `params`, and its rank.
+    pub fn rank(&self, prover: &str, params: &proof::Params) -> Result<Outcome, failure::Error> {
+        let path = format!(
+            "/api/leaderboard?hasher={}&size={}",
+            params.hasher.name(),
+            params.size
+        );
+        let reply = self.retry(|| self.transport.get(&path))?;
+        let entries: Vec<PrintableEntry> = serde_json::from_str(&reply.body)?;
+
+        let id = Params::id(params);
+        let track: Vec<_> = entries
+            .into_iter()
+            .filter(|entry| entry.params.id == id)
+            .collect();
+        let of = track.len();
+
+        track
+            .into_iter()
+            .enumerate()
+            .find(|(_, entry)| entry.prover == prover)
+            .map(|(i, entry)| Outcome {
+                entry,
+                rank: i + 1,
+                of,
+            })
+            .ok_or_else(|| format_err!("{} is not on the leaderboard", prover))
+    }
+
+    /// Sends a request until it succeeds, the server refuses it, or the
+    /// retries run out. Connection errors, rate limits and server errors are
+    /// retried.
+    fn retry<F>(&self, send: F) -> Result<Reply, failure::Error>
+    where
+        F: Fn() -> Result<Reply, failure::Error>,
+    {
+        let mut backoff = self.backoff;
+        let mut attempt = 0;
+
+        loop {
+            let (err, wait) = match send() {
+                Ok(ref reply) if reply.status < 300 => return Ok(reply.clone()),
+                Ok(reply) => {
+                    let rejection = Rejection::from_reply(&reply);
+                    if reply.status != 429 && reply.status < 500 {
+                        return Err(rejection.into());
+                    }
+                    let wait = reply.retry_after.map(Duration::from_secs);
+                    (rejection.into(), wait)
+                }
+                Err(err) => (err, None),
+            };
+
+            if attempt == self.retries {
+                return Err(err);
+            }
+            attempt += 1;
+
+            let wait = std::cmp::max(backoff, wait.unwrap_or_default());
+            eprintln!(
+                "request failed ({}), retrying in {}s ({}/{})",
+                err,
+                wait.as_secs(),
+                attempt,
+                self.retries
+            );
+            thread::sleep(wait);
+            backoff *= 2;
+        }
+    }
+}
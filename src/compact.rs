@@ -0,0 +1,362 @@
+//! Compact binary encoding of proof responses.
+//!
+//! Responses are encoded as CBOR. The same Merkle path nodes show up over and
+//! over again across challenges, so every field element is stored once in a
+//! node table, and referenced by its index everywhere else.
+//!
+//! A reference is a map with the single integer key `0`. JSON objects only
+//! have string keys, so no part of a response can be mistaken for one. The
+//! node table comes first, which lets `decode` resolve references while it
+//! reads the body, without an intermediate copy of it.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read};
+
+use rocket::data::{FromDataSimple, Outcome};
+use rocket::http::ContentType;
+use rocket::{Data, Outcome::*, Request, State};
+use serde::de::{self, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{SerializeMap, SerializeSeq, SerializeStruct, Serializer};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::config::GameConfig;
+use crate::encoding::{Body, Encoding, EncodingError};
+use crate::error::guard_failure;
+use crate::models::proof;
+
+/// Key of the single-entry maps that reference a node in the table.
+const REF: u64 = 0;
+
+/// Arrays of at least this many unsigned numbers are treated as nodes.
+const MIN_NODE_LEN: usize = 4;
+
+/// The content type of compact responses, `application/cbor`.
+pub fn content_type() -> ContentType {
+    ContentType::new("application", "cbor")
+}
+
+/// A response with its nodes replaced by references, as it is encoded.
+enum Tree {
+    Ref(usize),
+    Array(Vec<Tree>),
+    Object(Vec<(String, Tree)>),
+    Leaf(Value),
+}
+
+impl Serialize for Tree {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            Tree::Ref(idx) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry(&REF, &idx)?;
+                map.end()
+            }
+            Tree::Array(ref items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+            Tree::Object(ref entries) => {
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (key, value) in entries {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+            Tree::Leaf(ref value) => value.serialize(serializer),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Table {
+    nodes: Vec<Vec<u64>>,
+    index: HashMap<Vec<u64>, usize>,
+}
+
+impl Table {
+    fn intern(&mut self, value: Value) -> Tree {
+        match value {
+            Value::Array(items) => match as_node(&items) {
+                Some(node) => {
+                    let nodes = &mut self.nodes;
+                    let idx = *self.index.entry(node.clone()).or_insert_with(|| {
+                        nodes.push(node);
+                        nodes.len() - 1
+                    });
+                    Tree::Ref(idx)
+                }
+                None => Tree::Array(items.into_iter().map(|v| self.intern(v)).collect()),
+            },
+            Value::Object(map) => {
+                Tree::Object(map.into_iter().map(|(k, v)| (k, self.intern(v))).collect())
+            }
+            other => Tree::Leaf(other),
+        }
+    }
+}
+
+fn as_node(items: &[Value]) -> Option<Vec<u64>> {
+    if items.len() < MIN_NODE_LEN {
+        return None;
+    }
+
+    items.iter().map(Value::as_u64).collect()
+}
+
+/// Encodes a response into its compact representation.
+pub fn encode(res: &proof::Response) -> Result<Vec<u8>, failure::Error> {
+    let mut table = Table::default();
+    let body = table.intern(serde_json::to_value(res)?);
+
+    struct Encoded<'a> {
+        nodes: &'a [Vec<u64>],
+        body: &'a Tree,
+    }
+
+    impl<'a> Serialize for Encoded<'a> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut encoded = serializer.serialize_struct("Encoded", 2)?;
+            encoded.serialize_field("nodes", self.nodes)?;
+            encoded.serialize_field("body", self.body)?;
+            encoded.end()
+        }
+    }
+
+    let encoded = serde_cbor::to_vec(&Encoded {
+        nodes: &table.nodes,
+        body: &body,
+    })?;
+
+    Ok(encoded)
+}
+
+/// Decodes a response from its compact representation, leaving its proof to
+/// the decoder of its format. Fields of the wrong type make the proof
+/// invalid, as they do in JSON.
+pub fn decode<R: Read>(input: R) -> Result<proof::Envelope, EncodingError> {
+    let malformed = |e: serde_cbor::Error| {
+        EncodingError::Io(io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    };
+
+    let mut de = serde_cbor::Deserializer::from_reader(input);
+    let body = de.deserialize_map(EncodedVisitor).map_err(malformed)?;
+    de.end().map_err(malformed)?;
+
+    Ok(serde_json::from_value(body)?)
+}
+
+/// Reads the node table, then the body with its references resolved.
+struct EncodedVisitor;
+
+impl<'de> Visitor<'de> for EncodedVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a node table followed by a body")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        match map.next_key::<String>()? {
+            Some(ref key) if key == "nodes" => {}
+            _ => return Err(de::Error::custom("the node table must come first")),
+        }
+        let nodes: Vec<Vec<u64>> = map.next_value()?;
+
+        match map.next_key::<String>()? {
+            Some(ref key) if key == "body" => {}
+            _ => return Err(de::Error::custom("missing body")),
+        }
+        let body = map.next_value_seed(Resolve { nodes: &nodes })?;
+
+        if map.next_key::<String>()?.is_some() {
+            return Err(de::Error::custom("unexpected field after the body"));
+        }
+
+        Ok(body)
+    }
+}
+
+/// Reads a value of the body, replacing references with their nodes.
+#[derive(Clone, Copy)]
+struct Resolve<'a> {
+    nodes: &'a [Vec<u64>],
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for Resolve<'a> {
+    type Value = Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for Resolve<'a> {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a compact response body")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Value, E> {
+        Ok(Value::Bool(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Value, E> {
+        Ok(v.into())
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Value, E> {
+        Ok(v.into())
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<Value, E> {
+        Ok(v.into())
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Value, E> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Value, E> {
+        Ok(Value::String(v))
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        self.deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(item) = seq.next_element_seed(self)? {
+            items.push(item);
+        }
+
+        Ok(Value::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut object = Map::new();
+
+        while let Some(key) = map.next_key::<Key>()? {
+            match key {
+                Key::Ref if object.is_empty() => {
+                    let idx: usize = map.next_value()?;
+                    let node = self
+                        .nodes
+                        .get(idx)
+                        .ok_or_else(|| de::Error::custom(format!("unknown node {}", idx)))?;
+                    if map.next_key::<Key>()?.is_some() {
+                        return Err(de::Error::custom("invalid node reference"));
+                    }
+
+                    return Ok(Value::Array(node.iter().map(|&n| n.into()).collect()));
+                }
+                Key::Ref => return Err(de::Error::custom("invalid node reference")),
+                Key::Name(name) => {
+                    let value = map.next_value_seed(self)?;
+                    object.insert(name, value);
+                }
+            }
+        }
+
+        Ok(Value::Object(object))
+    }
+}
+
+/// A key of a map in the body: the name of a field, or `REF`.
+enum Key {
+    Ref,
+    Name(String),
+}
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Key, D::Error> {
+        struct KeyVisitor;
+
+        impl<'de> Visitor<'de> for KeyVisitor {
+            type Value = Key;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a field name or a node reference")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Key, E> {
+                if v == REF {
+                    Ok(Key::Ref)
+                } else {
+                    Err(E::custom(format!("unknown key {}", v)))
+                }
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Key, E> {
+                Ok(Key::Name(v.to_string()))
+            }
+
+            fn visit_string<E>(self, v: String) -> Result<Key, E> {
+                Ok(Key::Name(v))
+            }
+        }
+
+        deserializer.deserialize_any(KeyVisitor)
+    }
+}
+
+/// Data guard for `application/cbor` encoded responses, optionally sent with
+/// a `Content-Encoding`.
+#[derive(Debug)]
+pub struct Compact(pub proof::Envelope);
+
+impl Compact {
+    pub fn into_inner(self) -> proof::Envelope {
+        self.0
+    }
+}
+
+impl FromDataSimple for Compact {
+    type Error = EncodingError;
+
+    fn from_data(r: &Request, data: Data) -> Outcome<Self, Self::Error> {
+        let encoding = match Encoding::of_request(r) {
+            Ok(encoding) => encoding,
+            Err(e) => return Failure((guard_failure(r, e.code(), &e), e)),
+        };
+
+        let config = r
+            .guard::<State<GameConfig>>()
+            .expect("game config is managed");
+
+        let mut body = match Body::new(encoding, data.open(), config.limits.cbor, config.max_ratio)
+        {
+            Ok(body) => body,
+            Err(e) => {
+                let e = EncodingError::from(e);
+                return Failure((guard_failure(r, e.code(), &e), e));
+            }
+        };
+
+        match decode(&mut body) {
+            Ok(res) => Success(Compact(res)),
+            Err(e) => {
+                let e = if body.exceeded() {
+                    EncodingError::TooLarge(e.to_string())
+                } else {
+                    e
+                };
+                Failure((guard_failure(r, e.code(), &e), e))
+            }
+        }
+    }
+}
@@ -0,0 +1,387 @@
+//! Settings of the game, read from the `game` table of `Rocket.toml` and from
+//! the environment when the server ignites.
+//!
+//! ```toml
+//! [global.game]
+//! key = "..."          # or GAME_KEY
+//! seed_ttl = 604800    # or GAME_SEED_TTL
+//! max_ratio = 100      # or GAME_MAX_RATIO
+//!
+//! [global.game.budget]
+//! max_size = 17179869184
+//!
+//! [global.game.policy]
+//! proof_types = ["Zigzag", "DrgPoRep", "ZigzagSnark"]
+//! hashers = ["Pedersen", "Blake2s", "Sha256"]
+//!
+//! [global.game.audits]
+//! enabled = false
+//! ```
+//!
+//! Body size limits are taken from Rocket's own `limits`, so that they apply
+//! alike to every way a proof can be sent.
+
+use std::env;
+use std::str::FromStr;
+
+use failure::{bail, ensure, format_err};
+use rocket::config::{Config, ConfigError, Value};
+use serde::Deserialize;
+
+use crate::models::proof::{self, HasherType, ProofType, MIN_NODES};
+
+/// The key seeds are signed with when none is configured. Fine for playing
+/// locally, not for a public server.
+pub const DEFAULT_KEY: &str = "my cool key";
+
+/// The seed of the DRG graphs when none is configured.
+pub const DEFAULT_GRAPH_SEED: [u32; 7] = [0; 7];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameConfig {
+    /// Key of the MACs that make seeds unforgeable.
+    pub key: String,
+    /// How long a seed stays valid after it was issued, in seconds.
+    pub seed_ttl: u64,
+    /// The largest accepted ratio between decoded and encoded body size.
+    pub max_ratio: u64,
+    /// The seed of the DRG graphs, published to provers at `/api/graph-seed`.
+    pub graph_seed: [u32; 7],
+    /// How long an upload session is kept without activity, in seconds.
+    pub upload_ttl: u64,
+    /// The most upload sessions a client address may keep open.
+    pub upload_max_sessions: usize,
+    /// The most bytes of chunks kept for all upload sessions together.
+    pub upload_max_bytes: u64,
+    /// The most proofs accepted in one batch.
+    pub batch_max_items: usize,
+    /// The directory with the circuit parameters of the zigzag-snark tracks.
+    pub snark_params: String,
+    pub budget: Budget,
+    pub policy: Policy,
+    pub audits: Audits,
+    #[serde(skip)]
+    pub limits: BodyLimits,
+}
+
+impl Default for GameConfig {
+    fn default() -> GameConfig {
+        GameConfig {
+            key: DEFAULT_KEY.into(),
+            seed_ttl: 7 * 24 * 60 * 60,
+            max_ratio: 100,
+            graph_seed: DEFAULT_GRAPH_SEED,
+            upload_ttl: 60 * 60,
+            upload_max_sessions: 4,
+            upload_max_bytes: 16 << 30,
+            batch_max_items: 100,
+            snark_params: "./snark-params".into(),
+            budget: Budget::default(),
+            policy: Policy::default(),
+            audits: Audits::default(),
+            limits: BodyLimits::default(),
+        }
+    }
+}
+
+/// Upper bounds on the work a single verification may take.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Budget {
+    /// The largest replica size, in bytes.
+    pub max_size: u64,
+    pub max_challenge_count: usize,
+    pub max_vde: usize,
+    pub max_degree: usize,
+    pub max_layers: usize,
+    pub max_partitions: usize,
+    pub max_replicas: usize,
+}
+
+impl Default for Budget {
+    fn default() -> Budget {
+        Budget {
+            max_size: 16 << 30,
+            max_challenge_count: 10_000,
+            max_vde: 10,
+            max_degree: 100,
+            max_layers: 20,
+            max_partitions: 16,
+            max_replicas: 16,
+        }
+    }
+}
+
+impl Budget {
+    pub fn check(&self, params: &proof::Params) -> Result<(), failure::Error> {
+        ensure!(
+            params.size as u64 <= self.max_size,
+            "size must be at most {} bytes, got {}",
+            self.max_size,
+            params.size
+        );
+        ensure!(
+            params.challenge_count <= self.max_challenge_count,
+            "challenge_count must be at most {}, got {}",
+            self.max_challenge_count,
+            params.challenge_count
+        );
+        ensure!(
+            params.vde <= self.max_vde,
+            "vde must be at most {}, got {}",
+            self.max_vde,
+            params.vde
+        );
+        ensure!(
+            params.degree <= self.max_degree,
+            "degree must be at most {}, got {}",
+            self.max_degree,
+            params.degree
+        );
+        ensure!(
+            params.replicas <= self.max_replicas,
+            "replicas must be at most {}, got {}",
+            self.max_replicas,
+            params.replicas
+        );
+        if let Some(ref zigzag) = params.zigzag {
+            ensure!(
+                zigzag.layers <= self.max_layers,
+                "layers must be at most {}, got {}",
+                self.max_layers,
+                zigzag.layers
+            );
+            ensure!(
+                zigzag.partitions <= self.max_partitions,
+                "partitions must be at most {}, got {}",
+                self.max_partitions,
+                zigzag.partitions
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Which proofs are played.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    pub proof_types: Vec<ProofType>,
+    pub hashers: Vec<HasherType>,
+}
+
+impl Default for Policy {
+    fn default() -> Policy {
+        Policy {
+            proof_types: vec![
+                ProofType::Zigzag,
+                ProofType::DrgPoRep,
+                ProofType::ZigzagSnark,
+            ],
+            hashers: vec![
+                HasherType::Pedersen,
+                HasherType::Blake2s,
+                HasherType::Sha256,
+            ],
+        }
+    }
+}
+
+impl Policy {
+    pub fn check(&self, params: &proof::Params) -> Result<(), failure::Error> {
+        ensure!(
+            self.proof_types.contains(&params.typ),
+            "{:?} proofs are not accepted",
+            params.typ
+        );
+        ensure!(
+            self.hashers.contains(&params.hasher),
+            "{} hashes are not accepted",
+            params.hasher.name()
+        );
+
+        Ok(())
+    }
+}
+
+/// Follow-up audits of accepted submissions, which check that the prover
+/// still holds the replica.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Audits {
+    pub enabled: bool,
+    /// How many audits are scheduled per accepted submission.
+    pub count: usize,
+    /// The audits are issued at random within this many seconds after the
+    /// submission.
+    pub window: u64,
+    /// How many seconds the prover has to answer an audit once it is issued.
+    pub deadline: u64,
+    /// The number of nodes challenged per audit.
+    pub challenges: usize,
+}
+
+impl Default for Audits {
+    fn default() -> Audits {
+        Audits {
+            enabled: false,
+            count: 3,
+            window: 24 * 60 * 60,
+            deadline: 60 * 60,
+            challenges: 4,
+        }
+    }
+}
+
+/// Request body limits, in bytes.
+#[derive(Debug, Clone)]
+pub struct BodyLimits {
+    pub json: u64,
+    pub cbor: u64,
+    pub batch: u64,
+    /// A whole resumable upload.
+    pub upload: u64,
+    /// A single chunk of a resumable upload.
+    pub chunk: u64,
+}
+
+impl Default for BodyLimits {
+    fn default() -> BodyLimits {
+        BodyLimits {
+            json: 1 << 20,
+            cbor: 1 << 20,
+            batch: 1 << 20,
+            upload: 1 << 30,
+            chunk: 16 << 20,
+        }
+    }
+}
+
+impl GameConfig {
+    /// Reads the `game` table and the body limits of a Rocket configuration,
+    /// then applies the `GAME_*` environment variables.
+    pub fn from_config(config: &Config) -> Result<GameConfig, failure::Error> {
+        let mut game: GameConfig = match config.get_table("game") {
+            Ok(table) => Value::Table(table.clone())
+                .try_into()
+                .map_err(|e| format_err!("invalid [game] section: {}", e))?,
+            Err(ConfigError::Missing(_)) => GameConfig::default(),
+            Err(e) => bail!("invalid [game] section: {}", e),
+        };
+
+        if let Ok(key) = env::var("GAME_KEY") {
+            game.key = key;
+        }
+        if let Some(ttl) = parse_env("GAME_SEED_TTL")? {
+            game.seed_ttl = ttl;
+        }
+        if let Some(ratio) = parse_env("GAME_MAX_RATIO")? {
+            game.max_ratio = ratio;
+        }
+
+        let defaults = BodyLimits::default();
+        game.limits = BodyLimits {
+            json: config.limits.get("json").unwrap_or(defaults.json),
+            cbor: config.limits.get("cbor").unwrap_or(defaults.cbor),
+            batch: config.limits.get("batch").unwrap_or(defaults.batch),
+            upload: config.limits.get("upload").unwrap_or(defaults.upload),
+            chunk: config.limits.get("chunk").unwrap_or(defaults.chunk),
+        };
+
+        game.validate()?;
+
+        if config.environment.is_prod() && game.key == DEFAULT_KEY {
+            println!("Warning: seeds are signed with the default key, set game.key or GAME_KEY");
+        }
+
+        Ok(game)
+    }
+
+    pub fn validate(&self) -> Result<(), failure::Error> {
+        ensure!(!self.key.is_empty(), "game.key must not be empty");
+        ensure!(self.seed_ttl > 0, "game.seed_ttl must be at least 1 second");
+        ensure!(self.max_ratio >= 1, "game.max_ratio must be at least 1");
+        ensure!(
+            self.upload_ttl > 0,
+            "game.upload_ttl must be at least 1 second"
+        );
+        ensure!(
+            self.upload_max_sessions >= 1,
+            "game.upload_max_sessions must be at least 1"
+        );
+        ensure!(
+            self.batch_max_items >= 1,
+            "game.batch_max_items must be at least 1"
+        );
+        ensure!(
+            self.budget.max_size >= (MIN_NODES * 32) as u64,
+            "game.budget.max_size must be at least {} bytes",
+            MIN_NODES * 32
+        );
+        ensure!(
+            self.budget.max_challenge_count >= 1
+                && self.budget.max_degree >= 1
+                && self.budget.max_layers >= 1
+                && self.budget.max_partitions >= 1
+                && self.budget.max_replicas >= 1,
+            "game.budget limits must be at least 1"
+        );
+        ensure!(
+            !self.policy.proof_types.is_empty(),
+            "game.policy.proof_types must allow at least one proof type"
+        );
+        ensure!(
+            !self.policy.hashers.is_empty(),
+            "game.policy.hashers must allow at least one hasher"
+        );
+
+        ensure!(
+            self.audits.count >= 1
+                && self.audits.window >= 1
+                && self.audits.deadline >= 1
+                && self.audits.challenges >= 1,
+            "game.audits settings must be at least 1"
+        );
+
+        let limits = &self.limits;
+        ensure!(
+            limits.json > 0 && limits.cbor > 0 && limits.batch > 0 && limits.chunk > 0,
+            "limits must be positive"
+        );
+        ensure!(
+            limits.chunk <= limits.upload,
+            "limits.chunk ({}) must not exceed limits.upload ({})",
+            limits.chunk,
+            limits.upload
+        );
+        ensure!(
+            limits.upload <= self.upload_max_bytes,
+            "limits.upload ({}) must not exceed game.upload_max_bytes ({})",
+            limits.upload,
+            self.upload_max_bytes
+        );
+
+        Ok(())
+    }
+
+    /// Checks that the params are within budget and allowed by the policy.
+    pub fn check_params(&self, params: &proof::Params) -> Result<(), failure::Error> {
+        self.policy.check(params)?;
+        self.budget.check(params)
+    }
+}
+
+fn parse_env<T: FromStr>(name: &str) -> Result<Option<T>, failure::Error>
+where
+    T::Err: std::fmt::Display,
+{
+    match env::var(name) {
+        Ok(val) => val
+            .parse()
+            .map(Some)
+            .map_err(|e| format_err!("invalid {}={:?}: {}", name, val, e)),
+        Err(_) => Ok(None),
+    }
+}
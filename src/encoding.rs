@@ -0,0 +1,508 @@
+use std::cell::Cell;
+use std::error;
+use std::fmt;
+use std::io::prelude::*;
+use std::io::{self, BufReader, Cursor};
+use std::rc::Rc;
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use rocket::data::{FromDataSimple, Outcome};
+use rocket::http::ContentType;
+use rocket::{fairing, http, Data, Outcome::*, Request, Response, State};
+use rocket_contrib::json::Json;
+use serde::de::DeserializeOwned;
+
+use crate::config::GameConfig;
+use crate::error::{guard_failure, ErrorCode};
+
+/// A `Content-Encoding` supported for request and response bodies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Identity,
+    Gzip,
+    Deflate,
+    Zstd,
+    Brotli,
+}
+
+/// Response encodings, most preferred first.
+const PREFERENCE: [Encoding; 4] = [
+    Encoding::Brotli,
+    Encoding::Zstd,
+    Encoding::Gzip,
+    Encoding::Deflate,
+];
+
+impl Encoding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Identity => "identity",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+            Encoding::Zstd => "zstd",
+            Encoding::Brotli => "br",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match name.trim().to_lowercase().as_str() {
+            "identity" => Some(Encoding::Identity),
+            "gzip" | "x-gzip" => Some(Encoding::Gzip),
+            "deflate" => Some(Encoding::Deflate),
+            "zstd" => Some(Encoding::Zstd),
+            "br" => Some(Encoding::Brotli),
+            _ => None,
+        }
+    }
+
+    /// The encoding of a request body, from its `Content-Encoding` header.
+    /// Only a single encoding is supported.
+    pub fn of_request(r: &Request) -> Result<Encoding, EncodingError> {
+        let names = r
+            .headers()
+            .get("Content-Encoding")
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .collect::<Vec<_>>();
+
+        let encodings = names
+            .iter()
+            .map(|name| {
+                Encoding::from_name(name)
+                    .ok_or_else(|| EncodingError::Unsupported(name.to_string()))
+            })
+            .filter(|e| match e {
+                Ok(Encoding::Identity) => false,
+                _ => true,
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        match encodings.len() {
+            0 => Ok(Encoding::Identity),
+            1 => Ok(encodings[0]),
+            _ => Err(EncodingError::Unsupported(names.join(", "))),
+        }
+    }
+
+    /// Picks the most preferred encoding accepted by an `Accept-Encoding`
+    /// header, honoring quality values. Returns `None` when the body should
+    /// be sent as is.
+    pub fn negotiate(accept: &str) -> Option<Encoding> {
+        Encoding::negotiate_among(accept, &PREFERENCE)
+    }
+
+    /// Like `negotiate`, but only considers the `available` encodings, which
+    /// are given most preferred first.
+    pub fn negotiate_among(accept: &str, available: &[Encoding]) -> Option<Encoding> {
+        let mut wildcard = None;
+        let accepted = accept
+            .split(',')
+            .filter_map(|item| {
+                let mut parts = item.split(';');
+                let name = parts.next()?.trim().to_lowercase();
+                let q = parts
+                    .filter_map(|p| {
+                        let p = p.trim();
+                        if p.starts_with("q=") {
+                            p[2..].trim().parse::<f32>().ok()
+                        } else {
+                            None
+                        }
+                    })
+                    .next()
+                    .unwrap_or(1.);
+
+                if name == "*" {
+                    wildcard = Some(q);
+                    None
+                } else {
+                    Some((Encoding::from_name(&name)?, q))
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut best: Option<(Encoding, f32)> = None;
+        for &encoding in available {
+            let q = accepted
+                .iter()
+                .find(|(e, _)| *e == encoding)
+                .map(|(_, q)| *q)
+                .or(wildcard)
+                .unwrap_or(0.);
+
+            if q > 0. && best.map(|(_, best_q)| q > best_q).unwrap_or(true) {
+                best = Some((encoding, q));
+            }
+        }
+
+        best.map(|(encoding, _)| encoding)
+    }
+
+    /// Wraps `input` in a reader that decodes it.
+    pub fn decoder<'a, R: Read + 'a>(self, input: R) -> io::Result<Box<dyn Read + 'a>> {
+        let decoder: Box<dyn Read + 'a> = match self {
+            Encoding::Identity => Box::new(input),
+            Encoding::Gzip => Box::new(GzDecoder::new(input)),
+            Encoding::Deflate => Box::new(ZlibDecoder::new(input)),
+            Encoding::Zstd => Box::new(zstd::stream::read::Decoder::new(input)?),
+            Encoding::Brotli => Box::new(brotli::Decompressor::new(input, 4096)),
+        };
+
+        Ok(decoder)
+    }
+
+    /// Encodes a full body.
+    pub fn encode(self, body: &[u8]) -> io::Result<Vec<u8>> {
+        self.encode_with(body, false)
+    }
+
+    /// Encodes with the slowest, best compression. Meant for bodies that are
+    /// compressed once and served many times.
+    pub fn encode_best(self, body: &[u8]) -> io::Result<Vec<u8>> {
+        self.encode_with(body, true)
+    }
+
+    fn encode_with(self, body: &[u8], best: bool) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Identity => Ok(body.to_vec()),
+            Encoding::Gzip => {
+                let level = if best {
+                    Compression::best()
+                } else {
+                    Compression::default()
+                };
+                let mut gz = GzEncoder::new(Vec::new(), level);
+                gz.write_all(body)?;
+                gz.finish()
+            }
+            Encoding::Deflate => {
+                let level = if best {
+                    Compression::best()
+                } else {
+                    Compression::default()
+                };
+                let mut zlib = ZlibEncoder::new(Vec::new(), level);
+                zlib.write_all(body)?;
+                zlib.finish()
+            }
+            Encoding::Zstd => zstd::stream::encode_all(body, if best { 19 } else { 3 }),
+            Encoding::Brotli => {
+                let quality = if best { 11 } else { 5 };
+                let mut br = brotli::CompressorWriter::new(Vec::new(), 4096, quality, 22);
+                br.write_all(body)?;
+                Ok(br.into_inner())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Bodies smaller than this are not worth compressing.
+pub const MIN_COMPRESS_SIZE: usize = 860;
+
+/// Whether a body of this content type gets smaller when compressed. Images,
+/// fonts and archives in formats that are compressed already do not.
+pub fn is_compressible(content_type: &ContentType) -> bool {
+    let top = content_type.top().as_str().to_lowercase();
+    let sub = content_type.sub().as_str().to_lowercase();
+
+    match (top.as_str(), sub.as_str()) {
+        ("image", "svg+xml") | ("image", "x-icon") | ("image", "bmp") => true,
+        ("image", _) | ("audio", _) | ("video", _) => false,
+        ("font", "woff") | ("font", "woff2") => false,
+        ("application", "font-woff")
+        | ("application", "zip")
+        | ("application", "gzip")
+        | ("application", "x-gzip")
+        | ("application", "cbor")
+        | ("application", "zstd")
+        | ("application", "x-brotli") => false,
+        _ => true,
+    }
+}
+
+#[derive(Debug)]
+pub struct CompressionFairing;
+
+impl fairing::Fairing for CompressionFairing {
+    fn info(&self) -> fairing::Info {
+        fairing::Info {
+            name: "Response compression",
+            kind: fairing::Kind::Response,
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        let accept = request
+            .headers()
+            .get("Accept-Encoding")
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let encoding = Encoding::negotiate(&accept)
+            .filter(|_| !response.headers().contains("Content-Encoding"))
+            // Static assets pick their own representation, and tag it.
+            .filter(|_| !response.headers().contains("ETag"))
+            .filter(|_| {
+                response
+                    .content_type()
+                    .map(|ct| is_compressible(&ct))
+                    .unwrap_or(true)
+            });
+
+        if let Some(encoding) = encoding {
+            response.body_bytes().and_then(|body| {
+                if body.len() < MIN_COMPRESS_SIZE {
+                    response.set_sized_body(Cursor::new(body));
+                    return None;
+                }
+
+                encoding
+                    .encode(&body)
+                    .and_then(|buf| {
+                        response.set_sized_body(Cursor::new(buf));
+                        response.set_raw_header("Content-Encoding", encoding.as_str());
+                        response.set_raw_header("Vary", "Accept-Encoding");
+                        Ok(())
+                    })
+                    .map_err(|e| eprintln!("{}", e))
+                    .ok()
+            });
+        }
+
+        // Enable cache control on static assets
+        let uri = request.uri().path();
+
+        if request.method() == http::Method::Get && !uri.starts_with("/api") && uri != "/" {
+            response.set_raw_header("Cache-Control", "public, max-age=31536000");
+        } else {
+            response.set_raw_header("Cache-Control", "no-cache, no-store, must-revalidate");
+        }
+    }
+}
+
+/// Data guard for request bodies sent with a `Content-Encoding`.
+#[derive(Debug)]
+pub struct Compressed<T>(pub T);
+
+impl<T> Compressed<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+#[derive(Debug)]
+pub enum EncodingError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    TooLarge(String),
+    Unsupported(String),
+}
+
+impl EncodingError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            EncodingError::Io(_) => ErrorCode::MalformedRequest,
+            EncodingError::Json(e) => {
+                if e.is_data() {
+                    ErrorCode::InvalidProof
+                } else {
+                    ErrorCode::MalformedRequest
+                }
+            }
+            EncodingError::TooLarge(_) => ErrorCode::PayloadTooLarge,
+            EncodingError::Unsupported(_) => ErrorCode::UnsupportedEncoding,
+        }
+    }
+}
+
+impl fmt::Display for EncodingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EncodingError::Io(e) => write!(f, "{}", e),
+            EncodingError::Json(e) => write!(f, "{}", e),
+            EncodingError::TooLarge(msg) | EncodingError::Unsupported(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl From<io::Error> for EncodingError {
+    fn from(err: io::Error) -> EncodingError {
+        let too_large = err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<TooLarge>())
+            .map(|e| e.0.clone());
+
+        match too_large {
+            Some(msg) => EncodingError::TooLarge(msg),
+            None => EncodingError::Io(err),
+        }
+    }
+}
+
+impl From<serde_json::Error> for EncodingError {
+    fn from(err: serde_json::Error) -> EncodingError {
+        if err.is_io() {
+            io::Error::from(err).into()
+        } else {
+            EncodingError::Json(err)
+        }
+    }
+}
+
+/// Bodies that decode to less than this are not subject to the ratio limit.
+const RATIO_GRACE: u64 = 1 << 20;
+
+#[derive(Debug)]
+struct TooLarge(String);
+
+impl fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl error::Error for TooLarge {}
+
+fn too_large(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, TooLarge(msg))
+}
+
+/// Shared between the raw and the decoded side of a `Body`.
+#[derive(Debug, Default)]
+struct Tally {
+    raw: Cell<u64>,
+    exceeded: Cell<bool>,
+}
+
+/// Counts the raw bytes read, failing once there are more than `limit`.
+struct Counted<R> {
+    inner: R,
+    tally: Rc<Tally>,
+    limit: u64,
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.tally.raw.set(self.tally.raw.get() + n as u64);
+        if self.tally.raw.get() > self.limit {
+            self.tally.exceeded.set(true);
+            return Err(too_large(format!("body exceeds {} bytes", self.limit)));
+        }
+        Ok(n)
+    }
+}
+
+/// A decoded request body, that fails once the output exceeds `size_limit`
+/// bytes or expands to more than `max_ratio` times the encoded input.
+pub(crate) struct Body<'a> {
+    decoder: Box<dyn Read + 'a>,
+    tally: Rc<Tally>,
+    count: u64,
+    size_limit: u64,
+    max_ratio: u64,
+}
+
+impl<'a> Body<'a> {
+    pub(crate) fn new<R: Read + 'a>(
+        encoding: Encoding,
+        input: R,
+        size_limit: u64,
+        max_ratio: u64,
+    ) -> io::Result<Self> {
+        let tally = Rc::new(Tally::default());
+        let decoder = encoding.decoder(Counted {
+            inner: input,
+            tally: tally.clone(),
+            limit: size_limit,
+        })?;
+
+        Ok(Body {
+            decoder,
+            tally,
+            count: 0,
+            size_limit,
+            max_ratio,
+        })
+    }
+
+    /// Whether reading failed because one of the limits was exceeded.
+    pub(crate) fn exceeded(&self) -> bool {
+        self.tally.exceeded.get()
+    }
+}
+
+impl<'a> Read for Body<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.decoder.read(buf)?;
+        self.count += n as u64;
+
+        if self.count > self.size_limit {
+            self.tally.exceeded.set(true);
+            return Err(too_large(format!(
+                "decoded body exceeds {} bytes",
+                self.size_limit
+            )));
+        }
+
+        let raw = std::cmp::max(self.tally.raw.get(), 1);
+        if self.count > RATIO_GRACE && self.count / raw > self.max_ratio {
+            self.tally.exceeded.set(true);
+            return Err(too_large(format!(
+                "body expands more than {} times when decoded",
+                self.max_ratio
+            )));
+        }
+
+        Ok(n)
+    }
+}
+
+/// Decodes a JSON value, sent with the given encoding, that decodes to at
+/// most `size_limit` bytes, at a ratio of at most `max_ratio`.
+///
+/// The body is decoded and parsed as it streams in, so at no point is the
+/// full decoded document held in memory.
+pub fn decode<T: DeserializeOwned, R: Read>(
+    encoding: Encoding,
+    input: R,
+    size_limit: u64,
+    max_ratio: u64,
+) -> Result<T, EncodingError> {
+    let body = Body::new(encoding, input, size_limit, max_ratio)?;
+    let mut de = serde_json::Deserializer::from_reader(BufReader::new(body));
+    let value = T::deserialize(&mut de)?;
+    de.end()?;
+
+    Ok(value)
+}
+
+impl<T: DeserializeOwned> FromDataSimple for Compressed<Json<T>> {
+    type Error = EncodingError;
+
+    fn from_data(r: &Request, data: Data) -> Outcome<Self, Self::Error> {
+        let encoding = match Encoding::of_request(r) {
+            Ok(Encoding::Identity) => return Forward(data),
+            Ok(encoding) => encoding,
+            Err(e) => return Failure((guard_failure(r, e.code(), &e), e)),
+        };
+
+        let config = r
+            .guard::<State<GameConfig>>()
+            .expect("game config is managed");
+
+        match decode(encoding, data.open(), config.limits.json, config.max_ratio) {
+            Ok(v) => Success(Compressed(Json(v))),
+            Err(e) => Failure((guard_failure(r, e.code(), &e), e)),
+        }
+    }
+}
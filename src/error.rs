@@ -5,40 +5,227 @@ use rocket::http::{ContentType, Status};
 use rocket::request::Request;
 use rocket::response::{Responder, Response};
 use rocket_contrib::json;
+use rocket_contrib::json::JsonValue;
 
 pub type ApiResult<T> = Result<T, ApiError>;
 
+/// Stable, machine-readable error codes, sent as the `code` field of every
+/// error response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    MalformedRequest,
+    InvalidSeed,
+    ExpiredSeed,
+    InvalidProof,
+    UnsupportedFormat,
+    ParamsNotAllowed,
+    PayloadTooLarge,
+    UnsupportedEncoding,
+    UnsupportedMediaType,
+    ChecksumMismatch,
+    IncompleteUpload,
+    RateLimited,
+    DuplicateProof,
+    MissedAudit,
+    /// A conflict no more specific code describes.
+    Conflict,
+    /// A well-formed request no more specific code describes, that could not
+    /// be processed.
+    UnprocessableEntity,
+    Unauthorized,
+    NotFound,
+    Internal,
+}
+
+impl ErrorCode {
+    const ALL: [ErrorCode; 19] = [
+        ErrorCode::MalformedRequest,
+        ErrorCode::InvalidSeed,
+        ErrorCode::ExpiredSeed,
+        ErrorCode::InvalidProof,
+        ErrorCode::UnsupportedFormat,
+        ErrorCode::ParamsNotAllowed,
+        ErrorCode::PayloadTooLarge,
+        ErrorCode::UnsupportedEncoding,
+        ErrorCode::UnsupportedMediaType,
+        ErrorCode::ChecksumMismatch,
+        ErrorCode::IncompleteUpload,
+        ErrorCode::RateLimited,
+        ErrorCode::DuplicateProof,
+        ErrorCode::MissedAudit,
+        ErrorCode::Conflict,
+        ErrorCode::UnprocessableEntity,
+        ErrorCode::Unauthorized,
+        ErrorCode::NotFound,
+        ErrorCode::Internal,
+    ];
+
+    /// The code with the given `as_str` name.
+    pub fn parse(name: &str) -> Option<ErrorCode> {
+        ErrorCode::ALL
+            .iter()
+            .cloned()
+            .find(|code| code.as_str() == name)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::MalformedRequest => "malformed_request",
+            ErrorCode::InvalidSeed => "invalid_seed",
+            ErrorCode::ExpiredSeed => "expired_seed",
+            ErrorCode::InvalidProof => "invalid_proof",
+            ErrorCode::UnsupportedFormat => "unsupported_format",
+            ErrorCode::ParamsNotAllowed => "params_not_allowed",
+            ErrorCode::PayloadTooLarge => "payload_too_large",
+            ErrorCode::UnsupportedEncoding => "unsupported_encoding",
+            ErrorCode::UnsupportedMediaType => "unsupported_media_type",
+            ErrorCode::ChecksumMismatch => "checksum_mismatch",
+            ErrorCode::IncompleteUpload => "incomplete_upload",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::DuplicateProof => "duplicate_proof",
+            ErrorCode::MissedAudit => "missed_audit",
+            ErrorCode::Conflict => "conflict",
+            ErrorCode::UnprocessableEntity => "unprocessable_entity",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::Internal => "internal_error",
+        }
+    }
+
+    pub fn status(self) -> Status {
+        match self {
+            ErrorCode::MalformedRequest => Status::BadRequest,
+            ErrorCode::InvalidSeed | ErrorCode::Unauthorized => Status::Unauthorized,
+            ErrorCode::ExpiredSeed
+            | ErrorCode::IncompleteUpload
+            | ErrorCode::DuplicateProof
+            | ErrorCode::MissedAudit
+            | ErrorCode::Conflict => Status::Conflict,
+            ErrorCode::InvalidProof
+            | ErrorCode::UnprocessableEntity
+            | ErrorCode::UnsupportedFormat
+            | ErrorCode::ParamsNotAllowed
+            | ErrorCode::ChecksumMismatch => Status::UnprocessableEntity,
+            ErrorCode::PayloadTooLarge => Status::PayloadTooLarge,
+            ErrorCode::UnsupportedEncoding | ErrorCode::UnsupportedMediaType => {
+                Status::UnsupportedMediaType
+            }
+            ErrorCode::RateLimited => Status::TooManyRequests,
+            ErrorCode::NotFound => Status::NotFound,
+            ErrorCode::Internal => Status::InternalServerError,
+        }
+    }
+}
+
+/// The code and message of an error a request guard failed with, kept on the
+/// request for the catcher of its status.
+struct GuardFailure(Option<(ErrorCode, String)>);
+
+/// Keeps the error a request guard fails with on `request`, and returns the
+/// status to fail with. Rocket drops the errors of guards, so without it the
+/// catcher could only guess the code.
+pub fn guard_failure<E: fmt::Display>(request: &Request, code: ErrorCode, err: &E) -> Status {
+    request.local_cache(|| GuardFailure(Some((code, err.to_string()))));
+    code.status()
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
 #[derive(Debug)]
-pub struct ApiError(failure::Error);
+pub struct ApiError {
+    code: ErrorCode,
+    inner: failure::Error,
+    retry_after: Option<u64>,
+}
+
+impl ApiError {
+    pub fn new<E: Into<failure::Error>>(code: ErrorCode, err: E) -> ApiError {
+        ApiError {
+            code,
+            inner: err.into(),
+            retry_after: None,
+        }
+    }
+
+    /// Tells the client to wait `secs` seconds before trying again.
+    pub fn with_retry_after(mut self, secs: u64) -> ApiError {
+        self.retry_after = Some(secs);
+        self
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+
+    /// The error a request guard failed `request` with, if it was kept with
+    /// `guard_failure` and has the given status, or else `code` with
+    /// `message`.
+    pub fn caught(request: &Request, status: Status, code: ErrorCode, message: &str) -> ApiError {
+        match request.local_cache(|| GuardFailure(None)).0 {
+            Some((code, ref message)) if code.status() == status => {
+                ApiError::new(code, failure::err_msg(message.clone()))
+            }
+            _ => ApiError::new(code, failure::err_msg(message.to_string())),
+        }
+    }
+
+    /// The JSON body describing this error.
+    pub fn to_json(&self) -> JsonValue {
+        json!({
+            "status": "failure",
+            "code": self.code.as_str(),
+            "message": format!("Error: {}", self),
+        })
+    }
+}
 
 impl fmt::Display for ApiError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}", self.inner)
     }
 }
 
+/// Anything not explicitly tagged with a code is an internal error.
 impl<T: Into<failure::Error>> From<T> for ApiError {
     fn from(t: T) -> ApiError {
-        ApiError(t.into())
+        ApiError::new(ErrorCode::Internal, t)
     }
 }
+
+/// Tag the error of a `Result` with an `ErrorCode`.
+pub trait ResultExt<T> {
+    fn with_code(self, code: ErrorCode) -> ApiResult<T>;
+}
+
+impl<T, E: Into<failure::Error>> ResultExt<T> for Result<T, E> {
+    fn with_code(self, code: ErrorCode) -> ApiResult<T> {
+        self.map_err(|e| ApiError::new(code, e))
+    }
+}
+
 impl<'a> Responder<'a> for ApiError {
     fn respond_to(self, _: &Request) -> Result<Response<'static>, Status> {
-        println!("Error {}", self);
+        println!("Error [{}] {}", self.code, self);
 
         // Create JSON response
-        let resp = json!({
-            "status": "failure",
-            "message": format!("Error: {}", self),
-        })
-        .to_string();
+        let resp = self.to_json().to_string();
 
         // Respond. The `Ok` here is a bit of a misnomer. It means we
         // successfully created an error response
-        Ok(Response::build()
-            .status(Status::BadRequest)
+        let mut response = Response::build();
+        response
+            .status(self.code.status())
             .header(ContentType::JSON)
-            .sized_body(Cursor::new(resp))
-            .finalize())
+            .sized_body(Cursor::new(resp));
+
+        if let Some(secs) = self.retry_after {
+            response.raw_header("Retry-After", secs.to_string());
+        }
+
+        Ok(response.finalize())
     }
 }
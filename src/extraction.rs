@@ -0,0 +1,186 @@
+//! The unsealing race.
+//!
+//! Instead of random data, the prover replicates data derived from the seed,
+//! so the server can recompute any node of the original without storing it.
+//! The race has two phases: the prover first submits the sealing proof of
+//! the replica, and only then is issued a few node ranges, which it decodes
+//! from the replica and returns with Merkle inclusion proofs in `comm_d`.
+//! The extraction time runs on the server, from issuing the ranges to
+//! receiving them.
+//!
+//! As the original data is derived from the public seed, the answer shows
+//! that the prover can regenerate the data and holds a replica committing to
+//! it, not that it decoded the ranges from the replica.
+
+use std::fs::File;
+use std::io::Write;
+
+use blake2::crypto_mac::Mac;
+use blake2::{Blake2b, Digest};
+use failure::{ensure, format_err};
+use memmap::{MmapMut, MmapOptions};
+use serde::{Deserialize, Serialize};
+
+use crate::models::audit::AuditProofs;
+use crate::models::proof::{Commitment, HasherType};
+use crate::models::seed::Seed;
+use crate::schemes;
+
+/// The number of ranges in a challenge.
+pub const RANGE_COUNT: usize = 4;
+
+/// The most nodes a single range covers.
+pub const RANGE_NODES: usize = 16;
+
+/// The ranges of nodes to extract from an accepted replica, and the id and
+/// token to answer them under.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Challenge {
+    pub id: i32,
+    pub ranges: Vec<NodeRange>,
+    /// Binds the answer to the prover of the replica, see `token`.
+    pub token: String,
+}
+
+/// `len` nodes starting at node `start`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeRange {
+    pub start: usize,
+    pub len: usize,
+}
+
+/// A range of the original data, as decoded from the replica.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExtractedRange {
+    pub start: usize,
+    pub len: usize,
+    /// The hex encoded nodes.
+    pub data: String,
+}
+
+/// The ranges extracted from a replica, in the order they were issued.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Extraction {
+    pub ranges: Vec<ExtractedRange>,
+    /// Merkle inclusion proofs of every extracted node in the `comm_d` of
+    /// the replica, range after range.
+    pub proofs: AuditProofs,
+}
+
+/// Node `i` of the data replicated for `seed`.
+///
+/// The top two bits are cleared, which keeps every node a valid field
+/// element.
+pub fn node(seed: &Seed, i: usize) -> [u8; 32] {
+    let mut hasher = Blake2b::new();
+    hasher.input(seed.seed.as_bytes());
+    hasher.input(&(i as u64).to_le_bytes());
+    let digest = hasher.result();
+
+    let mut node = [0u8; 32];
+    node.copy_from_slice(&digest[..32]);
+    node[31] &= 0b0011_1111;
+    node
+}
+
+/// Writes the `nodes` nodes of data replicated for `seed` to `file`, and
+/// maps it.
+pub fn seed_data(mut file: File, seed: &Seed, nodes: usize) -> Result<MmapMut, failure::Error> {
+    for i in 0..nodes {
+        file.write_all(&node(seed, i))?;
+    }
+
+    Ok(unsafe { MmapOptions::new().map_mut(&file)? })
+}
+
+/// The ranges to extract for challenge `id` from a replica of `nodes`
+/// nodes. They are derived from the key of the game, so they can't be known
+/// before the replica was accepted.
+pub fn ranges(key: &str, id: i32, nodes: usize) -> Vec<NodeRange> {
+    let len = std::cmp::min(RANGE_NODES, nodes);
+
+    (0..RANGE_COUNT)
+        .map(|j| {
+            let mut mac = Blake2b::new_varkey(key.as_bytes()).expect("any key size works");
+            mac.input(format!("extraction:{}:{}", id, j).as_bytes());
+            let code = mac.result().code();
+
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&code[..8]);
+            let start = u64::from_le_bytes(bytes) % (nodes - len + 1) as u64;
+
+            NodeRange {
+                start: start as usize,
+                len,
+            }
+        })
+        .collect()
+}
+
+/// The token to answer challenge `id` of `prover` with. Only the server can
+/// compute it, and it is only handed to whoever submitted the replica.
+pub fn token(key: &str, id: i32, prover: &str) -> String {
+    let mut mac = Blake2b::new_varkey(key.as_bytes()).expect("any key size works");
+    mac.input(format!("extraction-token:{}:{}", id, prover).as_bytes());
+    hex::encode(mac.result().code())
+}
+
+/// Checks that `token` was issued for challenge `id` of `prover`.
+pub fn check_token(key: &str, id: i32, prover: &str, token: &str) -> Result<(), failure::Error> {
+    let code = hex::decode(token)?;
+    let mut mac = Blake2b::new_varkey(key.as_bytes()).expect("any key size works");
+    mac.input(format!("extraction-token:{}:{}", id, prover).as_bytes());
+    mac.verify(&code)
+        .map_err(|_| format_err!("the token was not issued for extraction {}", id))
+}
+
+/// Checks that the extracted ranges are the issued ones, hold the seed data,
+/// and are part of the data committed to by `comm_d`.
+pub fn check(
+    extraction: &Extraction,
+    issued: &[NodeRange],
+    seed: &Seed,
+    comm_d: &Commitment,
+    hasher: HasherType,
+    size: usize,
+) -> Result<(), failure::Error> {
+    ensure!(
+        extraction.ranges.len() == issued.len(),
+        "expected {} ranges, got {}",
+        issued.len(),
+        extraction.ranges.len()
+    );
+
+    let mut nodes = Vec::new();
+    let mut data = Vec::new();
+
+    for (range, expected) in extraction.ranges.iter().zip(issued) {
+        ensure!(
+            range.start == expected.start && range.len == expected.len,
+            "range {}+{} was not issued",
+            range.start,
+            range.len
+        );
+
+        let bytes = hex::decode(&range.data)?;
+        let original: Vec<u8> = (range.start..range.start + range.len)
+            .flat_map(|i| node(seed, i).to_vec())
+            .collect();
+        if bytes != original {
+            return Err(format_err!(
+                "range {}+{} does not match the seed data",
+                range.start,
+                range.len
+            ));
+        }
+
+        nodes.extend(range.start..range.start + range.len);
+        data.extend(bytes);
+    }
+
+    let included =
+        schemes::verify_inclusion(comm_d, hasher, size, &nodes, &data, &extraction.proofs)?;
+    ensure!(included, "the ranges are not part of the committed data");
+
+    Ok(())
+}
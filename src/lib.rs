@@ -5,12 +5,24 @@ extern crate diesel;
 #[macro_use]
 extern crate diesel_migrations;
 
+pub mod assets;
+pub mod batch;
+pub mod bench;
+pub mod client;
+pub mod compact;
+pub mod config;
+pub mod encoding;
+pub mod error;
+pub mod extraction;
 pub mod models;
+pub mod presets;
 pub mod proofs;
+pub mod ratelimit;
+pub mod schemes;
+pub mod units;
+pub mod upload;
 
 mod db;
-mod error;
-mod gzip;
 mod routes;
 mod schema;
 
@@ -19,9 +31,16 @@ mod tests;
 
 use rocket::fairing::AdHoc;
 use rocket::{catchers, routes, Rocket};
-use rocket_contrib::serve::StaticFiles;
 
+use std::path::Path;
+
+use crate::assets::StaticAssets;
+use crate::config::GameConfig;
 use crate::db::DbConn;
+use crate::upload::Uploads;
+
+/// Directory the frontend is served from.
+pub const STATIC_DIR: &str = "./static";
 
 // This macro from `diesel_migrations` defines an `embedded_migrations` module
 // containing a function named `run`. This allows the example to be run and
@@ -30,8 +49,27 @@ embed_migrations!();
 
 pub fn rocket() -> (Rocket, Option<DbConn>) {
     let rocket = rocket::ignite()
+        .attach(AdHoc::on_attach(
+            "Game Config",
+            |rocket| match GameConfig::from_config(rocket.config()) {
+                Ok(config) => Ok(rocket.manage(config)),
+                Err(e) => {
+                    println!("Error: Invalid game configuration: {}", e);
+                    Err(rocket)
+                }
+            },
+        ))
+        .attach(AdHoc::on_attach("SNARK Parameters", |rocket| {
+            let dir = rocket
+                .state::<GameConfig>()
+                .map(|config| config.snark_params.clone())
+                .expect("game config is managed");
+            schemes::use_snark_parameters(Path::new(&dir));
+            Ok(rocket)
+        }))
         .attach(DbConn::fairing())
-        .attach(gzip::GzipFairing)
+        .attach(encoding::CompressionFairing)
+        .attach(ratelimit::RateLimitFairing)
         .attach(AdHoc::on_attach("Database Migrations", |rocket| {
             let conn = DbConn::get_one(&rocket).expect("database connection");
             match embedded_migrations::run(&*conn) {
@@ -42,18 +80,70 @@ pub fn rocket() -> (Rocket, Option<DbConn>) {
                 }
             }
         }))
-        .register(catchers![routes::catchers::not_found])
+        .attach(AdHoc::on_attach(
+            "Static Assets",
+            |rocket| match StaticAssets::load(STATIC_DIR) {
+                Ok(assets) => Ok(rocket.manage(assets)),
+                Err(e) => {
+                    println!("Error: Failed to load static assets: {}", e);
+                    Err(rocket)
+                }
+            },
+        ))
+        .attach(AdHoc::on_attach("Upload Sessions", |rocket| {
+            let dir = std::env::temp_dir().join("replication-game-uploads");
+            let config = rocket
+                .state::<GameConfig>()
+                .expect("game config is managed");
+            match Uploads::new(&dir, config) {
+                Ok(uploads) => {
+                    uploads.sweep();
+                    Ok(rocket.manage(uploads))
+                }
+                Err(e) => {
+                    println!("Error: Failed to create {}: {}", dir.display(), e);
+                    Err(rocket)
+                }
+            }
+        }))
+        .register(catchers![
+            routes::catchers::bad_request,
+            routes::catchers::unauthorized,
+            routes::catchers::not_found,
+            routes::catchers::conflict,
+            routes::catchers::payload_too_large,
+            routes::catchers::unsupported_media_type,
+            routes::catchers::unprocessable_entity,
+            routes::catchers::too_many_requests,
+            routes::catchers::internal_error
+        ])
         .mount(
             "/api",
             routes![
                 routes::index::index,
                 routes::seed::seed,
+                routes::seed::graph_seed,
                 routes::proof::proof,
-                routes::proof::proof_gz,
-                routes::leaderboard::leaderboard
+                routes::proof::proof_compressed,
+                routes::proof::proof_compact,
+                routes::proof::proof_batch,
+                routes::proof::verify,
+                routes::proof::verify_compressed,
+                routes::proof::verify_compact,
+                ratelimit::rate_limited,
+                routes::upload::create,
+                routes::upload::status,
+                routes::upload::chunk,
+                routes::upload::finalize,
+                routes::leaderboard::leaderboard,
+                routes::extraction::replica,
+                routes::extraction::extraction,
+                routes::extraction::leaderboard,
+                routes::audit::open,
+                routes::audit::answer
             ],
         )
-        .mount("/", StaticFiles::from("./static"));
+        .mount("/", routes![routes::assets::index, routes::assets::asset]);
 
     let conn = if cfg!(test) {
         DbConn::get_one(&rocket)
@@ -0,0 +1,185 @@
+use blake2::crypto_mac::Mac;
+use blake2::Blake2b;
+use diesel::{self, prelude::*};
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use storage_proofs::hasher::{Blake2sHasher, PedersenHasher, Sha256Hasher};
+use storage_proofs::merkle::MerkleProof;
+
+use crate::config::Audits;
+use crate::models::leaderboard::{Entry, Params};
+use crate::models::proof::{self, Commitment, HasherType};
+use crate::schema::{audits, params};
+
+/// A follow-up challenge of a replica that made it onto the leaderboard.
+#[table_name = "audits"]
+#[belongs_to(Params)]
+#[derive(Queryable, Debug, Clone, Deserialize, Serialize, Associations)]
+pub struct Audit {
+    pub id: i32,
+    pub prover: String,
+    pub params_id: i64,
+    /// The JSON encoded `comm_r` of the replica.
+    pub comm_r: String,
+    pub issued_at: i64,
+    pub deadline: i64,
+    /// `None` until the audit was answered or its deadline passed.
+    pub passed: Option<bool>,
+}
+
+/// An open audit, as sent to the prover.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditChallenge {
+    pub id: i32,
+    pub comm_r: Commitment,
+    pub hasher: HasherType,
+    /// The nodes of the replica to prove the inclusion of.
+    pub nodes: Vec<usize>,
+    pub deadline: i64,
+}
+
+/// Merkle inclusion proofs of the challenged nodes in the replica, in the
+/// order of the nodes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AuditProofs {
+    Pedersen(Vec<MerkleProof<PedersenHasher>>),
+    Blake2s(Vec<MerkleProof<Blake2sHasher>>),
+    Sha256(Vec<MerkleProof<Sha256Hasher>>),
+}
+
+impl Audit {
+    /// Schedules the audits of every replica of an accepted submission, at
+    /// random times within the audit window after `now`.
+    pub fn schedule(
+        res: &proof::Response,
+        params_id: i64,
+        config: &Audits,
+        now: u64,
+        conn: &PgConnection,
+    ) -> QueryResult<()> {
+        use crate::schema::audits::dsl;
+
+        let mut rng = thread_rng();
+        let mut rows = Vec::new();
+
+        for k in 0..res.proof_params.replicas {
+            let replica = res.replica(k).expect("the replica count was checked");
+            let comm_r = serde_json::to_string(&replica.tau.comm_r).expect("commitments serialize");
+
+            for _ in 0..config.count {
+                let issued_at = now + rng.gen_range(0, config.window);
+                rows.push((
+                    dsl::prover.eq(res.prover.clone()),
+                    dsl::params_id.eq(params_id),
+                    dsl::comm_r.eq(comm_r.clone()),
+                    dsl::issued_at.eq(issued_at as i64),
+                    dsl::deadline.eq((issued_at + config.deadline) as i64),
+                ));
+            }
+        }
+
+        diesel::insert_into(audits::table)
+            .values(&rows)
+            .execute(conn)?;
+
+        Ok(())
+    }
+
+    /// The audits of `prover` that were issued and can still be answered.
+    pub fn open(prover: &str, now: u64, conn: &PgConnection) -> QueryResult<Vec<(Audit, Params)>> {
+        audits::table
+            .inner_join(params::table)
+            .filter(audits::prover.eq(prover))
+            .filter(audits::passed.is_null())
+            .filter(audits::issued_at.le(now as i64))
+            .filter(audits::deadline.gt(now as i64))
+            .order(audits::deadline.asc())
+            .load(conn)
+    }
+
+    /// The audit with the given id, if it was issued by `now`.
+    pub fn find(id: i32, now: u64, conn: &PgConnection) -> QueryResult<Option<(Audit, Params)>> {
+        audits::table
+            .inner_join(params::table)
+            .filter(audits::id.eq(id))
+            .filter(audits::issued_at.le(now as i64))
+            .first(conn)
+            .optional()
+    }
+
+    pub fn pass(id: i32, conn: &PgConnection) -> QueryResult<()> {
+        diesel::update(audits::table.filter(audits::id.eq(id)))
+            .set(audits::passed.eq(Some(true)))
+            .execute(conn)?;
+
+        Ok(())
+    }
+
+    /// Drops the audits of `prover` with these params that were not answered
+    /// yet, as a better submission replaced the replicas they challenge.
+    pub fn cancel(prover: &str, params_id: i64, conn: &PgConnection) -> QueryResult<()> {
+        diesel::delete(
+            audits::table
+                .filter(audits::prover.eq(prover))
+                .filter(audits::params_id.eq(params_id))
+                .filter(audits::passed.is_null()),
+        )
+        .execute(conn)?;
+
+        Ok(())
+    }
+
+    /// Fails the audits whose deadline passed by `now`, and counts them
+    /// against the leaderboard entries.
+    pub fn expire(now: u64, conn: &PgConnection) -> QueryResult<()> {
+        conn.transaction(|| {
+            let missed: Vec<Audit> = diesel::update(
+                audits::table
+                    .filter(audits::passed.is_null())
+                    .filter(audits::deadline.le(now as i64)),
+            )
+            .set(audits::passed.eq(Some(false)))
+            .get_results(conn)?;
+
+            for audit in &missed {
+                Entry::miss_audit(&audit.prover, audit.params_id, conn)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    /// The challenged nodes, derived from the key of the game so they can't
+    /// be known before the audit is issued.
+    pub fn nodes(&self, key: &str, size: usize, count: usize) -> Vec<usize> {
+        let leaves = std::cmp::max(size / 32, 1) as u64;
+
+        (0..count)
+            .map(|j| {
+                let mut mac = Blake2b::new_varkey(key.as_bytes()).expect("any key size works");
+                mac.input(format!("audit:{}:{}:{}", self.id, self.comm_r, j).as_bytes());
+                let code = mac.result().code();
+
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(&code[..8]);
+                (u64::from_le_bytes(bytes) % leaves) as usize
+            })
+            .collect()
+    }
+
+    /// The audit as sent to the prover.
+    pub fn challenge(
+        &self,
+        params: &Params,
+        key: &str,
+        count: usize,
+    ) -> Result<AuditChallenge, failure::Error> {
+        Ok(AuditChallenge {
+            id: self.id,
+            comm_r: serde_json::from_str(&self.comm_r)?,
+            hasher: params.hasher,
+            nodes: self.nodes(key, params.size as usize, count),
+            deadline: self.deadline,
+        })
+    }
+}
@@ -0,0 +1,153 @@
+use diesel::{self, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::models::leaderboard::Params;
+use crate::models::proof;
+use crate::schema::{extraction_challenges, extractions, params};
+
+/// A replica accepted for the unsealing race, whose ranges were issued at
+/// `issued_at_ms`.
+#[table_name = "extraction_challenges"]
+#[belongs_to(Params)]
+#[derive(Queryable, Debug, Clone, Deserialize, Serialize, Associations)]
+pub struct ExtractionChallenge {
+    pub id: i32,
+    pub prover: String,
+    pub params_id: i64,
+    /// The JSON encoded seed of the replica.
+    pub seed: String,
+    /// The JSON encoded `comm_d` of the replica.
+    pub comm_d: String,
+    pub issued_at_ms: i64,
+    pub answered: bool,
+}
+
+impl ExtractionChallenge {
+    /// Records an accepted replica, and returns its challenge.
+    pub fn issue(
+        res: &proof::Response,
+        issued_at_ms: u64,
+        conn: &PgConnection,
+    ) -> QueryResult<ExtractionChallenge> {
+        use crate::schema::extraction_challenges::dsl;
+
+        let params_id = Params::insert(&res.proof_params, conn)?;
+
+        diesel::insert_into(extraction_challenges::table)
+            .values((
+                dsl::prover.eq(res.prover.as_str()),
+                dsl::params_id.eq(params_id),
+                dsl::seed.eq(serde_json::to_string(&res.seed).expect("seeds serialize")),
+                dsl::comm_d
+                    .eq(serde_json::to_string(&res.tau.comm_d).expect("commitments serialize")),
+                dsl::issued_at_ms.eq(issued_at_ms as i64),
+            ))
+            .get_result(conn)
+    }
+
+    pub fn find(
+        id: i32,
+        conn: &PgConnection,
+    ) -> QueryResult<Option<(ExtractionChallenge, Params)>> {
+        extraction_challenges::table
+            .inner_join(params::table)
+            .filter(extraction_challenges::id.eq(id))
+            .first(conn)
+            .optional()
+    }
+
+    /// Marks the challenge as answered, unless it already was. Returns
+    /// whether this call answered it.
+    pub fn answer(id: i32, conn: &PgConnection) -> QueryResult<bool> {
+        let updated = diesel::update(
+            extraction_challenges::table
+                .filter(extraction_challenges::id.eq(id))
+                .filter(extraction_challenges::answered.eq(false)),
+        )
+        .set(extraction_challenges::answered.eq(true))
+        .execute(conn)?;
+
+        Ok(updated == 1)
+    }
+}
+
+/// The fastest extraction of a prover with a set of params.
+#[table_name = "extractions"]
+#[belongs_to(Params)]
+#[derive(Queryable, Insertable, Debug, Clone, Deserialize, Serialize, Associations)]
+pub struct ExtractionEntry {
+    pub id: i32,
+    pub prover: String,
+    pub extraction_time_ms: i64,
+    pub params_id: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PrintableExtractionEntry {
+    pub id: i32,
+    pub prover: String,
+    pub extraction_time_ms: i64,
+    pub params: Params,
+}
+
+impl ExtractionEntry {
+    /// All entries, fastest first.
+    pub fn all(conn: &PgConnection) -> QueryResult<Vec<PrintableExtractionEntry>> {
+        let rows = extractions::table
+            .inner_join(params::table)
+            .order(extractions::extraction_time_ms.asc())
+            .load::<(ExtractionEntry, Params)>(conn)?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(e, p)| PrintableExtractionEntry {
+                id: e.id,
+                prover: e.prover,
+                extraction_time_ms: e.extraction_time_ms,
+                params: p,
+            })
+            .collect())
+    }
+
+    /// Records the extraction time of `prover`, if it beats their previous
+    /// one with the same params.
+    pub fn upsert(
+        prover: &str,
+        extraction_time_ms: i64,
+        params_id: i64,
+        conn: &PgConnection,
+    ) -> QueryResult<()> {
+        use crate::schema::extractions::dsl;
+
+        let previous = dsl::extractions
+            .filter(dsl::prover.eq(prover))
+            .filter(dsl::params_id.eq(params_id))
+            .select(dsl::extraction_time_ms)
+            .first::<i64>(conn)
+            .optional()?;
+
+        match previous {
+            Some(previous) if extraction_time_ms < previous => {
+                diesel::update(
+                    dsl::extractions
+                        .filter(dsl::prover.eq(prover))
+                        .filter(dsl::params_id.eq(params_id)),
+                )
+                .set(dsl::extraction_time_ms.eq(extraction_time_ms))
+                .execute(conn)?;
+            }
+            Some(_) => {}
+            None => {
+                diesel::insert_into(extractions::table)
+                    .values((
+                        dsl::prover.eq(prover),
+                        dsl::extraction_time_ms.eq(extraction_time_ms),
+                        dsl::params_id.eq(params_id),
+                    ))
+                    .execute(conn)?;
+            }
+        }
+
+        Ok(())
+    }
+}
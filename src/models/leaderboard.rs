@@ -1,3 +1,5 @@
+use std::cmp::Ordering;
+
 use blake2::{Blake2b, Digest};
 use byteorder::{BigEndian, ByteOrder};
 use diesel::{self, prelude::*};
@@ -14,6 +16,13 @@ pub struct Entry {
     pub prover: String,
     pub repl_time: i32,
     pub params_id: i64,
+    /// Follow-up audits of the prover's replicas with these params that were
+    /// not answered in time.
+    pub missed_audits: i32,
+    /// How long synthesizing and proving the circuits of the entry's SNARK
+    /// proof took, as reported by the prover.
+    pub synthesis_time_ms: Option<i64>,
+    pub proving_time_ms: Option<i64>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -22,46 +31,116 @@ pub struct PrintableEntry {
     pub prover: String,
     pub repl_time: i32,
     pub params: Params,
+    #[serde(default)]
+    pub missed_audits: i32,
+    /// Bytes sealed per second, over all replicas.
+    #[serde(default)]
+    pub throughput: f64,
+    /// Reported by the prover, for SNARK proofs only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub synthesis_time_ms: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proving_time_ms: Option<i64>,
 }
 
 impl Entry {
-    pub fn all(conn: &PgConnection) -> QueryResult<Vec<PrintableEntry>> {
-        let rows = leaderboard::table
+    /// All entries, fastest first, except that entries with missed audits
+    /// come after all others. Each hasher is a track of its own, which
+    /// `hasher` restricts the entries to, and `size` restricts them to
+    /// replicas of that many bytes. Entries of multiple replicas are ranked
+    /// among themselves by throughput instead.
+    pub fn all(
+        hasher: Option<proof::HasherType>,
+        size: Option<u64>,
+        conn: &PgConnection,
+    ) -> QueryResult<Vec<PrintableEntry>> {
+        let mut query = leaderboard::table
             .inner_join(params::table)
-            .order(leaderboard::repl_time.asc())
-            .load::<(Entry, Params)>(conn)?;
+            .order((
+                leaderboard::missed_audits.gt(0).asc(),
+                leaderboard::repl_time.asc(),
+            ))
+            .into_boxed();
+        if let Some(hasher) = hasher {
+            query = query.filter(params::hasher.eq(hasher));
+        }
+        if let Some(size) = size {
+            query = query.filter(params::size.eq(size as i64));
+        }
+        let rows = query.load::<(Entry, Params)>(conn)?;
 
-        Ok(rows
+        let mut entries: Vec<_> = rows
             .into_iter()
             .map(|(e, p)| PrintableEntry {
                 id: e.id,
                 prover: e.prover,
                 repl_time: e.repl_time,
+                throughput: throughput(&p, e.repl_time),
                 params: p,
+                missed_audits: e.missed_audits,
+                synthesis_time_ms: e.synthesis_time_ms,
+                proving_time_ms: e.proving_time_ms,
             })
-            .collect())
+            .collect();
+
+        // The multi-replica entries swap places with each other, the other
+        // entries keep the order of the query. Ties stay fastest first.
+        let slots: Vec<usize> = (0..entries.len())
+            .filter(|&i| entries[i].params.replicas > 1)
+            .collect();
+        let mut multi: Vec<_> = slots.iter().map(|&i| entries[i].clone()).collect();
+        multi.sort_by(|a, b| {
+            (a.missed_audits > 0).cmp(&(b.missed_audits > 0)).then(
+                b.throughput
+                    .partial_cmp(&a.throughput)
+                    .unwrap_or(Ordering::Equal),
+            )
+        });
+        for (i, entry) in slots.into_iter().zip(multi) {
+            entries[i] = entry;
+        }
+
+        Ok(entries)
     }
 
+    /// Records the replication time of `prover`, if it beats their previous
+    /// one with the same params, along with the SNARK times of the proof.
+    /// Returns whether it was recorded.
     pub fn insert(
         prover: &str,
         repl_time: i32,
         params_id: i64,
+        snark_times: Option<(i64, i64)>,
         conn: &PgConnection,
-    ) -> QueryResult<()> {
+    ) -> QueryResult<bool> {
         use crate::schema::leaderboard::dsl;
 
+        let synthesis_time_ms = snark_times.map(|(synthesis, _)| synthesis);
+        let proving_time_ms = snark_times.map(|(_, proving)| proving);
+
         let record = dsl::leaderboard
             .filter(dsl::prover.eq(prover))
             .filter(dsl::params_id.eq(params_id))
-            .first::<(i32, String, i32, i64)>(conn)
+            .select(dsl::repl_time)
+            .first::<i32>(conn)
             .optional()?;
 
-        if let Some(record) = record {
-            if repl_time < record.2 {
+        if let Some(previous) = record {
+            if repl_time < previous {
                 // better time
-                diesel::update(dsl::leaderboard.filter(dsl::prover.eq(prover)))
-                    .set((dsl::repl_time.eq(repl_time), dsl::params_id.eq(params_id)))
-                    .execute(conn)?;
+                diesel::update(
+                    dsl::leaderboard
+                        .filter(dsl::prover.eq(prover))
+                        .filter(dsl::params_id.eq(params_id)),
+                )
+                .set((
+                    dsl::repl_time.eq(repl_time),
+                    dsl::synthesis_time_ms.eq(synthesis_time_ms),
+                    dsl::proving_time_ms.eq(proving_time_ms),
+                ))
+                .execute(conn)?;
+            } else {
+                return Ok(false);
             }
         } else {
             // regular insert
@@ -70,10 +149,27 @@ impl Entry {
                     dsl::prover.eq(prover),
                     dsl::repl_time.eq(repl_time),
                     dsl::params_id.eq(params_id),
+                    dsl::synthesis_time_ms.eq(synthesis_time_ms),
+                    dsl::proving_time_ms.eq(proving_time_ms),
                 ))
                 .execute(conn)?;
         }
 
+        Ok(true)
+    }
+
+    /// Counts a missed audit against the entry of `prover` with these params.
+    pub fn miss_audit(prover: &str, params_id: i64, conn: &PgConnection) -> QueryResult<()> {
+        use crate::schema::leaderboard::dsl;
+
+        diesel::update(
+            dsl::leaderboard
+                .filter(dsl::prover.eq(prover))
+                .filter(dsl::params_id.eq(params_id)),
+        )
+        .set(dsl::missed_audits.eq(dsl::missed_audits + 1))
+        .execute(conn)?;
+
         Ok(())
     }
 }
@@ -93,13 +189,22 @@ pub struct Params {
     pub is_tapered: Option<bool>,
     pub taper_layers: Option<i32>,
     pub taper: Option<f64>,
+    pub hasher: proof::HasherType,
+    pub partitions: Option<i32>,
+    pub replicas: i32,
 }
 
 impl Params {
-    pub fn insert(val: &proof::Params, conn: &PgConnection) -> QueryResult<i64> {
+    /// The id of the params in the `params` table, derived from the params
+    /// themselves so clients can find their entries.
+    pub fn id(val: &proof::Params) -> i64 {
         let serialized_params = serde_json::to_vec(val).expect("invalid params");
         let hash = Blake2b::digest(&serialized_params);
-        let id = BigEndian::read_i64(&hash);
+        BigEndian::read_i64(&hash)
+    }
+
+    pub fn insert(val: &proof::Params, conn: &PgConnection) -> QueryResult<i64> {
+        let id = Params::id(val);
 
         use crate::schema::params::dsl;
 
@@ -120,6 +225,9 @@ impl Params {
                     is_tapered: val.zigzag.as_ref().map(|v| v.is_tapered),
                     taper_layers: val.zigzag.as_ref().map(|v| v.taper_layers as i32),
                     taper: val.zigzag.as_ref().map(|v| v.taper),
+                    hasher: val.hasher,
+                    partitions: val.zigzag.as_ref().map(|v| v.partitions as i32),
+                    replicas: val.replicas as i32,
                 })
                 .execute(conn)?;
         }
@@ -128,14 +236,30 @@ impl Params {
     }
 }
 
+/// Bytes sealed per second. Replication times are counted in whole seconds,
+/// so anything faster counts as one second.
+fn throughput(params: &Params, repl_time: i32) -> f64 {
+    let bytes = params.size as f64 * f64::from(params.replicas);
+    bytes / f64::from(std::cmp::max(repl_time, 1))
+}
+
+/// Records the replication time, and returns the id of the params and
+/// whether the time was recorded.
 pub fn upsert_entry_with_params(
     res: &proof::Response,
     repl_time: i32,
     conn: &PgConnection,
-) -> QueryResult<()> {
+) -> QueryResult<(i64, bool)> {
     let params_id = Params::insert(&res.proof_params, conn)?;
 
-    Entry::insert(&res.prover, repl_time, params_id, conn)?;
+    let snark_times = match res.proof {
+        proof::Proof::ZigzagSnark(ref snark) => {
+            Some((snark.synthesis_time_ms as i64, snark.proving_time_ms as i64))
+        }
+        _ => None,
+    };
+
+    let recorded = Entry::insert(&res.prover, repl_time, params_id, snark_times, conn)?;
 
-    Ok(())
+    Ok((params_id, recorded))
 }
@@ -1,3 +1,6 @@
+pub mod audit;
+pub mod extraction;
 pub mod leaderboard;
 pub mod proof;
 pub mod seed;
+pub mod submission;
@@ -1,30 +1,220 @@
+use std::fmt;
+
 use diesel_derive_enum::DbEnum;
+use failure::{ensure, format_err};
+use rocket::http::RawStr;
+use rocket::request::FromFormValue;
+use serde::de::{self, Deserializer, IgnoredAny, MapAccess, Visitor};
 use serde::{Deserialize, Serialize};
 use storage_proofs::hasher::pedersen::PedersenDomain;
-use storage_proofs::hasher::PedersenHasher;
+use storage_proofs::hasher::{Blake2sHasher, Domain, PedersenHasher, Sha256Hasher};
 use storage_proofs::layered_drgporep::LayerChallenges;
 use storage_proofs::{drgporep, layered_drgporep, porep};
 
+use crate::error::{ApiError, ApiResult, ErrorCode};
 use crate::models::seed::Seed;
+use crate::schemes;
+use crate::units;
+
+/// The format of the responses made by this version of the game. Bumped
+/// whenever the serialized proofs change, e.g. with a new storage-proofs.
+pub const FORMAT_VERSION: u32 = 1;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Response {
+    /// The format the proofs are serialized in. Responses from before the
+    /// format was versioned are of the first one.
+    #[serde(default = "first_format")]
+    pub format_version: u32,
     pub prover: String,
     pub seed: Seed,
     pub proof_params: Params,
     pub proof: Proof,
-    pub tau: porep::Tau<PedersenDomain>,
+    pub tau: porep::Tau<Commitment>,
     // only set for zigzag,
-    pub comm_r_star: Option<PedersenDomain>,
+    pub comm_r_star: Option<Commitment>,
+    /// The other replicas of a multi-replica proof, the first one is the
+    /// replica above.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub replicas: Vec<Replica>,
+}
+
+/// A response as submitted, read in a single pass. The format is dispatched
+/// on as soon as `format_version` is read, which the game writes first, so
+/// that proofs in a format this version of the game doesn't know are told
+/// apart from malformed ones, without holding them in memory.
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    pub format_version: u32,
+    /// The response, if it is in a format this version of the game decodes.
+    response: Option<Response>,
+}
+
+impl Envelope {
+    /// The response, if it is in a format this version of the game decodes.
+    pub fn open(self) -> ApiResult<Response> {
+        let format_version = self.format_version;
+        self.response.ok_or_else(|| {
+            ApiError::new(
+                ErrorCode::UnsupportedFormat,
+                format_err!("unsupported proof format v{}", format_version),
+            )
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(field_identifier, rename_all = "snake_case")]
+enum Field {
+    FormatVersion,
+    Prover,
+    Seed,
+    ProofParams,
+    Proof,
+    Tau,
+    CommRStar,
+    Replicas,
+    #[serde(other)]
+    Other,
+}
+
+impl<'de> Deserialize<'de> for Envelope {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Envelope, D::Error> {
+        deserializer.deserialize_map(EnvelopeVisitor)
+    }
+}
+
+struct EnvelopeVisitor;
+
+impl<'de> Visitor<'de> for EnvelopeVisitor {
+    type Value = Envelope;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a proof response")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Envelope, A::Error> {
+        let mut format_version = None;
+        let mut prover = None;
+        let mut seed = None;
+        let mut proof_params = None;
+        let mut proof = None;
+        let mut tau = None;
+        let mut comm_r_star = None;
+        let mut replicas = None;
+
+        // Fields before `format_version` are read as the first format, the
+        // one of responses from before the format was versioned.
+        while let Some(field) = map.next_key()? {
+            match field {
+                Field::FormatVersion => {
+                    let version = map.next_value()?;
+                    if version != 1 {
+                        while map.next_entry::<IgnoredAny, IgnoredAny>()?.is_some() {}
+                        return Ok(Envelope {
+                            format_version: version,
+                            response: None,
+                        });
+                    }
+                    format_version = Some(version);
+                }
+                Field::Prover => prover = Some(map.next_value()?),
+                Field::Seed => seed = Some(map.next_value()?),
+                Field::ProofParams => proof_params = Some(map.next_value()?),
+                Field::Proof => proof = Some(map.next_value()?),
+                Field::Tau => tau = Some(map.next_value()?),
+                Field::CommRStar => comm_r_star = Some(map.next_value()?),
+                Field::Replicas => replicas = Some(map.next_value()?),
+                Field::Other => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        let format_version = format_version.unwrap_or_else(first_format);
+        Ok(Envelope {
+            format_version,
+            response: Some(Response {
+                format_version,
+                prover: prover.ok_or_else(|| de::Error::missing_field("prover"))?,
+                seed: seed.ok_or_else(|| de::Error::missing_field("seed"))?,
+                proof_params: proof_params
+                    .ok_or_else(|| de::Error::missing_field("proof_params"))?,
+                proof: proof.ok_or_else(|| de::Error::missing_field("proof"))?,
+                tau: tau.ok_or_else(|| de::Error::missing_field("tau"))?,
+                comm_r_star: comm_r_star.unwrap_or(None),
+                replicas: replicas.unwrap_or_default(),
+            }),
+        })
+    }
+}
+
+/// The proof of one more replica of the same seed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replica {
+    pub proof: Proof,
+    pub tau: porep::Tau<Commitment>,
+    pub comm_r_star: Option<Commitment>,
+}
+
+/// A single replica of a response, borrowed from it, as schemes verify it.
+#[derive(Debug, Clone)]
+pub struct ReplicaRef<'a> {
+    pub format_version: u32,
+    pub prover: &'a str,
+    /// The seed of the replica.
+    pub seed: Seed,
+    pub proof_params: &'a Params,
+    pub proof: &'a Proof,
+    pub tau: &'a porep::Tau<Commitment>,
+    pub comm_r_star: Option<Commitment>,
+}
+
+impl Response {
+    /// Replica `k`, with the seed of the replica.
+    pub fn replica(&self, k: usize) -> Option<ReplicaRef> {
+        let (seed, proof, tau, comm_r_star) = if k == 0 {
+            (self.seed.clone(), &self.proof, &self.tau, &self.comm_r_star)
+        } else {
+            let replica = self.replicas.get(k - 1)?;
+            (
+                self.seed.replica(k),
+                &replica.proof,
+                &replica.tau,
+                &replica.comm_r_star,
+            )
+        };
+
+        Some(ReplicaRef {
+            format_version: self.format_version,
+            prover: &self.prover,
+            seed,
+            proof_params: &self.proof_params,
+            proof,
+            tau,
+            comm_r_star: comm_r_star.clone(),
+        })
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Params {
     pub typ: ProofType,
+    /// Left out when it is the default, so that params from before hashers
+    /// were selectable keep their identity.
+    #[serde(default, skip_serializing_if = "HasherType::is_default")]
+    pub hasher: HasherType,
+    /// In bytes. Also read from strings with a unit, like `"16GiB"`.
+    #[serde(deserialize_with = "units::deserialize_size")]
     pub size: usize,
     pub challenge_count: usize,
     pub vde: usize,
     pub degree: usize,
+    /// The number of replicas sealed from one seed. Left out when there is
+    /// just one, so that params from before the throughput track keep their
+    /// identity.
+    #[serde(default = "one", skip_serializing_if = "is_one")]
+    pub replicas: usize,
     pub zigzag: Option<ZigZagParams>,
 }
 
@@ -35,9 +225,78 @@ pub struct ZigZagParams {
     pub is_tapered: bool,
     pub taper_layers: usize,
     pub taper: f64,
+    /// The number of partition proofs. Left out when there is just one, so
+    /// that params from before partitions were selectable keep their
+    /// identity.
+    #[serde(default = "one", skip_serializing_if = "is_one")]
+    pub partitions: usize,
+}
+
+fn first_format() -> u32 {
+    1
 }
 
+fn one() -> usize {
+    1
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_one(val: &usize) -> bool {
+    *val == 1
+}
+
+/// The smallest number of nodes a replica may have.
+pub const MIN_NODES: usize = 4;
+
 impl Params {
+    /// Checks that the params describe a setup storage-proofs can work with,
+    /// and that they fit into the `params` table.
+    pub fn check(&self) -> Result<(), failure::Error> {
+        ensure!(
+            self.size % 32 == 0,
+            "size must be a multiple of 32 bytes, got {}",
+            self.size
+        );
+        let nodes = self.size / 32;
+        ensure!(
+            nodes >= MIN_NODES,
+            "size must be at least {} bytes, got {}",
+            MIN_NODES * 32,
+            self.size
+        );
+        ensure!(
+            self.size <= i64::max_value() as usize,
+            "size is too large: {}",
+            self.size
+        );
+        ensure!(
+            self.degree >= 1 && self.degree < nodes,
+            "degree must be between 1 and {}, got {}",
+            nodes - 1,
+            self.degree
+        );
+        ensure!(
+            self.challenge_count >= 1,
+            "challenge_count must be at least 1"
+        );
+        ensure!(self.replicas >= 1, "replicas must be at least 1");
+        ensure!(
+            fits_i32(self.challenge_count)
+                && fits_i32(self.vde)
+                && fits_i32(self.degree)
+                && fits_i32(self.replicas),
+            "challenge_count, vde, degree and replicas must fit into 32 bits"
+        );
+
+        schemes::get(&self.typ).check(self)
+    }
+
+    /// The number of partition proofs, one unless the zigzag params say
+    /// otherwise.
+    pub fn partitions(&self) -> usize {
+        self.zigzag.as_ref().map(|z| z.partitions).unwrap_or(1)
+    }
+
     pub fn as_zigzag_params(&self) -> Option<(usize, LayerChallenges)> {
         self.zigzag.as_ref().map(|zigzag| {
             let layer_challenges = if zigzag.is_tapered {
@@ -55,15 +314,179 @@ impl Params {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, DbEnum)]
+impl ZigZagParams {
+    pub(crate) fn check(&self) -> Result<(), failure::Error> {
+        ensure!(
+            self.expansion_degree >= 1,
+            "expansion_degree must be at least 1"
+        );
+        ensure!(self.layers >= 1, "layers must be at least 1");
+        ensure!(self.partitions >= 1, "partitions must be at least 1");
+        ensure!(
+            fits_i32(self.expansion_degree)
+                && fits_i32(self.layers)
+                && fits_i32(self.taper_layers)
+                && fits_i32(self.partitions),
+            "expansion_degree, layers, taper_layers and partitions must fit into 32 bits"
+        );
+        if self.is_tapered {
+            ensure!(
+                self.taper_layers <= self.layers,
+                "taper_layers ({}) must not exceed layers ({})",
+                self.taper_layers,
+                self.layers
+            );
+            ensure!(
+                self.taper.is_finite() && self.taper > 0.,
+                "taper must be a positive number, got {}",
+                self.taper
+            );
+        }
+        Ok(())
+    }
+}
+
+fn fits_i32(val: usize) -> bool {
+    val <= i32::max_value() as usize
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, DbEnum)]
 pub enum ProofType {
     Zigzag,
     #[db_rename = "drgporep"]
     DrgPoRep,
+    ZigzagSnark,
+}
+
+/// The hash function replicas are committed to with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, DbEnum)]
+pub enum HasherType {
+    Pedersen,
+    Blake2s,
+    Sha256,
+}
+
+impl Default for HasherType {
+    fn default() -> HasherType {
+        HasherType::Pedersen
+    }
+}
+
+impl HasherType {
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    pub fn is_default(&self) -> bool {
+        *self == HasherType::default()
+    }
+
+    /// The name used on the command line and in queries.
+    pub fn name(self) -> &'static str {
+        match self {
+            HasherType::Pedersen => "pedersen",
+            HasherType::Blake2s => "blake2s",
+            HasherType::Sha256 => "sha256",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<HasherType> {
+        [
+            HasherType::Pedersen,
+            HasherType::Blake2s,
+            HasherType::Sha256,
+        ]
+        .iter()
+        .cloned()
+        .find(|hasher| hasher.name() == name)
+    }
+}
+
+impl<'v> FromFormValue<'v> for HasherType {
+    type Error = &'v RawStr;
+
+    fn from_form_value(value: &'v RawStr) -> Result<HasherType, &'v RawStr> {
+        HasherType::from_name(value.as_str()).ok_or(value)
+    }
 }
 
+/// A commitment in the domain of any hasher.
+///
+/// Pedersen commitments are field elements and serialize as such, the others
+/// are plain bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Commitment {
+    Pedersen(PedersenDomain),
+    Bytes([u8; 32]),
+}
+
+impl Commitment {
+    pub fn new<D: Domain>(hasher: HasherType, domain: D) -> Commitment {
+        let bytes = domain.into_bytes();
+        match hasher {
+            HasherType::Pedersen => Commitment::Pedersen(
+                PedersenDomain::try_from_bytes(&bytes).expect("pedersen domain"),
+            ),
+            _ => {
+                let mut raw = [0u8; 32];
+                raw.copy_from_slice(&bytes);
+                Commitment::Bytes(raw)
+            }
+        }
+    }
+
+    /// Converts the commitment into the domain of a hasher.
+    pub fn domain<D: Domain>(&self) -> Result<D, failure::Error> {
+        let bytes = match self {
+            Commitment::Pedersen(domain) => domain.into_bytes(),
+            Commitment::Bytes(raw) => raw.to_vec(),
+        };
+
+        D::try_from_bytes(&bytes).map_err(|e| format_err!("invalid commitment: {}", e))
+    }
+}
+
+/// The proofs of each scheme. The unsuffixed variants use Pedersen hashes.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Proof {
     Zigzag(Vec<layered_drgporep::Proof<PedersenHasher>>),
     DrgPoRep(drgporep::Proof<PedersenHasher>),
+    ZigzagBlake2s(Vec<layered_drgporep::Proof<Blake2sHasher>>),
+    DrgPoRepBlake2s(drgporep::Proof<Blake2sHasher>),
+    ZigzagSha256(Vec<layered_drgporep::Proof<Sha256Hasher>>),
+    DrgPoRepSha256(drgporep::Proof<Sha256Hasher>),
+    ZigzagSnark(SnarkProof),
+}
+
+/// Groth16 proofs of the zigzag circuit, one per partition.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnarkProof {
+    /// The hex encoded proofs.
+    pub proofs: Vec<String>,
+    /// How long synthesizing the circuits took, as reported by the prover.
+    pub synthesis_time_ms: u64,
+    /// How long generating the proofs took, as reported by the prover.
+    pub proving_time_ms: u64,
+}
+
+impl Proof {
+    /// The type of the scheme that produced this proof.
+    pub fn typ(&self) -> ProofType {
+        match self {
+            Proof::Zigzag(_) | Proof::ZigzagBlake2s(_) | Proof::ZigzagSha256(_) => {
+                ProofType::Zigzag
+            }
+            Proof::DrgPoRep(_) | Proof::DrgPoRepBlake2s(_) | Proof::DrgPoRepSha256(_) => {
+                ProofType::DrgPoRep
+            }
+            Proof::ZigzagSnark(_) => ProofType::ZigzagSnark,
+        }
+    }
+
+    /// The hasher the proof was made with.
+    pub fn hasher(&self) -> HasherType {
+        match self {
+            Proof::Zigzag(_) | Proof::DrgPoRep(_) | Proof::ZigzagSnark(_) => HasherType::Pedersen,
+            Proof::ZigzagBlake2s(_) | Proof::DrgPoRepBlake2s(_) => HasherType::Blake2s,
+            Proof::ZigzagSha256(_) | Proof::DrgPoRepSha256(_) => HasherType::Sha256,
+        }
+    }
 }
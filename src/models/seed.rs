@@ -1,3 +1,4 @@
+use blake2::{Blake2b, Digest};
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -5,3 +6,34 @@ pub struct Seed {
     pub timestamp: i32,
     pub seed: String,
 }
+
+impl Seed {
+    /// The seed of replica `k` of a multi-replica proof. The first replica
+    /// uses the seed itself, the others are derived from it.
+    ///
+    /// The top two bits of derived ids are cleared, which makes them valid
+    /// replica ids for every hasher.
+    pub fn replica(&self, k: usize) -> Seed {
+        if k == 0 {
+            return self.clone();
+        }
+
+        let mut hasher = Blake2b::new();
+        hasher.input(self.seed.as_bytes());
+        hasher.input(&(k as u64).to_le_bytes());
+        let mut id = hasher.result()[..32].to_vec();
+        id[31] &= 0b0011_1111;
+
+        Seed {
+            timestamp: self.timestamp,
+            seed: hex::encode(id),
+        }
+    }
+}
+
+/// The seed the DRG graphs of the game are built from. Provers need the one
+/// of the server they play against, or their proofs don't verify.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GraphSeed {
+    pub graph_seed: [u32; 7],
+}
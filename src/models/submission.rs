@@ -0,0 +1,118 @@
+use blake2::{Blake2b, Digest};
+use diesel::{self, prelude::*};
+use failure::format_err;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{ApiError, ApiResult, ErrorCode};
+use crate::models::proof;
+use crate::schema::submissions;
+
+/// A proof that was submitted before, and its verdict.
+#[table_name = "submissions"]
+#[derive(Queryable, Insertable, Debug, Clone, Deserialize, Serialize)]
+pub struct Submission {
+    pub hash: String,
+    pub prover: String,
+    pub valid: bool,
+    pub code: Option<String>,
+    pub message: Option<String>,
+    /// Set when the proof was submitted by another prover first.
+    pub flagged: bool,
+    pub created_at: i64,
+}
+
+/// Hash of the canonical bytes of a response. The prover name is left out, so
+/// the same proof is recognized under any name.
+pub fn proof_hash(res: &proof::Response) -> String {
+    let canonical = if res.replicas.is_empty() {
+        serde_json::to_vec(&(
+            &res.seed,
+            &res.proof_params,
+            &res.proof,
+            &res.tau,
+            &res.comm_r_star,
+        ))
+    } else {
+        // Single replica proofs hash as they did before there were others.
+        serde_json::to_vec(&(
+            &res.seed,
+            &res.proof_params,
+            &res.proof,
+            &res.tau,
+            &res.comm_r_star,
+            &res.replicas,
+        ))
+    }
+    .expect("responses serialize");
+
+    hex::encode(Blake2b::digest(&canonical))
+}
+
+impl Submission {
+    pub fn new(
+        hash: &str,
+        prover: &str,
+        verdict: &ApiResult<()>,
+        flagged: bool,
+        created_at: u64,
+    ) -> Submission {
+        let (code, message) = match verdict {
+            Ok(()) => (None, None),
+            Err(err) => (Some(err.code().as_str().to_string()), Some(err.to_string())),
+        };
+
+        Submission {
+            hash: hash.to_string(),
+            prover: prover.to_string(),
+            valid: verdict.is_ok(),
+            code,
+            message,
+            flagged,
+            created_at: created_at as i64,
+        }
+    }
+
+    /// All earlier submissions of the proof with this hash, oldest first.
+    pub fn find(hash: &str, conn: &PgConnection) -> QueryResult<Vec<Submission>> {
+        use crate::schema::submissions::dsl;
+
+        dsl::submissions
+            .filter(dsl::hash.eq(hash))
+            .order(dsl::created_at.asc())
+            .load(conn)
+    }
+
+    pub fn flagged(conn: &PgConnection) -> QueryResult<Vec<Submission>> {
+        use crate::schema::submissions::dsl;
+
+        dsl::submissions
+            .filter(dsl::flagged.eq(true))
+            .order(dsl::created_at.asc())
+            .load(conn)
+    }
+
+    pub fn insert(&self, conn: &PgConnection) -> QueryResult<()> {
+        diesel::insert_into(submissions::table)
+            .values(self)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+
+        Ok(())
+    }
+
+    /// The verdict reached when the proof was first verified.
+    pub fn verdict(&self) -> ApiResult<()> {
+        if self.valid {
+            return Ok(());
+        }
+
+        let code = self
+            .code
+            .as_ref()
+            .and_then(|code| ErrorCode::parse(code))
+            .unwrap_or(ErrorCode::InvalidProof);
+        let message = self.message.as_ref().map(String::as_str).unwrap_or("");
+
+        Err(ApiError::new(code, format_err!("{}", message)))
+    }
+}
@@ -0,0 +1,76 @@
+//! The params of the official tracks, and params read from files.
+
+use std::fs;
+use std::path::Path;
+
+use failure::format_err;
+
+use crate::models::proof::{self, HasherType, ProofType};
+use crate::schemes;
+
+/// The official tracks, by name. All of them fit the default budget.
+const PRESETS: [(&str, ProofType, usize); 4] = [
+    ("zigzag-1gib", ProofType::Zigzag, 1 << 30),
+    ("zigzag-16gib", ProofType::Zigzag, 1 << 34),
+    ("drgporep-1gib", ProofType::DrgPoRep, 1 << 30),
+    ("drgporep-16gib", ProofType::DrgPoRep, 1 << 34),
+];
+
+/// The names of all presets.
+pub fn names() -> Vec<&'static str> {
+    PRESETS
+        .iter()
+        .map(|&(name, _, _)| name)
+        .chain(schemes::snark_tracks().into_iter().map(|(name, _)| name))
+        .collect()
+}
+
+/// The params of the preset with the given name. They are the defaults of
+/// the command line, so proofs made without a preset land on the same
+/// tracks. The zigzag-snark tracks are presets as well.
+pub fn get(name: &str) -> Option<proof::Params> {
+    if let Some((_, params)) = schemes::snark_tracks()
+        .into_iter()
+        .find(|&(track, _)| track == name)
+    {
+        return Some(params);
+    }
+
+    PRESETS
+        .iter()
+        .find(|&&(preset, _, _)| preset == name)
+        .map(|&(_, ref typ, size)| proof::Params {
+            typ: typ.clone(),
+            hasher: HasherType::Pedersen,
+            size,
+            challenge_count: 200,
+            vde: 0,
+            degree: 6,
+            replicas: 1,
+            zigzag: match typ {
+                ProofType::DrgPoRep => None,
+                _ => Some(proof::ZigZagParams {
+                    expansion_degree: 6,
+                    layers: 10,
+                    is_tapered: true,
+                    taper_layers: 7,
+                    taper: 1.0 / 3.0,
+                    partitions: 1,
+                }),
+            },
+        })
+}
+
+/// Reads params from a TOML file, or from a JSON file unless the name ends
+/// in `.toml`. Sizes may have units, like `size = "16GiB"`.
+pub fn load(path: &Path) -> Result<proof::Params, failure::Error> {
+    let raw = fs::read_to_string(path)
+        .map_err(|err| format_err!("failed to read {}: {}", path.display(), err))?;
+
+    let params = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml::from_str(&raw).map_err(failure::Error::from),
+        _ => serde_json::from_str(&raw).map_err(failure::Error::from),
+    };
+
+    params.map_err(|err| format_err!("invalid params in {}: {}", path.display(), err))
+}
@@ -1,189 +1,291 @@
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use failure::format_err;
 
 use memmap::MmapMut;
 use memmap::MmapOptions;
 use paired::bls12_381::Bls12;
 use rand::{thread_rng, Rng};
 
-use storage_proofs::drgporep::{self, *};
-use storage_proofs::drgraph::*;
 use storage_proofs::fr32::fr_into_bytes;
-use storage_proofs::hasher::{Domain, Hasher, PedersenHasher};
-use storage_proofs::layered_drgporep::{self, LayerChallenges};
-use storage_proofs::porep::PoRep;
-use storage_proofs::proof::ProofScheme;
-use storage_proofs::zigzag_drgporep::*;
+use storage_proofs::hasher::Domain;
 
+use crate::config::{GameConfig, DEFAULT_GRAPH_SEED};
+use crate::error::{ApiError, ApiResult, ErrorCode, ResultExt};
+use crate::extraction::{self, Challenge, Extraction};
+use crate::models::audit::{AuditChallenge, AuditProofs};
 use crate::models::proof;
 use crate::models::seed::Seed;
+use crate::schemes::{self, GameScheme};
 
-fn file_backed_mmap_from_random_bytes(rng: &mut impl Rng, n: usize) -> MmapMut {
-    let mut tmpfile: File = tempfile::tempfile().unwrap();
+pub(crate) fn file_backed_mmap_from_random_bytes(rng: &mut impl Rng, n: usize) -> MmapMut {
+    let tmpfile: File = tempfile::tempfile().unwrap();
+    mmap_from_random_bytes(tmpfile, rng, n)
+}
 
+fn mmap_from_random_bytes(mut file: File, rng: &mut impl Rng, n: usize) -> MmapMut {
     for _ in 0..n {
-        tmpfile
-            .write_all(&fr_into_bytes::<Bls12>(&rng.gen()))
-            .unwrap();
+        file.write_all(&fr_into_bytes::<Bls12>(&rng.gen())).unwrap();
     }
 
-    unsafe { MmapOptions::new().map_mut(&tmpfile).unwrap() }
+    unsafe { MmapOptions::new().map_mut(&file).unwrap() }
 }
 
-pub fn zigzag_work(prover: String, params: proof::Params, seed: Seed) -> String {
-    let replica_id = id_from_str::<<PedersenHasher as Hasher>::Domain>(&seed.seed);
-
-    let data_size = params.size;
-    let m = params.degree;
-    let challenge_count = params.challenge_count;
-    let sloth_iter = params.vde;
-
-    let (expansion_degree, layer_challenges) = params
-        .as_zigzag_params()
-        .unwrap_or_else(|| (6, LayerChallenges::new_fixed(10, challenge_count)));
+/// `elapsed` in whole milliseconds.
+pub(crate) fn millis(elapsed: Duration) -> u64 {
+    elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis())
+}
 
-    let partitions = 1;
+/// Replicates and proves random data with the scheme of `params.typ`, once
+/// for every replica, on the graphs of `graph_seed`.
+pub fn work(
+    prover: String,
+    params: proof::Params,
+    seed: Seed,
+    graph_seed: [u32; 7],
+) -> proof::Response {
+    let scheme = schemes::get(&params.typ);
+
+    // Sealed one after another, a prover after throughput would seal them in
+    // parallel.
+    combine((0..params.replicas).map(|k| {
+        eprintln!("sealing replica {} of {}", k + 1, params.replicas);
+        scheme
+            .prove(prover.clone(), params.clone(), seed.replica(k), graph_seed)
+            .expect("failed to prove")
+    }))
+}
 
-    let mut rng = thread_rng();
+/// The response of the first replica, with the others appended to it.
+fn combine(mut replicas: impl Iterator<Item = proof::Response>) -> proof::Response {
+    let mut res = replicas.next().expect("at least one replica");
+    res.replicas = replicas
+        .map(|replica| proof::Replica {
+            proof: replica.proof,
+            tau: replica.tau,
+            comm_r_star: replica.comm_r_star,
+        })
+        .collect();
 
-    eprintln!("generating fake data");
+    res
+}
 
-    let nodes = data_size / 32;
-    let mut data = file_backed_mmap_from_random_bytes(&mut rng, nodes);
+/// Replicates and proves random data like `work`, keeping the replica in the
+/// file at `replica` to answer audits with. Replica `k` of multi-replica
+/// params is kept next to it, in `replica` with `.k` appended.
+pub fn seal_work(
+    prover: String,
+    params: proof::Params,
+    seed: Seed,
+    graph_seed: [u32; 7],
+    replica: &Path,
+) -> proof::Response {
+    combine((0..params.replicas).map(|k| {
+        eprintln!("sealing replica {} of {}", k + 1, params.replicas);
+        let data = mmap_from_random_bytes(
+            create(&replica_path(replica, k)),
+            &mut thread_rng(),
+            params.size / 32,
+        );
+        seal_into(
+            prover.clone(),
+            params.clone(),
+            seed.replica(k),
+            graph_seed,
+            data,
+        )
+    }))
+}
 
-    let sp = layered_drgporep::SetupParams {
-        drg: drgporep::DrgParams {
-            nodes,
-            degree: m,
-            expansion_degree,
-            // TODO: where should this come from?
-            seed: [0u32; 7],
-        },
-        sloth_iter,
-        layer_challenges,
-    };
+/// The file replica `k` is kept in by `seal_work`.
+pub fn replica_path(replica: &Path, k: usize) -> PathBuf {
+    if k == 0 {
+        return replica.to_path_buf();
+    }
 
-    eprintln!("running setup");
-    let pp = ZigZagDrgPoRep::<PedersenHasher>::setup(&sp).unwrap();
+    let mut name = replica.as_os_str().to_os_string();
+    name.push(format!(".{}", k));
+    PathBuf::from(name)
+}
 
-    eprintln!("running replicate");
+/// Replicates and proves the data of `seed` for the unsealing race, keeping
+/// the replica in the file at `replica` to extract from once the ranges are
+/// issued.
+pub fn seed_seal_work(
+    prover: String,
+    params: proof::Params,
+    seed: Seed,
+    graph_seed: [u32; 7],
+    replica: &Path,
+) -> proof::Response {
+    assert_eq!(params.replicas, 1, "only single replicas can be unsealed");
+
+    let data = extraction::seed_data(create(replica), &seed, params.size / 32)
+        .expect("failed to write the seed data");
+    seal_into(prover, params, seed, graph_seed, data)
+}
 
-    let (tau, aux) =
-        ZigZagDrgPoRep::<PedersenHasher>::replicate(&pp, &replica_id, &mut data, None).unwrap();
+/// Creates the file a replica is kept in.
+fn create(replica: &Path) -> File {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(replica)
+        .expect("failed to create the replica file")
+}
 
-    let pub_inputs = layered_drgporep::PublicInputs::<<PedersenHasher as Hasher>::Domain> {
-        replica_id,
-        tau: Some(tau.simplify()),
-        comm_r_star: tau.comm_r_star,
-        k: Some(0),
-    };
+fn seal_into(
+    prover: String,
+    params: proof::Params,
+    seed: Seed,
+    graph_seed: [u32; 7],
+    mut data: MmapMut,
+) -> proof::Response {
+    let res = schemes::get(&params.typ)
+        .seal(prover, params, seed, &mut data, graph_seed)
+        .expect("failed to prove");
+    data.flush().expect("failed to write the replica");
 
-    let priv_inputs = layered_drgporep::PrivateInputs {
-        aux,
-        tau: tau.layer_taus.clone(),
-    };
+    res
+}
 
-    eprintln!("generating one proof");
+/// Answers an audit from the replica kept in the file at `replica`.
+pub fn audit_work(challenge: &AuditChallenge, replica: &Path) -> AuditProofs {
+    let file = File::open(replica).expect("failed to open the replica file");
+    let data = unsafe { MmapOptions::new().map(&file) }.expect("failed to map the replica file");
 
-    let pr = ZigZagDrgPoRep::<PedersenHasher>::prove_all_partitions(
-        &pp,
-        &pub_inputs,
-        &priv_inputs,
-        partitions,
-    )
-    .expect("failed to prove");
+    schemes::audit_proofs(&data, challenge.hasher, &challenge.nodes).expect("failed to prove")
+}
 
-    let verified = ZigZagDrgPoRep::<PedersenHasher>::verify_all_partitions(&pp, &pub_inputs, &pr)
-        .expect("failed to verify");
+/// Extracts the ranges of `challenge` from the replica of `res`, kept by
+/// `seed_seal_work` in the file at `replica`.
+pub fn extraction_work(
+    res: &proof::Response,
+    challenge: &Challenge,
+    graph_seed: [u32; 7],
+    replica: &Path,
+) -> Extraction {
+    let file = File::open(replica).expect("failed to open the replica file");
+    let data = unsafe { MmapOptions::new().map(&file) }.expect("failed to map the replica file");
+
+    schemes::get(&res.proof_params.typ)
+        .unseal(
+            &res.proof_params,
+            &res.seed,
+            &data,
+            &challenge.ranges,
+            graph_seed,
+        )
+        .expect("failed to unseal")
+}
 
-    assert!(verified, "verification failed");
+/// A zigzag proof on the graphs of the default graph seed.
+pub fn zigzag_work(prover: String, params: proof::Params, seed: Seed) -> proof::Response {
+    schemes::ZigZagScheme
+        .prove(prover, params, seed, DEFAULT_GRAPH_SEED)
+        .expect("failed to prove")
+}
 
-    serde_json::to_string(&proof::Response {
-        prover,
-        seed,
-        proof_params: params,
-        proof: proof::Proof::Zigzag(pr),
-        comm_r_star: Some(tau.comm_r_star),
-        tau: tau.simplify(),
-    })
-    .expect("failed to serialize")
+/// A DRG PoRep proof on the graphs of the default graph seed.
+pub fn porep_work(prover: String, params: proof::Params, seed: Seed) -> proof::Response {
+    schemes::DrgPoRepScheme
+        .prove(prover, params, seed, DEFAULT_GRAPH_SEED)
+        .expect("failed to prove")
 }
 
-pub fn id_from_str<T: Domain>(raw: &str) -> T {
-    let replica_id_raw = hex::decode(raw).expect("invalid hex for replica id seed");
+pub fn id_from_str<T: Domain>(raw: &str) -> Result<T, failure::Error> {
+    let replica_id_raw = hex::decode(raw)?;
     let mut replica_id_bytes = vec![0u8; 32];
     let len = ::std::cmp::min(32, replica_id_raw.len());
     replica_id_bytes[..len].copy_from_slice(&replica_id_raw[..len]);
-    T::try_from_bytes(&replica_id_bytes).expect("invalid replica id")
+    let id = T::try_from_bytes(&replica_id_bytes)?;
+    Ok(id)
 }
 
-pub fn porep_work(prover: String, params: proof::Params, seed: Seed) -> String {
-    let replica_id = id_from_str::<<PedersenHasher as Hasher>::Domain>(&seed.seed);
-
-    let data_size = params.size;
-    let m = params.degree;
-    let sloth_iter = params.vde;
-    let challenge_count = params.challenge_count;
+/// Verifies a submitted proof against its params and seed.
+///
+/// This never panics: malformed params, seeds and proofs, as well as any
+/// panic inside storage-proofs, are turned into an error.
+pub fn validate(res: &proof::Response, config: &GameConfig) -> ApiResult<()> {
+    // Checked first, params and seeds are only understood in known formats.
+    schemes::verifier(&res.proof, res.format_version).with_code(ErrorCode::UnsupportedFormat)?;
+
+    res.proof_params
+        .check()
+        .with_code(ErrorCode::ParamsNotAllowed)?;
+    config
+        .check_params(&res.proof_params)
+        .with_code(ErrorCode::ParamsNotAllowed)?;
+
+    schemes::check_replica_id(res.proof_params.hasher, &res.seed.seed)
+        .with_code(ErrorCode::InvalidSeed)?;
+
+    let replicas = res.proof_params.replicas;
+    if res.replicas.len() + 1 != replicas {
+        return Err(ApiError::new(
+            ErrorCode::InvalidProof,
+            format_err!(
+                "Expected {} replicas, got {}",
+                replicas,
+                res.replicas.len() + 1
+            ),
+        ));
+    }
 
-    let mut rng = thread_rng();
+    for k in 0..replicas {
+        let replica = res.replica(k).expect("the replica count was checked");
+        verify_replica(&replica, config)?;
+    }
 
-    eprintln!("generating fake data");
+    Ok(())
+}
 
-    let nodes = data_size / 32;
+/// Verifies the proof of a single replica.
+fn verify_replica(res: &proof::ReplicaRef, config: &GameConfig) -> ApiResult<()> {
+    if res.proof.hasher() != res.proof_params.hasher {
+        return Err(ApiError::new(
+            ErrorCode::InvalidProof,
+            format_err!(
+                "The proof was made with {}, not {}",
+                res.proof.hasher().name(),
+                res.proof_params.hasher.name()
+            ),
+        ));
+    }
 
-    let mut data: Vec<u8> = (0..nodes)
-        .flat_map(|_| fr_into_bytes::<Bls12>(&rng.gen()))
-        .collect();
+    // The params pick the track and the rules the proof is checked against,
+    // so they have to be those of the scheme that verifies it.
+    if res.proof.typ() != res.proof_params.typ {
+        return Err(ApiError::new(
+            ErrorCode::InvalidProof,
+            format_err!(
+                "The proof is a {} proof, not {}",
+                schemes::for_proof(&res.proof).name(),
+                schemes::get(&res.proof_params.typ).name()
+            ),
+        ));
+    }
 
-    // TODO: proper challenge generation
-    let challenges = vec![2; challenge_count];
-
-    let sp = SetupParams {
-        drg: DrgParams {
-            nodes,
-            degree: m,
-            expansion_degree: 0,
-            // TODO: where should this come from?
-            seed: [0u32; 7],
-        },
-        challenges_count: challenge_count,
-        private: false,
-        sloth_iter,
-    };
-
-    eprintln!("running setup");
-    let pp = DrgPoRep::<PedersenHasher, BucketGraph<PedersenHasher>>::setup(&sp).unwrap();
-
-    eprintln!("running replicate");
-    let (tau, aux) =
-        DrgPoRep::<PedersenHasher, _>::replicate(&pp, &replica_id, data.as_mut_slice(), None)
-            .unwrap();
-
-    let pub_inputs = PublicInputs {
-        replica_id: Some(replica_id),
-        challenges,
-        tau: Some(tau),
-    };
-
-    let priv_inputs = PrivateInputs::<PedersenHasher> {
-        tree_d: &aux.tree_d,
-        tree_r: &aux.tree_r,
-    };
-
-    eprintln!("sampling proving & verifying");
-
-    let pr = DrgPoRep::<PedersenHasher, _>::prove(&pp, &pub_inputs, &priv_inputs)
-        .expect("failed to prove");
+    // The proof decides which scheme verifies it, the params only describe
+    // the setup.
+    let valid = panic::catch_unwind(AssertUnwindSafe(|| {
+        schemes::verifier(&res.proof, res.format_version)?.verify(res, config.graph_seed)
+    }))
+    .unwrap_or_else(|_| Err(format_err!("verification aborted on malformed proof")))
+    .with_code(ErrorCode::InvalidProof)?;
+
+    if !valid {
+        return Err(ApiError::new(
+            ErrorCode::InvalidProof,
+            format_err!("Submitted proofs are invalid"),
+        ));
+    }
 
-    DrgPoRep::<PedersenHasher, _>::verify(&pp, &pub_inputs, &pr).expect("failed to verify");
-
-    serde_json::to_string(&proof::Response {
-        prover,
-        seed,
-        proof_params: params,
-        proof: proof::Proof::DrgPoRep(pr),
-        comm_r_star: None,
-        tau,
-    })
-    .expect("failed to serialize")
+    Ok(())
 }
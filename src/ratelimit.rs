@@ -0,0 +1,526 @@
+//! Token bucket rate limiting, configured per route.
+//!
+//! Every key, a client address or a prover name, gets a bucket of `burst`
+//! tokens that refills at a steady rate. A request takes one token, and is
+//! refused with `429 Too Many Requests` while the bucket is empty.
+//!
+//! Rules are read from the `rate_limits` table of `Rocket.toml`:
+//!
+//! ```toml
+//! [global.rate_limits]
+//! backend = "memory"  # or "postgres", to share buckets between instances
+//!
+//! [global.rate_limits.routes.proof]
+//! route = "POST /api/proof"
+//! ip = { per_minute = 30, burst = 10 }
+//! prover = { per_minute = 10, burst = 5 }
+//! ```
+//!
+//! Behind a reverse proxy, the client address is taken from the header the
+//! proxy sets instead of the peer of the connection. For `X-Forwarded-For`,
+//! `hops` is the number of proxies that append to it, and the address is the
+//! one appended by the outermost of them; anything before it was sent by the
+//! client:
+//!
+//! ```toml
+//! [global.rate_limits]
+//! proxy = { header = "X-Forwarded-For", hops = 1 }
+//! ```
+//!
+//! Limits by address are enforced by `RateLimitFairing` before a request
+//! reaches its handler. The prover name is only known once the body is
+//! decoded, so handlers check it with the `ProverLimit` guard.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use diesel::prelude::*;
+use diesel::sql_types::{Bool, Double, Text};
+use failure::format_err;
+use rocket::config::{Config, Value};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::{Method, Status};
+use rocket::request::{self, FromRequest};
+use rocket::{get, Data, Outcome, Request, Rocket, State};
+use serde::Deserialize;
+
+use crate::db::DbConn;
+use crate::error::{ApiError, ApiResult, ErrorCode};
+
+/// Above this many buckets, full ones are forgotten.
+const MAX_BUCKETS: usize = 10_000;
+
+/// Where rate limited requests are sent instead of their handler.
+const LIMITED_PATH: &str = "/api/rate-limited";
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct Rate {
+    pub per_minute: u32,
+    pub burst: u32,
+}
+
+impl Rate {
+    /// Tokens added per second.
+    fn per_sec(self) -> f64 {
+        f64::from(self.per_minute) / 60.
+    }
+
+    fn burst(self) -> f64 {
+        f64::from(self.burst.max(1))
+    }
+
+    /// How long until a bucket holding `tokens` has a whole token.
+    fn wait(self, tokens: f64) -> Duration {
+        if self.per_minute == 0 {
+            return Duration::from_secs(u64::from(u32::max_value()));
+        }
+
+        let secs = (1. - tokens).max(0.) / self.per_sec();
+        Duration::from_millis((secs * 1000.).ceil() as u64)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+    /// The rate of the rule the bucket belongs to.
+    rate: Rate,
+}
+
+/// In-memory buckets.
+#[derive(Debug, Default)]
+pub struct Limiter {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl Limiter {
+    fn refill(bucket: &mut Bucket, now: Instant) {
+        let elapsed = now.duration_since(bucket.last);
+        let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) * 1e-9;
+        bucket.tokens = (bucket.tokens + elapsed * bucket.rate.per_sec()).min(bucket.rate.burst());
+        bucket.last = now;
+    }
+
+    /// Takes a token from the bucket of `key`, or returns how long to wait
+    /// until one is available.
+    pub fn check(&self, key: &str, rate: Rate) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() >= MAX_BUCKETS {
+            buckets.retain(|_, bucket| {
+                Limiter::refill(bucket, now);
+                bucket.tokens < bucket.rate.burst()
+            });
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert_with(|| Bucket {
+            tokens: rate.burst(),
+            last: now,
+            rate,
+        });
+        bucket.rate = rate;
+        Limiter::refill(bucket, now);
+
+        if bucket.tokens >= 1. {
+            bucket.tokens -= 1.;
+            Ok(())
+        } else {
+            Err(rate.wait(bucket.tokens))
+        }
+    }
+}
+
+#[derive(QueryableByName)]
+struct Taken {
+    #[sql_type = "Double"]
+    tokens: f64,
+    #[sql_type = "Bool"]
+    taken: bool,
+}
+
+/// Takes a token from the bucket of `key` stored in Postgres, so that all
+/// instances share it.
+fn check_postgres(conn: &PgConnection, key: &str, rate: Rate) -> QueryResult<Result<(), Duration>> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards");
+    let now = now.as_secs() as f64 + f64::from(now.subsec_nanos()) * 1e-9;
+
+    let row = diesel::sql_query(
+        "INSERT INTO rate_limits (key, tokens, updated_at, taken) VALUES ($1, $2 - 1, $3, TRUE)
+         ON CONFLICT (key) DO UPDATE SET
+           tokens = CASE
+             WHEN LEAST($2, rate_limits.tokens + ($3 - rate_limits.updated_at) * $4) >= 1
+             THEN LEAST($2, rate_limits.tokens + ($3 - rate_limits.updated_at) * $4) - 1
+             ELSE LEAST($2, rate_limits.tokens + ($3 - rate_limits.updated_at) * $4)
+           END,
+           taken = LEAST($2, rate_limits.tokens + ($3 - rate_limits.updated_at) * $4) >= 1,
+           updated_at = $3
+         RETURNING tokens, taken",
+    )
+    .bind::<Text, _>(key)
+    .bind::<Double, _>(rate.burst())
+    .bind::<Double, _>(now)
+    .bind::<Double, _>(rate.per_sec())
+    .get_result::<Taken>(conn)?;
+
+    if row.taken {
+        Ok(Ok(()))
+    } else {
+        Ok(Err(rate.wait(row.tokens)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Memory,
+    Postgres,
+}
+
+impl Default for Backend {
+    fn default() -> Backend {
+        Backend::Memory
+    }
+}
+
+/// The header a reverse proxy puts the client address in.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Proxy {
+    pub header: String,
+    /// The number of proxies appending to `X-Forwarded-For`.
+    #[serde(default = "default_hops")]
+    pub hops: usize,
+}
+
+fn default_hops() -> usize {
+    1
+}
+
+#[derive(Debug, Deserialize)]
+struct RuleConfig {
+    route: String,
+    ip: Option<Rate>,
+    prover: Option<Rate>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RateLimitsConfig {
+    #[serde(default)]
+    backend: Backend,
+    #[serde(default)]
+    routes: HashMap<String, RuleConfig>,
+    proxy: Option<Proxy>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub name: String,
+    pub method: Method,
+    /// The path the rule applies to, or all paths below it when it ends in
+    /// `*`.
+    pub path: String,
+    pub ip: Option<Rate>,
+    pub prover: Option<Rate>,
+}
+
+impl Rule {
+    fn parse(name: &str, config: RuleConfig) -> Result<Rule, failure::Error> {
+        let mut parts = config.route.split_whitespace();
+        let (method, path) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(path), None) => (method, path),
+            _ => {
+                return Err(format_err!(
+                    "route of `{}` must look like \"POST /api/proof\", not \"{}\"",
+                    name,
+                    config.route
+                ))
+            }
+        };
+
+        let method = method
+            .parse::<Method>()
+            .map_err(|_| format_err!("unknown method `{}` in route of `{}`", method, name))?;
+
+        for rate in config.ip.iter().chain(config.prover.iter()) {
+            if rate.burst == 0 {
+                return Err(format_err!("burst of `{}` must be at least 1", name));
+            }
+        }
+
+        Ok(Rule {
+            name: name.to_string(),
+            method,
+            path: path.to_string(),
+            ip: config.ip,
+            prover: config.prover,
+        })
+    }
+
+    fn matches(&self, method: Method, path: &str) -> bool {
+        if method != self.method {
+            return false;
+        }
+
+        if self.path.ends_with('*') {
+            path.starts_with(&self.path[..self.path.len() - 1])
+        } else {
+            path == self.path
+        }
+    }
+}
+
+/// The configured rules and their buckets.
+#[derive(Debug)]
+pub struct RateLimits {
+    backend: Backend,
+    rules: Vec<Rule>,
+    proxy: Option<Proxy>,
+    memory: Limiter,
+}
+
+impl RateLimits {
+    pub fn new(backend: Backend, rules: Vec<Rule>, proxy: Option<Proxy>) -> RateLimits {
+        RateLimits {
+            backend,
+            rules,
+            proxy,
+            memory: Limiter::default(),
+        }
+    }
+
+    /// Reads the `rate_limits` table of the configuration. Without one, no
+    /// route is limited.
+    pub fn from_config(config: &Config) -> Result<RateLimits, failure::Error> {
+        let table = match config.get_table("rate_limits") {
+            Ok(table) => table.clone(),
+            Err(_) => return Ok(RateLimits::new(Backend::Memory, Vec::new(), None)),
+        };
+
+        let parsed: RateLimitsConfig = Value::Table(table)
+            .try_into()
+            .map_err(|e| format_err!("invalid rate_limits: {}", e))?;
+
+        let mut rules = parsed
+            .routes
+            .into_iter()
+            .map(|(name, rule)| Rule::parse(&name, rule))
+            .collect::<Result<Vec<_>, _>>()?;
+        rules.sort_by(|a, b| a.name.cmp(&b.name));
+
+        if let Some(ref proxy) = parsed.proxy {
+            if proxy.hops == 0 {
+                return Err(format_err!("hops of the proxy must be at least 1"));
+            }
+        }
+
+        Ok(RateLimits::new(parsed.backend, rules, parsed.proxy))
+    }
+
+    pub fn rule(&self, name: &str) -> Option<&Rule> {
+        self.rules.iter().find(|rule| rule.name == name)
+    }
+
+    fn rule_for(&self, method: Method, path: &str) -> Option<&Rule> {
+        self.rules.iter().find(|rule| rule.matches(method, path))
+    }
+
+    /// Takes a token from the bucket of `key`, or returns how long to wait
+    /// until one is available.
+    fn take(
+        &self,
+        key: &str,
+        rate: Rate,
+        conn: Option<&PgConnection>,
+    ) -> Result<Result<(), Duration>, failure::Error> {
+        match (self.backend, conn) {
+            (Backend::Postgres, Some(conn)) => Ok(check_postgres(conn, key, rate)?),
+            (Backend::Postgres, None) => Err(format_err!("no database connection")),
+            (Backend::Memory, _) => Ok(self.memory.check(key, rate)),
+        }
+    }
+
+    fn check_ip(&self, r: &Request) -> Result<Result<(), Duration>, failure::Error> {
+        let rule = match self.rule_for(r.method(), r.uri().path()) {
+            Some(rule) => rule,
+            None => return Ok(Ok(())),
+        };
+
+        let rate = match rule.ip {
+            Some(rate) => rate,
+            None => return Ok(Ok(())),
+        };
+
+        let key = format!("{}:ip:{}", rule.name, self.client_key(r));
+        match self.backend {
+            Backend::Postgres => {
+                let conn = r.guard::<DbConn>().succeeded();
+                self.take(&key, rate, conn.as_ref().map(|c| &**c))
+            }
+            Backend::Memory => self.take(&key, rate, None),
+        }
+    }
+
+    /// The key of a request: the client address, when known. Headers are
+    /// only trusted when a proxy is configured, as clients can send any.
+    pub fn client_key(&self, r: &Request) -> String {
+        let forwarded = self.proxy.as_ref().and_then(|proxy| {
+            let values: Vec<_> = r.headers().get(&proxy.header).collect();
+            forwarded_address(&values.join(","), proxy.hops)
+        });
+
+        forwarded
+            .or_else(|| r.remote().map(|addr| addr.ip()))
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|| "unknown".into())
+    }
+}
+
+/// The client address in a comma separated list of addresses, each appended
+/// by one of `hops` proxies, the last by the nearest.
+pub fn forwarded_address(value: &str, hops: usize) -> Option<IpAddr> {
+    let addresses: Vec<_> = value
+        .split(',')
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .collect();
+    let i = addresses.len().checked_sub(hops)?;
+
+    addresses[i].parse().ok()
+}
+
+pub fn too_many_requests(wait: Duration) -> ApiError {
+    let secs = wait.as_secs() + if wait.subsec_nanos() > 0 { 1 } else { 0 };
+
+    ApiError::new(
+        ErrorCode::RateLimited,
+        format_err!("Too many requests, retry in {}s", secs),
+    )
+    .with_retry_after(secs)
+}
+
+/// How long a client over its address limit has to wait, kept for
+/// `rate_limited`.
+struct Limited(Option<Duration>);
+
+/// Sends requests over their address limit to `rate_limited`, before their
+/// handler gets to run.
+#[derive(Debug)]
+pub struct RateLimitFairing;
+
+impl Fairing for RateLimitFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Rate Limits",
+            kind: Kind::Attach | Kind::Request,
+        }
+    }
+
+    fn on_attach(&self, rocket: Rocket) -> fairing::Result {
+        match RateLimits::from_config(rocket.config()) {
+            Ok(limits) => Ok(rocket.manage(limits)),
+            Err(e) => {
+                println!("Error: Failed to configure rate limits: {}", e);
+                Err(rocket)
+            }
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        let checked = match request.guard::<State<RateLimits>>().succeeded() {
+            Some(limits) => limits.check_ip(request),
+            None => Ok(Ok(())),
+        };
+
+        match checked {
+            Ok(Ok(())) => {}
+            Ok(Err(wait)) => {
+                request.local_cache(|| Limited(Some(wait)));
+                request.set_method(Method::Get);
+                request.set_uri(Origin::parse(LIMITED_PATH).expect("valid path"));
+            }
+            // Better to serve a request than to fail all of them while the
+            // database is unavailable.
+            Err(e) => println!("Warning: rate limit check failed: {}", e),
+        }
+    }
+}
+
+/// Answers the requests that `RateLimitFairing` turned away.
+#[get("/rate-limited")]
+pub fn rate_limited(limited: RateLimited) -> ApiError {
+    too_many_requests(limited.0)
+}
+
+/// Request guard for requests rewritten by `RateLimitFairing`.
+pub struct RateLimited(Duration);
+
+impl<'a, 'r> FromRequest<'a, 'r> for RateLimited {
+    type Error = ();
+
+    fn from_request(r: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        match r.local_cache(|| Limited(None)).0 {
+            Some(wait) => Outcome::Success(RateLimited(wait)),
+            None => Outcome::Forward(()),
+        }
+    }
+}
+
+/// Request guard for the address of the client, as rate limits see it.
+pub struct ClientAddress(pub String);
+
+impl<'a, 'r> FromRequest<'a, 'r> for ClientAddress {
+    type Error = ();
+
+    fn from_request(r: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        match r.guard::<State<RateLimits>>() {
+            Outcome::Success(limits) => Outcome::Success(ClientAddress(limits.client_key(r))),
+            _ => Outcome::Failure((Status::InternalServerError, ())),
+        }
+    }
+}
+
+/// Request guard that checks the prover limit of the requested route, once
+/// the handler knows the prover.
+pub struct ProverLimit<'r> {
+    limits: State<'r, RateLimits>,
+    rule: Option<Rule>,
+}
+
+impl<'r> ProverLimit<'r> {
+    pub fn check(&self, prover: &str, conn: &PgConnection) -> ApiResult<()> {
+        let rule = match self.rule {
+            Some(ref rule) => rule,
+            None => return Ok(()),
+        };
+
+        match rule.prover {
+            Some(rate) => {
+                let key = format!("{}:prover:{}", rule.name, prover);
+                self.limits
+                    .take(&key, rate, Some(conn))?
+                    .map_err(too_many_requests)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ProverLimit<'r> {
+    type Error = ();
+
+    fn from_request(r: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        let limits = match r.guard::<State<RateLimits>>() {
+            Outcome::Success(limits) => limits,
+            _ => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+        let rule = limits.rule_for(r.method(), r.uri().path()).cloned();
+
+        Outcome::Success(ProverLimit { limits, rule })
+    }
+}
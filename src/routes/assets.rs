@@ -0,0 +1,19 @@
+use std::path::PathBuf;
+
+use rocket::{get, State};
+
+use crate::assets::{AssetRequest, AssetResponse, StaticAssets};
+
+#[get("/")]
+pub fn index(assets: State<StaticAssets>, req: AssetRequest) -> Option<AssetResponse> {
+    assets.respond(&PathBuf::new(), &req)
+}
+
+#[get("/<path..>", rank = 10)]
+pub fn asset(
+    path: PathBuf,
+    assets: State<StaticAssets>,
+    req: AssetRequest,
+) -> Option<AssetResponse> {
+    assets.respond(&path, &req)
+}
@@ -0,0 +1,82 @@
+use std::panic::{self, AssertUnwindSafe};
+
+use failure::format_err;
+use rocket::{get, post, State};
+use rocket_contrib::json::Json;
+
+use crate::config::GameConfig;
+use crate::db::DbConn;
+use crate::error::{ApiError, ApiResult, ErrorCode, ResultExt};
+use crate::models::audit::{Audit, AuditChallenge, AuditProofs};
+use crate::routes::proof::now;
+use crate::schemes::verify_audit;
+
+/// The audits of `prover` that are waiting for an answer.
+#[get("/audits?<prover>")]
+pub fn open(
+    conn: DbConn,
+    config: State<GameConfig>,
+    prover: String,
+) -> ApiResult<Json<Vec<AuditChallenge>>> {
+    let challenges = Audit::open(&prover, now(), &conn)?
+        .iter()
+        .map(|(audit, params)| audit.challenge(params, &config.key, config.audits.challenges))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Json(challenges))
+}
+
+/// Checks the Merkle inclusion proofs answering an audit.
+#[post("/audits/<id>", format = "json", data = "<proofs>")]
+pub fn answer(
+    conn: DbConn,
+    config: State<GameConfig>,
+    id: i32,
+    proofs: Json<AuditProofs>,
+) -> ApiResult<()> {
+    let now = now();
+    Audit::expire(now, &conn)?;
+
+    let (audit, params) = Audit::find(id, now, &conn)?.ok_or_else(|| {
+        ApiError::new(
+            ErrorCode::NotFound,
+            format_err!("No audit {} was issued", id),
+        )
+    })?;
+
+    match audit.passed {
+        Some(true) => return Ok(()),
+        Some(false) => {
+            return Err(ApiError::new(
+                ErrorCode::MissedAudit,
+                format_err!("The deadline of audit {} has passed", id),
+            ));
+        }
+        None => {}
+    }
+
+    let challenge = audit.challenge(&params, &config.key, config.audits.challenges)?;
+
+    let valid = panic::catch_unwind(AssertUnwindSafe(|| {
+        verify_audit(
+            &challenge.comm_r,
+            challenge.hasher,
+            params.size as usize,
+            &challenge.nodes,
+            &proofs,
+        )
+    }))
+    .unwrap_or_else(|_| Err(format_err!("verification aborted on malformed proof")))
+    .with_code(ErrorCode::InvalidProof)?;
+
+    if !valid {
+        return Err(ApiError::new(
+            ErrorCode::InvalidProof,
+            format_err!("The proofs do not match the replica"),
+        ));
+    }
+
+    Audit::pass(id, &conn)?;
+
+    Ok(())
+}
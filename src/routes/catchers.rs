@@ -1,11 +1,97 @@
-use rocket::catch;
-use rocket_contrib::json;
-use rocket_contrib::json::JsonValue;
+use rocket::http::Status;
+use rocket::{catch, Request};
+
+use crate::error::{ApiError, ErrorCode};
+
+// Each catcher answers with the error of the guard that failed the request,
+// if it kept it, and with a generic error of its status otherwise.
+
+#[catch(400)]
+pub fn bad_request(req: &Request) -> ApiError {
+    ApiError::caught(
+        req,
+        Status::BadRequest,
+        ErrorCode::MalformedRequest,
+        "The request could not be understood.",
+    )
+}
+
+#[catch(401)]
+pub fn unauthorized(req: &Request) -> ApiError {
+    ApiError::caught(
+        req,
+        Status::Unauthorized,
+        ErrorCode::Unauthorized,
+        "The request is not authorized.",
+    )
+}
 
 #[catch(404)]
-pub fn not_found() -> JsonValue {
-    json!({
-        "status": "error",
-        "reason": "Resource was not found."
-    })
+pub fn not_found(req: &Request) -> ApiError {
+    ApiError::caught(
+        req,
+        Status::NotFound,
+        ErrorCode::NotFound,
+        "Resource was not found.",
+    )
+}
+
+#[catch(409)]
+pub fn conflict(req: &Request) -> ApiError {
+    ApiError::caught(
+        req,
+        Status::Conflict,
+        ErrorCode::Conflict,
+        "The request conflicts with the state of the server.",
+    )
+}
+
+#[catch(413)]
+pub fn payload_too_large(req: &Request) -> ApiError {
+    ApiError::caught(
+        req,
+        Status::PayloadTooLarge,
+        ErrorCode::PayloadTooLarge,
+        "The request body is too large.",
+    )
+}
+
+#[catch(415)]
+pub fn unsupported_media_type(req: &Request) -> ApiError {
+    ApiError::caught(
+        req,
+        Status::UnsupportedMediaType,
+        ErrorCode::UnsupportedMediaType,
+        "The request body is not of a supported type.",
+    )
+}
+
+#[catch(422)]
+pub fn unprocessable_entity(req: &Request) -> ApiError {
+    ApiError::caught(
+        req,
+        Status::UnprocessableEntity,
+        ErrorCode::UnprocessableEntity,
+        "The request body could not be processed.",
+    )
+}
+
+#[catch(429)]
+pub fn too_many_requests(req: &Request) -> ApiError {
+    ApiError::caught(
+        req,
+        Status::TooManyRequests,
+        ErrorCode::RateLimited,
+        "Too many requests, slow down.",
+    )
+}
+
+#[catch(500)]
+pub fn internal_error(req: &Request) -> ApiError {
+    ApiError::caught(
+        req,
+        Status::InternalServerError,
+        ErrorCode::Internal,
+        "Internal server error.",
+    )
 }
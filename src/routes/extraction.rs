@@ -0,0 +1,107 @@
+use failure::format_err;
+use rocket::{get, post, State};
+use rocket_contrib::json::Json;
+
+use crate::config::GameConfig;
+use crate::db::DbConn;
+use crate::error::{ApiError, ApiResult, ErrorCode, ResultExt};
+use crate::extraction::{self, Challenge, Extraction};
+use crate::models::extraction::{ExtractionChallenge, ExtractionEntry, PrintableExtractionEntry};
+use crate::models::proof::{self, ProofType};
+use crate::proofs::validate;
+use crate::ratelimit::ProverLimit;
+use crate::routes::proof::{check_seed, now, now_ms};
+
+/// Verifies the sealing proof of a replica of the seed data, and issues the
+/// ranges to extract from it. The extraction time starts now.
+#[post("/extraction", format = "json", data = "<replica>")]
+pub fn replica(
+    conn: DbConn,
+    config: State<GameConfig>,
+    limit: ProverLimit,
+    replica: Json<proof::Envelope>,
+) -> ApiResult<Json<Challenge>> {
+    let replica = replica.into_inner().open()?;
+    limit.check(&replica.prover, &conn)?;
+    check_seed(&config, &replica.seed, now())?;
+
+    if replica.proof_params.typ != ProofType::Zigzag || replica.proof_params.replicas != 1 {
+        return Err(ApiError::new(
+            ErrorCode::ParamsNotAllowed,
+            format_err!("Only single zigzag replicas can be unsealed"),
+        ));
+    }
+
+    validate(&replica, &config)?;
+
+    let challenge = ExtractionChallenge::issue(&replica, now_ms(), &conn)?;
+    let nodes = replica.proof_params.size / 32;
+
+    Ok(Json(Challenge {
+        id: challenge.id,
+        ranges: extraction::ranges(&config.key, challenge.id, nodes),
+        token: extraction::token(&config.key, challenge.id, &replica.prover),
+    }))
+}
+
+/// Checks that the answer comes from the prover of the replica, checks the
+/// extracted ranges against the seed data and the `comm_d` of the replica,
+/// and records the time since the ranges were issued.
+#[post("/extraction/<id>?<token>", format = "json", data = "<extraction>")]
+pub fn extraction(
+    conn: DbConn,
+    config: State<GameConfig>,
+    id: i32,
+    token: Option<String>,
+    extraction: Json<Extraction>,
+) -> ApiResult<()> {
+    let received_at_ms = now_ms();
+
+    let (challenge, params) = ExtractionChallenge::find(id, &conn)?.ok_or_else(|| {
+        ApiError::new(
+            ErrorCode::NotFound,
+            format_err!("No extraction {} was issued", id),
+        )
+    })?;
+
+    let token = token.ok_or_else(|| {
+        ApiError::new(
+            ErrorCode::Unauthorized,
+            format_err!("Missing the token of extraction {}", id),
+        )
+    })?;
+    extraction::check_token(&config.key, id, &challenge.prover, &token)
+        .with_code(ErrorCode::Unauthorized)?;
+
+    // The time of the first answer counts.
+    if challenge.answered {
+        return Ok(());
+    }
+
+    let seed = serde_json::from_str(&challenge.seed)?;
+    let comm_d = serde_json::from_str(&challenge.comm_d)?;
+    let size = params.size as usize;
+    let issued = extraction::ranges(&config.key, id, size / 32);
+
+    extraction::check(&extraction, &issued, &seed, &comm_d, params.hasher, size)
+        .with_code(ErrorCode::InvalidProof)?;
+
+    if !ExtractionChallenge::answer(id, &conn)? {
+        return Ok(());
+    }
+
+    let extraction_time_ms = received_at_ms.saturating_sub(challenge.issued_at_ms as u64);
+    ExtractionEntry::upsert(
+        &challenge.prover,
+        extraction_time_ms as i64,
+        challenge.params_id,
+        &conn,
+    )?;
+
+    Ok(())
+}
+
+#[get("/extraction/leaderboard")]
+pub fn leaderboard(conn: DbConn) -> ApiResult<Json<Vec<PrintableExtractionEntry>>> {
+    Ok(Json(ExtractionEntry::all(&conn)?))
+}
@@ -1,13 +1,42 @@
+use failure::format_err;
 use rocket::get;
+use rocket::http::RawStr;
 use rocket_contrib::json::Json;
 
 use crate::db::DbConn;
-use crate::error::ApiResult;
+use crate::error::{ApiError, ApiResult, ErrorCode};
 use crate::models::leaderboard::{Entry, PrintableEntry};
+use crate::models::proof::HasherType;
+use crate::units::Size;
 
-#[get("/leaderboard")]
-pub fn leaderboard(conn: DbConn) -> ApiResult<Json<Vec<PrintableEntry>>> {
-    let rows = Entry::all(&conn)?;
+#[get("/leaderboard?<hasher>&<size>")]
+pub fn leaderboard(
+    conn: DbConn,
+    hasher: Option<Result<HasherType, &RawStr>>,
+    size: Option<Result<Size, &RawStr>>,
+) -> ApiResult<Json<Vec<PrintableEntry>>> {
+    let hasher = match hasher {
+        Some(Ok(hasher)) => Some(hasher),
+        Some(Err(value)) => {
+            return Err(ApiError::new(
+                ErrorCode::MalformedRequest,
+                format_err!("Unknown hasher {:?}", value.as_str()),
+            ));
+        }
+        None => None,
+    };
+    let size = match size {
+        Some(Ok(Size(size))) => Some(size),
+        Some(Err(value)) => {
+            return Err(ApiError::new(
+                ErrorCode::MalformedRequest,
+                format_err!("Invalid size {:?}", value.as_str()),
+            ));
+        }
+        None => None,
+    };
+
+    let rows = Entry::all(hasher, size, &conn)?;
 
     Ok(Json(rows))
 }
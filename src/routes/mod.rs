@@ -1,5 +1,9 @@
+pub mod assets;
+pub mod audit;
 pub mod catchers;
+pub mod extraction;
 pub mod index;
 pub mod leaderboard;
 pub mod proof;
 pub mod seed;
+pub mod upload;
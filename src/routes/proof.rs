@@ -1,125 +1,308 @@
-use std::env;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::HashSet;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use blake2::crypto_mac::Mac;
 use blake2::Blake2b;
 use failure::format_err;
-use rocket::post;
+use rocket::{post, State};
 use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
 
-use storage_proofs::drgporep::{self, *};
-use storage_proofs::drgraph::*;
-use storage_proofs::hasher::{Hasher, PedersenHasher};
-use storage_proofs::layered_drgporep;
-use storage_proofs::proof::ProofScheme;
-use storage_proofs::zigzag_drgporep::*;
-
+use crate::batch::{Batch, ItemResult};
+use crate::compact::Compact;
+use crate::config::GameConfig;
 use crate::db::DbConn;
-use crate::error::ApiResult;
-use crate::gzip::Gzip;
+use crate::encoding::Compressed;
+use crate::error::{ApiError, ApiResult, ErrorCode, ResultExt};
+use crate::models::audit::Audit;
 use crate::models::leaderboard::upsert_entry_with_params;
 use crate::models::proof;
-use crate::proofs::id_from_str;
+use crate::models::seed::Seed;
+use crate::models::submission::{proof_hash, Submission};
+use crate::proofs::{millis, validate};
+use crate::ratelimit::ProverLimit;
 
 #[post("/proof", format = "json", data = "<res>")]
-pub fn proof_gz(conn: DbConn, res: Gzip<Json<proof::Response>>) -> ApiResult<()> {
-    proof(conn, res.into_inner())
+pub fn proof_compressed(
+    conn: DbConn,
+    config: State<GameConfig>,
+    limit: ProverLimit,
+    res: Compressed<Json<proof::Envelope>>,
+) -> ApiResult<()> {
+    submit(
+        &conn,
+        &config,
+        &limit,
+        &res.into_inner().into_inner().open()?,
+    )
+}
+
+#[post("/proof", format = "application/cbor", data = "<res>")]
+pub fn proof_compact(
+    conn: DbConn,
+    config: State<GameConfig>,
+    limit: ProverLimit,
+    res: Compact,
+) -> ApiResult<()> {
+    submit(&conn, &config, &limit, &res.into_inner().open()?)
 }
 
 #[post("/proof", format = "json", data = "<res>", rank = 2)]
-pub fn proof(conn: DbConn, res: Json<proof::Response>) -> ApiResult<()> {
-    // Get replication time
-    let repl_time = {
-        // Get current timestamp
-        let start = SystemTime::now();
-        let timestamp = start
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards");
-        let completion_time = timestamp.as_secs();
-        (completion_time - res.seed.timestamp as u64) as i32
-    };
+pub fn proof(
+    conn: DbConn,
+    config: State<GameConfig>,
+    limit: ProverLimit,
+    res: Json<proof::Envelope>,
+) -> ApiResult<()> {
+    submit(&conn, &config, &limit, &res.into_inner().open()?)
+}
 
-    // Verify authenticity of seed
-    let mac = hex::decode(&res.seed.seed)?;
-    let key = env::var("GAME_KEY").unwrap_or_else(|_| "my cool key".into());
-    let mut hasher = Blake2b::new_varkey(key.as_bytes())?;
-    hasher.input(&format!("{}", res.seed.timestamp).as_bytes());
-    hasher.verify(&mac)?;
-
-    if !validate(&res) {
-        return Err(format_err!("Submitted proofs are invalid").into());
-    }
+/// The outcome of a dry run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Verdict {
+    pub valid: bool,
+    /// The replication time the proof would be ranked with, if the seed is
+    /// valid.
+    pub repl_time: Option<u64>,
+    /// How long verification took, in milliseconds.
+    pub verify_time_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Circuit synthesis and proving time of SNARK proofs, as reported by
+    /// the prover.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub synthesis_time_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proving_time_ms: Option<u64>,
+}
 
-    upsert_entry_with_params(&res, repl_time, &conn)?;
+#[post("/proof/verify", format = "json", data = "<res>")]
+pub fn verify_compressed(
+    config: State<GameConfig>,
+    res: Compressed<Json<proof::Envelope>>,
+) -> Json<Verdict> {
+    Json(dry_run(&config, res.into_inner().into_inner()))
+}
 
-    Ok(())
+#[post("/proof/verify", format = "application/cbor", data = "<res>")]
+pub fn verify_compact(config: State<GameConfig>, res: Compact) -> Json<Verdict> {
+    Json(dry_run(&config, res.into_inner()))
 }
 
-fn validate(res: &proof::Response) -> bool {
-    let replica_id = id_from_str::<<PedersenHasher as Hasher>::Domain>(&res.seed.seed);
-    let params = &res.proof_params;
-    let data_size = params.size;
-    let m = params.degree;
-    let sloth_iter = params.vde;
-    let challenge_count = params.challenge_count;
-    let nodes = data_size / 32;
-    let param_seed = [0u32; 7];
-
-    match res.proof {
-        proof::Proof::Zigzag(ref proof) => {
-            if params.zigzag.is_none() {
-                return false;
-            }
+#[post("/proof/verify", format = "json", data = "<res>", rank = 2)]
+pub fn verify(config: State<GameConfig>, res: Json<proof::Envelope>) -> Json<Verdict> {
+    Json(dry_run(&config, res.into_inner()))
+}
+
+/// Runs the checks of a submission, without recording anything.
+fn dry_run(config: &GameConfig, envelope: proof::Envelope) -> Verdict {
+    let completion_time = now();
+    let start = Instant::now();
 
-            let (expansion_degree, layer_challenges) =
-                params.as_zigzag_params().expect("missing zigzag params");
-            let comm_r_star = res.comm_r_star.expect("missing comm r star");
-
-            let sp = layered_drgporep::SetupParams {
-                drg: drgporep::DrgParams {
-                    nodes,
-                    degree: m,
-                    expansion_degree,
-                    seed: param_seed,
-                },
-                sloth_iter,
-                layer_challenges,
+    let res = match envelope.open() {
+        Ok(res) => res,
+        Err(err) => {
+            return Verdict {
+                valid: false,
+                repl_time: None,
+                verify_time_ms: millis(start.elapsed()),
+                synthesis_time_ms: None,
+                proving_time_ms: None,
+                code: Some(err.code().as_str().to_string()),
+                message: Some(format!("Error: {}", err)),
             };
+        }
+    };
+    let res = &res;
 
-            let pp = ZigZagDrgPoRep::<PedersenHasher>::setup(&sp).unwrap();
+    let (repl_time, result) = match check_seed(config, &res.seed, completion_time) {
+        Ok(repl_time) => (Some(repl_time), validate(res, config)),
+        Err(err) => (None, Err(err)),
+    };
 
-            let pub_inputs = layered_drgporep::PublicInputs::<<PedersenHasher as Hasher>::Domain> {
-                replica_id,
-                tau: Some(res.tau),
-                comm_r_star,
-                k: Some(0),
-            };
+    let verify_time_ms = millis(start.elapsed());
 
-            ZigZagDrgPoRep::<PedersenHasher>::verify_all_partitions(&pp, &pub_inputs, proof)
-                .unwrap_or_else(|_| false)
+    let (synthesis_time_ms, proving_time_ms) = match res.proof {
+        proof::Proof::ZigzagSnark(ref snark) => {
+            (Some(snark.synthesis_time_ms), Some(snark.proving_time_ms))
         }
-        proof::Proof::DrgPoRep(ref proof) => {
-            let sp = SetupParams {
-                drg: DrgParams {
-                    nodes,
-                    degree: m,
-                    expansion_degree: 0,
-                    seed: param_seed,
-                },
-                challenges_count: challenge_count,
-                private: false,
-                sloth_iter,
-            };
+        _ => (None, None),
+    };
 
-            println!("running setup");
-            let pp = DrgPoRep::<PedersenHasher, BucketGraph<PedersenHasher>>::setup(&sp).unwrap();
-            let pub_inputs = PublicInputs::<<PedersenHasher as Hasher>::Domain> {
-                replica_id: Some(replica_id),
-                challenges: vec![2; challenge_count],
-                tau: Some(res.tau),
-            };
+    match result {
+        Ok(()) => Verdict {
+            valid: true,
+            repl_time,
+            verify_time_ms,
+            synthesis_time_ms,
+            proving_time_ms,
+            code: None,
+            message: None,
+        },
+        Err(err) => Verdict {
+            valid: false,
+            repl_time,
+            verify_time_ms,
+            synthesis_time_ms,
+            proving_time_ms,
+            code: Some(err.code().as_str().to_string()),
+            message: Some(format!("Error: {}", err)),
+        },
+    }
+}
+
+/// Verifies every response of a batch on its own, as soon as it is read.
+/// Each replication time runs from the item's seed to the time the batch
+/// arrived, so items later in the batch don't lose time. The prover limit is charged once per prover of the batch, not
+/// per item.
+#[post("/proofs/batch", data = "<batch>")]
+pub fn proof_batch(
+    conn: DbConn,
+    config: State<GameConfig>,
+    limit: ProverLimit,
+    batch: Result<Batch, ApiError>,
+) -> ApiResult<Json<Vec<ItemResult>>> {
+    let received_at = now();
+    let mut results = Vec::new();
+    let mut charged = HashSet::new();
+
+    batch?.each(|item| {
+        let index = results.len();
+        let result = match item {
+            Ok(res) => {
+                let checked = if charged.contains(&res.prover) {
+                    Ok(())
+                } else {
+                    limit.check(&res.prover, &conn).map(|()| {
+                        charged.insert(res.prover.clone());
+                    })
+                };
+                let result = checked.and_then(|()| submit_at(&conn, &config, &res, received_at));
+                ItemResult::new(index, Some(res.prover), result)
+            }
+            Err(err) => ItemResult::new(index, None, Err(err)),
+        };
+        results.push(result);
+    });
+
+    Ok(Json(results))
+}
 
-            DrgPoRep::<PedersenHasher, _>::verify(&pp, &pub_inputs, proof).unwrap_or_else(|_| false)
+fn submit(
+    conn: &DbConn,
+    config: &GameConfig,
+    limit: &ProverLimit,
+    res: &proof::Response,
+) -> ApiResult<()> {
+    let completion_time = now();
+    limit.check(&res.prover, conn)?;
+    submit_at(conn, config, res, completion_time)
+}
+
+/// Verifies and records a response that was completely received at
+/// `completion_time`.
+pub(crate) fn submit_at(
+    conn: &DbConn,
+    config: &GameConfig,
+    res: &proof::Response,
+    completion_time: u64,
+) -> ApiResult<()> {
+    let repl_time = check_seed(config, &res.seed, completion_time)?;
+
+    let hash = proof_hash(res);
+    let previous = Submission::find(&hash, conn)?;
+
+    // Seen before from this prover, no need to verify it again.
+    if let Some(own) = previous.iter().find(|s| s.prover == res.prover) {
+        return own.verdict();
+    }
+
+    if let Some(original) = previous.first() {
+        println!(
+            "Warning: {} submitted the proof {} of {}",
+            res.prover, hash, original.prover
+        );
+        let verdict = Err(ApiError::new(
+            ErrorCode::DuplicateProof,
+            format_err!("This proof was already submitted by another prover"),
+        ));
+        Submission::new(&hash, &res.prover, &verdict, true, completion_time).insert(conn)?;
+        return verdict;
+    }
+
+    let verdict = validate(res, config);
+
+    // Only remember verdicts that depend on nothing but the proof itself.
+    let cacheable = match verdict {
+        Ok(()) => true,
+        Err(ref err) => err.code() != ErrorCode::Internal,
+    };
+    if cacheable {
+        Submission::new(&hash, &res.prover, &verdict, false, completion_time).insert(conn)?;
+    }
+    verdict?;
+
+    let (params_id, recorded) = upsert_entry_with_params(res, repl_time as i32, conn)?;
+
+    // Misses are counted first, so replacing the entry doesn't excuse them.
+    Audit::expire(now(), conn)?;
+
+    // Only the replicas of the entry are audited, a slower proof has no
+    // entry to lose, and the replicas it replaced are not audited anymore.
+    if recorded {
+        Audit::cancel(&res.prover, params_id, conn)?;
+        if config.audits.enabled {
+            Audit::schedule(res, params_id, &config.audits, completion_time, conn)?;
         }
     }
+
+    Ok(())
+}
+
+/// Current unix time, in seconds.
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
+}
+
+/// Current unix time, in milliseconds.
+pub(crate) fn now_ms() -> u64 {
+    millis(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards"),
+    )
+}
+
+/// Checks that the seed was issued by this server and has not expired at
+/// `completion_time`, and returns the replication time.
+pub(crate) fn check_seed(config: &GameConfig, seed: &Seed, completion_time: u64) -> ApiResult<u64> {
+    // Verify authenticity of seed
+    let mac = hex::decode(&seed.seed).with_code(ErrorCode::InvalidSeed)?;
+    let mut hasher = Blake2b::new_varkey(config.key.as_bytes())?;
+    hasher.input(&format!("{}", seed.timestamp).as_bytes());
+    hasher.verify(&mac).with_code(ErrorCode::InvalidSeed)?;
+
+    // Get replication time
+    let repl_time = completion_time
+        .checked_sub(seed.timestamp as u64)
+        .ok_or_else(|| {
+            ApiError::new(
+                ErrorCode::InvalidSeed,
+                format_err!("Seed timestamp is in the future"),
+            )
+        })?;
+
+    if repl_time > config.seed_ttl {
+        return Err(ApiError::new(
+            ErrorCode::ExpiredSeed,
+            format_err!("Seed expired {}s ago", repl_time - config.seed_ttl),
+        ));
+    }
+
+    Ok(repl_time)
 }
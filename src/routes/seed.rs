@@ -1,16 +1,28 @@
-use std::env;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use blake2::crypto_mac::Mac;
 use blake2::Blake2b;
-use rocket::get;
+use rocket::{get, State};
 use rocket_contrib::json::Json;
 
+use crate::config::GameConfig;
 use crate::error::ApiResult;
-use crate::models::seed::Seed;
+use crate::models::seed::{GraphSeed, Seed};
 
 #[get("/seed")]
-pub fn seed() -> ApiResult<Json<Seed>> {
+pub fn seed(config: State<GameConfig>) -> ApiResult<Json<Seed>> {
+    Ok(Json(issue(&config)?))
+}
+
+#[get("/graph-seed")]
+pub fn graph_seed(config: State<GameConfig>) -> Json<GraphSeed> {
+    Json(GraphSeed {
+        graph_seed: config.graph_seed,
+    })
+}
+
+/// A new seed, signed with the key of the game.
+pub(crate) fn issue(config: &GameConfig) -> ApiResult<Seed> {
     // Get current timestamp
     let ts = {
         let start = SystemTime::now();
@@ -21,14 +33,13 @@ pub fn seed() -> ApiResult<Json<Seed>> {
     };
 
     // take the mac of the timestamp
-    let key = env::var("GAME_KEY").unwrap_or_else(|_| "my cool key".into());
-    let mut hasher = Blake2b::new_varkey(key.as_bytes())?;
+    let mut hasher = Blake2b::new_varkey(config.key.as_bytes())?;
     hasher.input(format!("{}", ts).as_bytes());
     let result = hasher.result();
     let code_bytes = result.code().to_vec();
 
-    Ok(Json(Seed {
+    Ok(Seed {
         timestamp: ts,
         seed: hex::encode(&code_bytes),
-    }))
+    })
 }
@@ -0,0 +1,104 @@
+use std::io::Read;
+
+use failure::format_err;
+use rocket::request::{self, FromRequest};
+use rocket::response::status::Created;
+use rocket::{get, post, put, Data, Outcome, Request, State};
+use rocket_contrib::json::Json;
+
+use crate::config::GameConfig;
+use crate::db::DbConn;
+use crate::error::{guard_failure, ApiError, ApiResult, ErrorCode, ResultExt};
+use crate::ratelimit::{ClientAddress, ProverLimit};
+use crate::routes::proof::{check_seed, now, submit_at};
+use crate::upload::{Finalize, NewSession, SessionStatus, Uploads};
+
+/// The hex encoded Blake2b digest of a chunk, from the `Upload-Checksum`
+/// header.
+pub struct Checksum(Vec<u8>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for Checksum {
+    type Error = ApiError;
+
+    fn from_request(r: &'a Request<'r>) -> request::Outcome<Self, ApiError> {
+        match r.headers().get_one("Upload-Checksum").map(hex::decode) {
+            Some(Ok(checksum)) => Outcome::Success(Checksum(checksum)),
+            _ => {
+                let err = ApiError::new(
+                    ErrorCode::MalformedRequest,
+                    format_err!("Missing or invalid Upload-Checksum header"),
+                );
+                Outcome::Failure((guard_failure(r, err.code(), &err), err))
+            }
+        }
+    }
+}
+
+#[post("/uploads", format = "json", data = "<new>")]
+pub fn create(
+    new: Json<NewSession>,
+    client: ClientAddress,
+    config: State<GameConfig>,
+    uploads: State<Uploads>,
+) -> ApiResult<Created<Json<SessionStatus>>> {
+    // Refuse sessions for seeds that could never be submitted.
+    check_seed(&config, &new.seed, now())?;
+
+    let status = uploads.create(new.into_inner(), &client.0)?;
+
+    Ok(Created(
+        format!("/api/uploads/{}", status.id),
+        Some(Json(status)),
+    ))
+}
+
+#[get("/uploads/<id>")]
+pub fn status(id: String, uploads: State<Uploads>) -> ApiResult<Json<SessionStatus>> {
+    Ok(Json(uploads.status(&id)?))
+}
+
+#[put("/uploads/<id>/<index>", data = "<data>")]
+pub fn chunk(
+    id: String,
+    index: u32,
+    checksum: Checksum,
+    config: State<GameConfig>,
+    data: Data,
+    uploads: State<Uploads>,
+) -> ApiResult<Json<SessionStatus>> {
+    let limits = &config.limits;
+    let mut buf = Vec::new();
+    data.open()
+        .take(limits.chunk + 1)
+        .read_to_end(&mut buf)
+        .with_code(ErrorCode::MalformedRequest)?;
+
+    if buf.len() as u64 > limits.chunk {
+        return Err(ApiError::new(
+            ErrorCode::PayloadTooLarge,
+            format_err!("Chunks are limited to {} bytes", limits.chunk),
+        ));
+    }
+
+    let status = uploads.put_chunk(&id, index, &buf, &checksum.0, limits.upload)?;
+
+    Ok(Json(status))
+}
+
+#[post("/uploads/<id>/finalize", format = "json", data = "<finalize>")]
+pub fn finalize(
+    conn: DbConn,
+    id: String,
+    finalize: Json<Finalize>,
+    config: State<GameConfig>,
+    limit: ProverLimit,
+    uploads: State<Uploads>,
+) -> ApiResult<()> {
+    let mut upload = uploads.finalize(&id, finalize.chunks)?;
+    let res = upload.decode(config.limits.upload, config.max_ratio)?;
+    limit.check(&res.prover, &conn)?;
+
+    // The clock stops when the last chunk arrived, not when the client got
+    // around to finalizing.
+    submit_at(&conn, &config, &res, upload.completion_time)
+}
@@ -1,14 +1,50 @@
+table! {
+    audits (id) {
+        id -> Integer,
+        prover -> Text,
+        params_id -> BigInt,
+        comm_r -> Text,
+        issued_at -> BigInt,
+        deadline -> BigInt,
+        passed -> Nullable<Bool>,
+    }
+}
+
+table! {
+    extraction_challenges (id) {
+        id -> Integer,
+        prover -> Text,
+        params_id -> BigInt,
+        seed -> Text,
+        comm_d -> Text,
+        issued_at_ms -> BigInt,
+        answered -> Bool,
+    }
+}
+
+table! {
+    extractions (id) {
+        id -> Integer,
+        prover -> Text,
+        extraction_time_ms -> BigInt,
+        params_id -> BigInt,
+    }
+}
+
 table! {
     leaderboard (id) {
         id -> Integer,
         prover -> Text,
         repl_time -> Integer,
         params_id -> BigInt,
+        missed_audits -> Integer,
+        synthesis_time_ms -> Nullable<BigInt>,
+        proving_time_ms -> Nullable<BigInt>,
     }
 }
 
 table! {
-    use crate::models::proof::ProofTypeMapping;
+    use crate::models::proof::{HasherTypeMapping, ProofTypeMapping};
     use diesel::sql_types::{Nullable, BigInt, Integer, Double, Bool};
 
     params (id) {
@@ -23,8 +59,33 @@ table! {
         is_tapered -> Nullable<Bool>,
         taper_layers -> Nullable<Integer>,
         taper -> Nullable<Double>,
+        hasher -> HasherTypeMapping,
+        partitions -> Nullable<Integer>,
+        replicas -> Integer,
+    }
+}
+
+table! {
+    submissions (hash, prover) {
+        hash -> Text,
+        prover -> Text,
+        valid -> Bool,
+        code -> Nullable<Text>,
+        message -> Nullable<Text>,
+        flagged -> Bool,
+        created_at -> BigInt,
     }
 }
 
-allow_tables_to_appear_in_same_query!(leaderboard, params);
+allow_tables_to_appear_in_same_query!(
+    audits,
+    extraction_challenges,
+    extractions,
+    leaderboard,
+    params,
+    submissions
+);
+joinable!(audits -> params (params_id));
+joinable!(extraction_challenges -> params (params_id));
+joinable!(extractions -> params (params_id));
 joinable!(leaderboard -> params (params_id));
@@ -0,0 +1,115 @@
+use failure::{ensure, format_err};
+use storage_proofs::hasher::Domain;
+use storage_proofs::merkle::{MerkleProof, MerkleTree};
+
+use super::GameHasher;
+use crate::models::audit::AuditProofs;
+use crate::models::proof::{Commitment, HasherType};
+
+/// The Merkle tree over the nodes of a replica, whose root is its `comm_r`,
+/// or over the original data, whose root is its `comm_d`.
+fn replica_tree<H: GameHasher>(
+    replica: &[u8],
+) -> Result<MerkleTree<H::Domain, H::Function>, failure::Error> {
+    let leaves = replica
+        .chunks(32)
+        .map(H::Domain::try_from_bytes)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(MerkleTree::new(leaves))
+}
+
+fn prove<H: GameHasher>(replica: &[u8], nodes: &[usize]) -> Result<AuditProofs, failure::Error> {
+    let leaves = replica.len() / 32;
+    let tree = replica_tree::<H>(replica)?;
+
+    let proofs = nodes
+        .iter()
+        .map(|&node| {
+            ensure!(node < leaves, "node {} is not in the replica", node);
+            Ok(MerkleProof::new_from_proof(&tree.gen_proof(node)))
+        })
+        .collect::<Result<Vec<_>, failure::Error>>()?;
+
+    Ok(H::audit_proofs(proofs))
+}
+
+/// Checks the proofs of `nodes` in the tree of `size` bytes with the given
+/// root, and, when given, that the nodes hold `data`.
+fn verify<H: GameHasher>(
+    root: &Commitment,
+    size: usize,
+    nodes: &[usize],
+    data: Option<&[u8]>,
+    proofs: &AuditProofs,
+) -> Result<bool, failure::Error> {
+    let proofs = H::as_audit_proofs(proofs)
+        .ok_or_else(|| format_err!("expected {} proofs", H::TYPE.name()))?;
+    ensure!(
+        proofs.len() == nodes.len(),
+        "expected {} proofs, got {}",
+        nodes.len(),
+        proofs.len()
+    );
+
+    if let Some(data) = data {
+        ensure!(
+            data.len() == nodes.len() * 32,
+            "expected {} bytes of data, got {}",
+            nodes.len() * 32,
+            data.len()
+        );
+    }
+
+    let root: H::Domain = root.domain()?;
+    // A shorter path could prove a node of a smaller tree with the same root.
+    let height = (size / 32).next_power_of_two().trailing_zeros() as usize;
+
+    Ok(proofs
+        .iter()
+        .zip(nodes)
+        .enumerate()
+        .all(|(i, (proof, &node))| {
+            proof.path.len() == height
+                && *proof.root() == root
+                && proof.validate(node)
+                && data.map_or(true, |data| {
+                    proof.validate_data(&data[i * 32..(i + 1) * 32])
+                })
+        }))
+}
+
+/// Proves the inclusion of `nodes` in a replica kept by the prover, or in
+/// the original data decoded from it.
+pub fn audit_proofs(
+    data: &[u8],
+    hasher: HasherType,
+    nodes: &[usize],
+) -> Result<AuditProofs, failure::Error> {
+    with_hasher!(hasher, prove(data, nodes))
+}
+
+/// Checks that the proofs show `nodes` in the replica of `size` bytes
+/// committed to by `comm_r`.
+pub fn verify_audit(
+    comm_r: &Commitment,
+    hasher: HasherType,
+    size: usize,
+    nodes: &[usize],
+    proofs: &AuditProofs,
+) -> Result<bool, failure::Error> {
+    with_hasher!(hasher, verify(comm_r, size, nodes, None, proofs))
+}
+
+/// Checks that the proofs show `nodes`, holding `data`, in the original data
+/// of `size` bytes committed to by `comm_d`.
+pub fn verify_inclusion(
+    comm_d: &Commitment,
+    hasher: HasherType,
+    size: usize,
+    nodes: &[usize],
+    data: &[u8],
+    proofs: &AuditProofs,
+) -> Result<bool, failure::Error> {
+    with_hasher!(hasher, verify(comm_d, size, nodes, Some(data), proofs))
+}
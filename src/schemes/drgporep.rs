@@ -0,0 +1,151 @@
+use failure::format_err;
+use storage_proofs::drgporep::*;
+use storage_proofs::drgraph::BucketGraph;
+use storage_proofs::porep::PoRep;
+use storage_proofs::proof::ProofScheme;
+
+use super::{GameHasher, GameScheme};
+use crate::bench;
+use crate::models::proof::{self, ProofType};
+use crate::models::seed::Seed;
+use crate::proofs::id_from_str;
+
+/// Single layer DRG PoRep.
+pub struct DrgPoRepScheme;
+
+type Scheme<H> = DrgPoRep<'static, H, BucketGraph<H>>;
+type PublicParams<H> = <Scheme<H> as ProofScheme<'static>>::PublicParams;
+
+fn setup<H: GameHasher>(
+    params: &proof::Params,
+    graph_seed: [u32; 7],
+) -> Result<PublicParams<H>, failure::Error> {
+    let sp = SetupParams {
+        drg: DrgParams {
+            nodes: params.size / 32,
+            degree: params.degree,
+            expansion_degree: 0,
+            seed: graph_seed,
+        },
+        challenges_count: params.challenge_count,
+        private: false,
+        sloth_iter: params.vde,
+    };
+
+    Ok(Scheme::<H>::setup(&sp)?)
+}
+
+// TODO: proper challenge generation
+fn challenges(params: &proof::Params) -> Vec<usize> {
+    vec![2; params.challenge_count]
+}
+
+fn seal<H: GameHasher>(
+    prover: String,
+    params: proof::Params,
+    seed: Seed,
+    data: &mut [u8],
+    graph_seed: [u32; 7],
+) -> Result<proof::Response, failure::Error> {
+    let replica_id = id_from_str::<H::Domain>(&seed.seed)?;
+
+    eprintln!("running setup");
+    let pp = bench::stage("setup", || setup::<H>(&params, graph_seed))?;
+
+    eprintln!("running replicate");
+    let (tau, aux) = bench::stage("replicate", || {
+        Scheme::<H>::replicate(&pp, &replica_id, data, None)
+    })?;
+
+    let pub_inputs = PublicInputs {
+        replica_id: Some(replica_id),
+        challenges: challenges(&params),
+        tau: Some(tau),
+    };
+
+    let priv_inputs = PrivateInputs::<H> {
+        tree_d: &aux.tree_d,
+        tree_r: &aux.tree_r,
+    };
+
+    eprintln!("sampling proving & verifying");
+
+    let pr = bench::stage("prove", || {
+        Scheme::<H>::prove(&pp, &pub_inputs, &priv_inputs)
+    })?;
+
+    bench::stage("verify", || Scheme::<H>::verify(&pp, &pub_inputs, &pr))?;
+
+    Ok(proof::Response {
+        format_version: proof::FORMAT_VERSION,
+        prover,
+        seed,
+        proof_params: params,
+        proof: H::drgporep_proof(pr),
+        comm_r_star: None,
+        tau: H::tau(tau),
+        replicas: Vec::new(),
+    })
+}
+
+fn verify<H: GameHasher>(
+    res: &proof::ReplicaRef,
+    graph_seed: [u32; 7],
+) -> Result<bool, failure::Error> {
+    let proof = H::as_drgporep(&res.proof).ok_or_else(|| format_err!("not a drgporep proof"))?;
+
+    let replica_id = id_from_str::<H::Domain>(&res.seed.seed)?;
+    let pp = setup::<H>(&res.proof_params, graph_seed)?;
+    let pub_inputs = PublicInputs::<H::Domain> {
+        replica_id: Some(replica_id),
+        challenges: challenges(&res.proof_params),
+        tau: Some(H::domain_tau(&res.tau)?),
+    };
+
+    let valid = Scheme::<H>::verify(&pp, &pub_inputs, proof)?;
+    Ok(valid)
+}
+
+impl GameScheme for DrgPoRepScheme {
+    fn typ(&self) -> ProofType {
+        ProofType::DrgPoRep
+    }
+
+    fn name(&self) -> &'static str {
+        "drgporep"
+    }
+
+    fn params(&self, params: proof::Params, _: proof::ZigZagParams) -> proof::Params {
+        proof::Params {
+            zigzag: None,
+            ..params
+        }
+    }
+
+    fn check(&self, params: &proof::Params) -> Result<(), failure::Error> {
+        match params.zigzag {
+            Some(_) => Err(format_err!("drgporep does not take zigzag params")),
+            None => Ok(()),
+        }
+    }
+
+    fn seal(
+        &self,
+        prover: String,
+        params: proof::Params,
+        seed: Seed,
+        data: &mut [u8],
+        graph_seed: [u32; 7],
+    ) -> Result<proof::Response, failure::Error> {
+        let hasher = params.hasher;
+        with_hasher!(hasher, seal(prover, params, seed, data, graph_seed))
+    }
+
+    fn verify(
+        &self,
+        res: &proof::ReplicaRef,
+        graph_seed: [u32; 7],
+    ) -> Result<bool, failure::Error> {
+        with_hasher!(res.proof.hasher(), verify(res, graph_seed))
+    }
+}
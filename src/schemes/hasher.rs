@@ -0,0 +1,99 @@
+use storage_proofs::hasher::{Blake2sHasher, Hasher, PedersenHasher, Sha256Hasher};
+use storage_proofs::merkle::MerkleProof;
+use storage_proofs::{drgporep, layered_drgporep, porep};
+
+use crate::models::audit::AuditProofs;
+use crate::models::proof::{self, Commitment, HasherType};
+use crate::proofs::id_from_str;
+
+/// A hasher replicas can be committed to with, together with the `Proof`
+/// variants that carry its proofs.
+pub trait GameHasher: Hasher + 'static {
+    const TYPE: HasherType;
+
+    fn zigzag_proof(proof: Vec<layered_drgporep::Proof<Self>>) -> proof::Proof;
+
+    fn drgporep_proof(proof: drgporep::Proof<Self>) -> proof::Proof;
+
+    fn as_zigzag(proof: &proof::Proof) -> Option<&Vec<layered_drgporep::Proof<Self>>>;
+
+    fn as_drgporep(proof: &proof::Proof) -> Option<&drgporep::Proof<Self>>;
+
+    fn audit_proofs(proofs: Vec<MerkleProof<Self>>) -> AuditProofs;
+
+    fn as_audit_proofs(proofs: &AuditProofs) -> Option<&Vec<MerkleProof<Self>>>;
+
+    fn commitment(domain: Self::Domain) -> Commitment {
+        Commitment::new(Self::TYPE, domain)
+    }
+
+    fn tau(tau: porep::Tau<Self::Domain>) -> porep::Tau<Commitment> {
+        porep::Tau {
+            comm_r: Self::commitment(tau.comm_r),
+            comm_d: Self::commitment(tau.comm_d),
+        }
+    }
+
+    fn domain_tau(
+        tau: &porep::Tau<Commitment>,
+    ) -> Result<porep::Tau<Self::Domain>, failure::Error> {
+        Ok(porep::Tau {
+            comm_r: tau.comm_r.domain()?,
+            comm_d: tau.comm_d.domain()?,
+        })
+    }
+}
+
+macro_rules! game_hasher {
+    ($hasher:ty, $typ:ident, $zigzag:ident, $drgporep:ident) => {
+        impl GameHasher for $hasher {
+            const TYPE: HasherType = HasherType::$typ;
+
+            fn zigzag_proof(proof: Vec<layered_drgporep::Proof<Self>>) -> proof::Proof {
+                proof::Proof::$zigzag(proof)
+            }
+
+            fn drgporep_proof(proof: drgporep::Proof<Self>) -> proof::Proof {
+                proof::Proof::$drgporep(proof)
+            }
+
+            fn as_zigzag(proof: &proof::Proof) -> Option<&Vec<layered_drgporep::Proof<Self>>> {
+                match proof {
+                    proof::Proof::$zigzag(proof) => Some(proof),
+                    _ => None,
+                }
+            }
+
+            fn as_drgporep(proof: &proof::Proof) -> Option<&drgporep::Proof<Self>> {
+                match proof {
+                    proof::Proof::$drgporep(proof) => Some(proof),
+                    _ => None,
+                }
+            }
+
+            fn audit_proofs(proofs: Vec<MerkleProof<Self>>) -> AuditProofs {
+                AuditProofs::$typ(proofs)
+            }
+
+            fn as_audit_proofs(proofs: &AuditProofs) -> Option<&Vec<MerkleProof<Self>>> {
+                match proofs {
+                    AuditProofs::$typ(proofs) => Some(proofs),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+game_hasher!(PedersenHasher, Pedersen, Zigzag, DrgPoRep);
+game_hasher!(Blake2sHasher, Blake2s, ZigzagBlake2s, DrgPoRepBlake2s);
+game_hasher!(Sha256Hasher, Sha256, ZigzagSha256, DrgPoRepSha256);
+
+/// Checks that the seed is a valid replica id in the domain of `hasher`.
+pub fn check_replica_id(hasher: HasherType, seed: &str) -> Result<(), failure::Error> {
+    match hasher {
+        HasherType::Pedersen => id_from_str::<<PedersenHasher as Hasher>::Domain>(seed).map(|_| ()),
+        HasherType::Blake2s => id_from_str::<<Blake2sHasher as Hasher>::Domain>(seed).map(|_| ()),
+        HasherType::Sha256 => id_from_str::<<Sha256Hasher as Hasher>::Domain>(seed).map(|_| ()),
+    }
+}
@@ -0,0 +1,159 @@
+//! The proof schemes played in the game.
+//!
+//! Each scheme knows which params it takes, how to set up, replicate and
+//! prove, and how to verify a submitted proof. Everything that depends on the
+//! algorithm goes through the registry below, so adding one means
+//! implementing `GameScheme`, adding its `ProofType` and `Proof` variants and
+//! listing it in `SCHEMES`.
+//!
+//! Schemes are generic over the hasher, which `params.hasher` selects.
+
+use failure::format_err;
+use rand::thread_rng;
+
+use crate::bench;
+use crate::extraction::{Extraction, NodeRange};
+use crate::models::proof::{self, ProofType};
+use crate::models::seed::Seed;
+use crate::proofs::file_backed_mmap_from_random_bytes;
+
+/// Calls the generic function `f` with the hasher of the given type.
+macro_rules! with_hasher {
+    ($hasher:expr, $f:ident($($arg:expr),*)) => {
+        match $hasher {
+            $crate::models::proof::HasherType::Pedersen => {
+                $f::<storage_proofs::hasher::PedersenHasher>($($arg),*)
+            }
+            $crate::models::proof::HasherType::Blake2s => {
+                $f::<storage_proofs::hasher::Blake2sHasher>($($arg),*)
+            }
+            $crate::models::proof::HasherType::Sha256 => {
+                $f::<storage_proofs::hasher::Sha256Hasher>($($arg),*)
+            }
+        }
+    };
+}
+
+mod audit;
+mod drgporep;
+mod hasher;
+mod snark;
+mod zigzag;
+
+pub use self::audit::{audit_proofs, verify_audit, verify_inclusion};
+pub use self::drgporep::DrgPoRepScheme;
+pub use self::hasher::{check_replica_id, GameHasher};
+pub use self::snark::{
+    setup_snark_parameters, snark_tracks, use_snark_parameters, ZigZagSnarkScheme,
+};
+pub use self::zigzag::ZigZagScheme;
+
+pub trait GameScheme: Sync {
+    /// The key of this scheme in the registry, and its type in the database.
+    fn typ(&self) -> ProofType;
+
+    /// The name used on the command line.
+    fn name(&self) -> &'static str;
+
+    /// Fills in the scheme specific params from the command line defaults,
+    /// or drops them when the scheme takes none.
+    fn params(&self, params: proof::Params, zigzag: proof::ZigZagParams) -> proof::Params;
+
+    /// Checks the scheme specific params. The common ones are checked by
+    /// `Params::check`.
+    fn check(&self, params: &proof::Params) -> Result<(), failure::Error>;
+
+    /// Replicates `data` for `seed` in place and proves it, using the hasher
+    /// of `params`. The replica is left in `data`, where a prover can keep it
+    /// for audits.
+    fn seal(
+        &self,
+        prover: String,
+        params: proof::Params,
+        seed: Seed,
+        data: &mut [u8],
+        graph_seed: [u32; 7],
+    ) -> Result<proof::Response, failure::Error>;
+
+    /// Replicates random data for `seed` and proves it.
+    fn prove(
+        &self,
+        prover: String,
+        params: proof::Params,
+        seed: Seed,
+        graph_seed: [u32; 7],
+    ) -> Result<proof::Response, failure::Error> {
+        eprintln!("generating fake data");
+        let nodes = params.size / 32;
+        let mut data = bench::stage("generate data", || {
+            file_backed_mmap_from_random_bytes(&mut thread_rng(), nodes)
+        });
+
+        self.seal(prover, params, seed, &mut data, graph_seed)
+    }
+
+    /// The proof formats `verify` understands. Verifiers of older formats are
+    /// kept around when the format changes, and pick the format by the
+    /// `format_version` of the response.
+    fn formats(&self) -> &'static [u32] {
+        &[proof::FORMAT_VERSION]
+    }
+
+    /// Verifies the proof of one replica of a submitted response, using the
+    /// hasher of the proof. Only called with responses in one of `formats`.
+    fn verify(&self, res: &proof::ReplicaRef, graph_seed: [u32; 7])
+        -> Result<bool, failure::Error>;
+
+    /// Decodes `ranges` from a replica of the data of `seed`, and proves
+    /// their inclusion in the original data. Schemes that can't be unsealed
+    /// leave this out.
+    fn unseal(
+        &self,
+        _params: &proof::Params,
+        _seed: &Seed,
+        _replica: &[u8],
+        _ranges: &[NodeRange],
+        _graph_seed: [u32; 7],
+    ) -> Result<Extraction, failure::Error> {
+        Err(format_err!("{} replicas can't be unsealed", self.name()))
+    }
+}
+
+static SCHEMES: [&dyn GameScheme; 3] = [&ZigZagScheme, &DrgPoRepScheme, &ZigZagSnarkScheme];
+
+/// All registered schemes.
+pub fn all() -> &'static [&'static dyn GameScheme] {
+    &SCHEMES
+}
+
+/// The scheme of the given type.
+pub fn get(typ: &ProofType) -> &'static dyn GameScheme {
+    all()
+        .iter()
+        .find(|scheme| scheme.typ() == *typ)
+        .cloned()
+        .expect("every proof type is registered")
+}
+
+/// The scheme that produced `proof`.
+pub fn for_proof(proof: &proof::Proof) -> &'static dyn GameScheme {
+    get(&proof.typ())
+}
+
+/// The scheme that verifies `proof`, in the given format.
+pub fn verifier(
+    proof: &proof::Proof,
+    format_version: u32,
+) -> Result<&'static dyn GameScheme, failure::Error> {
+    let scheme = for_proof(proof);
+    if !scheme.formats().contains(&format_version) {
+        return Err(format_err!("unsupported proof format v{}", format_version));
+    }
+
+    Ok(scheme)
+}
+
+/// The scheme with the given command line name.
+pub fn by_name(name: &str) -> Option<&'static dyn GameScheme> {
+    all().iter().find(|scheme| scheme.name() == name).cloned()
+}
@@ -0,0 +1,474 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use bellperson::groth16;
+use bellperson::{Circuit, ConstraintSystem, Index, LinearCombination, SynthesisError, Variable};
+use failure::{ensure, format_err};
+use fil_sapling_crypto::jubjub::JubjubBls12;
+use lazy_static::lazy_static;
+use paired::bls12_381::Bls12;
+use paired::Engine;
+use rand::thread_rng;
+use storage_proofs::circuit::zigzag::{ZigZagCircuit, ZigZagCompound};
+use storage_proofs::compound_proof::CompoundProof;
+use storage_proofs::hasher::{Hasher, PedersenHasher};
+use storage_proofs::layered_drgporep;
+use storage_proofs::porep::PoRep;
+use storage_proofs::proof::ProofScheme;
+use storage_proofs::zigzag_drgporep::ZigZagDrgPoRep;
+
+use super::zigzag::{setup, PublicParams};
+use super::{GameHasher, GameScheme, ZigZagScheme};
+use crate::bench;
+use crate::models::leaderboard::Params;
+use crate::models::proof::{self, HasherType, ProofType};
+use crate::models::seed::Seed;
+use crate::proofs::{id_from_str, millis};
+
+/// Zigzag proofs compressed into Groth16 proofs of the zigzag circuit.
+///
+/// Only the params of the `snark_tracks` are played. Their circuit
+/// parameters come from a setup run once by the operator of the server with
+/// `replication-game snark-setup`, which throws its randomness away, and are
+/// read from the directory given to `use_snark_parameters`. They are never
+/// generated while proving or verifying.
+pub struct ZigZagSnarkScheme;
+
+type Vanilla<'a> = ZigZagDrgPoRep<'a, PedersenHasher>;
+type Domain = <PedersenHasher as Hasher>::Domain;
+
+lazy_static! {
+    static ref ENGINE_PARAMS: JubjubBls12 = JubjubBls12::new();
+    static ref PARAMETERS: RwLock<Parameters> = RwLock::new(Parameters::default());
+}
+
+/// Where circuit parameters are read from, and the verifying keys read so
+/// far.
+#[derive(Default)]
+struct Parameters {
+    dir: Option<PathBuf>,
+    verifying_keys: HashMap<&'static str, groth16::VerifyingKey<Bls12>>,
+}
+
+/// The params of the SNARK tracks, by name.
+pub fn snark_tracks() -> Vec<(&'static str, proof::Params)> {
+    let snark = |size, challenge_count, vde, degree, expansion_degree, layers| proof::Params {
+        typ: ProofType::ZigzagSnark,
+        hasher: HasherType::Pedersen,
+        size,
+        challenge_count,
+        vde,
+        degree,
+        replicas: 1,
+        zigzag: Some(proof::ZigZagParams {
+            expansion_degree,
+            layers,
+            is_tapered: false,
+            taper_layers: 0,
+            taper: 0.,
+            partitions: 1,
+        }),
+    };
+
+    vec![
+        ("zigzag-snark-1kib", snark(1 << 10, 1, 1, 3, 8, 2)),
+        ("zigzag-snark-64kib", snark(64 << 10, 20, 0, 6, 6, 4)),
+    ]
+}
+
+/// The name of the track with these params.
+fn track(params: &proof::Params) -> Option<&'static str> {
+    let id = Params::id(params);
+    snark_tracks()
+        .into_iter()
+        .find(|(_, track)| Params::id(track) == id)
+        .map(|(name, _)| name)
+}
+
+/// Reads circuit parameters from `dir` from now on.
+pub fn use_snark_parameters(dir: &Path) {
+    let mut parameters = PARAMETERS.write().expect("poisoned lock");
+    parameters.dir = Some(dir.to_path_buf());
+    parameters.verifying_keys.clear();
+}
+
+fn parameters_path(name: &str, extension: &str) -> Result<PathBuf, failure::Error> {
+    let parameters = PARAMETERS.read().expect("poisoned lock");
+    let dir = parameters
+        .dir
+        .as_ref()
+        .ok_or_else(|| format_err!("no directory to read circuit parameters from"))?;
+
+    let path = dir.join(format!("{}.{}", name, extension));
+    ensure!(
+        path.exists(),
+        "no circuit parameters for {} in {}, run `replication-game snark-setup`",
+        name,
+        dir.display()
+    );
+
+    Ok(path)
+}
+
+/// The parameters of the circuit of track `name`, for proving.
+fn groth_params(name: &str) -> Result<groth16::Parameters<Bls12>, failure::Error> {
+    let file = File::open(parameters_path(name, "params")?)?;
+    Ok(groth16::Parameters::read(BufReader::new(file), false)?)
+}
+
+/// The verifying key of the circuit of track `name`.
+fn verifying_key(name: &'static str) -> Result<groth16::VerifyingKey<Bls12>, failure::Error> {
+    if let Some(vk) = PARAMETERS
+        .read()
+        .expect("poisoned lock")
+        .verifying_keys
+        .get(name)
+    {
+        return Ok(vk.clone());
+    }
+
+    let file = File::open(parameters_path(name, "vk")?)?;
+    let vk = groth16::VerifyingKey::read(BufReader::new(file))?;
+    PARAMETERS
+        .write()
+        .expect("poisoned lock")
+        .verifying_keys
+        .insert(name, vk.clone());
+
+    Ok(vk)
+}
+
+/// Generates the circuit parameters of track `name` into `dir`, as
+/// `<name>.params` for provers and `<name>.vk` for the server.
+pub fn setup_snark_parameters(
+    dir: &Path,
+    name: &str,
+    graph_seed: [u32; 7],
+) -> Result<(), failure::Error> {
+    let (name, params) = snark_tracks()
+        .into_iter()
+        .find(|(track, _)| *track == name)
+        .ok_or_else(|| format_err!("unknown zigzag-snark track {}", name))?;
+    let layers = params
+        .as_zigzag_params()
+        .ok_or_else(|| format_err!("missing zigzag params"))?;
+
+    let pp = setup::<PedersenHasher>(&params, layers, graph_seed)?;
+    let parameters = groth16::generate_random_parameters(
+        blank_circuit(&pp, engine_params()),
+        &mut thread_rng(),
+    )?;
+
+    fs::create_dir_all(dir)?;
+    parameters.write(BufWriter::new(File::create(
+        dir.join(format!("{}.params", name)),
+    )?))?;
+    parameters.vk.write(BufWriter::new(File::create(
+        dir.join(format!("{}.vk", name)),
+    )?))?;
+
+    Ok(())
+}
+
+fn blank_circuit<'a>(
+    pp: &PublicParams<PedersenHasher>,
+    engine_params: &'a JubjubBls12,
+) -> ZigZagCircuit<'a, Bls12, PedersenHasher> {
+    <ZigZagCompound as CompoundProof<
+        'a,
+        Bls12,
+        Vanilla<'a>,
+        ZigZagCircuit<'a, Bls12, PedersenHasher>,
+    >>::blank_circuit(pp, engine_params)
+}
+
+/// The circuit proving partition `k`.
+fn circuit<'a>(
+    pp: &'a PublicParams<PedersenHasher>,
+    pub_inputs: &'a layered_drgporep::PublicInputs<Domain>,
+    vanilla: &'a layered_drgporep::Proof<PedersenHasher>,
+    engine_params: &'a JubjubBls12,
+) -> ZigZagCircuit<'a, Bls12, PedersenHasher> {
+    <ZigZagCompound as CompoundProof<
+        'a,
+        Bls12,
+        Vanilla<'a>,
+        ZigZagCircuit<'a, Bls12, PedersenHasher>,
+    >>::circuit(pub_inputs, (), vanilla, pp, engine_params)
+}
+
+fn public_inputs<'a>(
+    pp: &'a PublicParams<PedersenHasher>,
+    pub_inputs: &layered_drgporep::PublicInputs<Domain>,
+    k: usize,
+) -> Vec<<Bls12 as Engine>::Fr> {
+    <ZigZagCompound as CompoundProof<
+        'a,
+        Bls12,
+        Vanilla<'a>,
+        ZigZagCircuit<'a, Bls12, PedersenHasher>,
+    >>::generate_public_inputs(pub_inputs, pp, Some(k))
+}
+
+fn engine_params() -> &'static JubjubBls12 {
+    &ENGINE_PARAMS
+}
+
+/// A constraint system that only computes the witness, to time synthesis
+/// apart from proving.
+#[derive(Debug, Default)]
+struct Synthesis {
+    inputs: usize,
+    aux: usize,
+    constraints: usize,
+}
+
+impl<E: Engine> ConstraintSystem<E> for Synthesis {
+    type Root = Self;
+
+    fn alloc<F, A, AR>(&mut self, _: A, f: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<E::Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        f()?;
+        self.aux += 1;
+        Ok(Variable::new_unchecked(Index::Aux(self.aux - 1)))
+    }
+
+    fn alloc_input<F, A, AR>(&mut self, _: A, f: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<E::Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        f()?;
+        // Input 0 is the constant one.
+        self.inputs += 1;
+        Ok(Variable::new_unchecked(Index::Input(self.inputs)))
+    }
+
+    fn enforce<A, AR, LA, LB, LC>(&mut self, _: A, a: LA, b: LB, c: LC)
+    where
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+        LA: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+        LB: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+        LC: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+    {
+        a(LinearCombination::zero());
+        b(LinearCombination::zero());
+        c(LinearCombination::zero());
+        self.constraints += 1;
+    }
+
+    fn push_namespace<NR, N>(&mut self, _: N)
+    where
+        NR: Into<String>,
+        N: FnOnce() -> NR,
+    {
+    }
+
+    fn pop_namespace(&mut self) {}
+
+    fn get_root(&mut self) -> &mut Self::Root {
+        self
+    }
+}
+
+impl GameScheme for ZigZagSnarkScheme {
+    fn typ(&self) -> ProofType {
+        ProofType::ZigzagSnark
+    }
+
+    fn name(&self) -> &'static str {
+        "zigzag-snark"
+    }
+
+    fn params(&self, params: proof::Params, zigzag: proof::ZigZagParams) -> proof::Params {
+        ZigZagScheme.params(params, zigzag)
+    }
+
+    fn check(&self, params: &proof::Params) -> Result<(), failure::Error> {
+        ZigZagScheme.check(params)?;
+        ensure!(
+            track(params).is_some(),
+            "zigzag-snark proofs are only played with the params of the {} tracks",
+            snark_tracks()
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        Ok(())
+    }
+
+    fn seal(
+        &self,
+        prover: String,
+        params: proof::Params,
+        seed: Seed,
+        data: &mut [u8],
+        graph_seed: [u32; 7],
+    ) -> Result<proof::Response, failure::Error> {
+        self.check(&params)?;
+        let name = track(&params).expect("the params were checked");
+
+        let replica_id = id_from_str::<Domain>(&seed.seed)?;
+        let layers = params
+            .as_zigzag_params()
+            .ok_or_else(|| format_err!("missing zigzag params"))?;
+        let partitions = params.partitions();
+
+        let mut rng = thread_rng();
+
+        eprintln!("running setup");
+        let pp = bench::stage("setup", || {
+            setup::<PedersenHasher>(&params, layers, graph_seed)
+        })?;
+
+        eprintln!("running replicate");
+        let (tau, aux) = bench::stage("replicate", || {
+            Vanilla::replicate(&pp, &replica_id, data, None)
+        })?;
+
+        let pub_inputs = layered_drgporep::PublicInputs::<Domain> {
+            replica_id,
+            tau: Some(tau.simplify()),
+            comm_r_star: tau.comm_r_star,
+            k: None,
+        };
+        let priv_inputs = layered_drgporep::PrivateInputs {
+            aux,
+            tau: tau.layer_taus.clone(),
+        };
+
+        eprintln!("generating {} partition proofs", partitions);
+        let vanilla = bench::stage("prove", || {
+            Vanilla::prove_all_partitions(&pp, &pub_inputs, &priv_inputs, partitions)
+        })?;
+
+        eprintln!("loading circuit parameters");
+        let groth_params = bench::stage("load circuit parameters", || groth_params(name))?;
+
+        let partition_inputs: Vec<_> = (0..partitions)
+            .map(|k| Vanilla::with_partition(pub_inputs.clone(), Some(k)))
+            .collect();
+
+        eprintln!("synthesizing circuits");
+        let start = Instant::now();
+        let mut synthesis = Synthesis::default();
+        bench::stage("synthesize circuits", || -> Result<(), failure::Error> {
+            for (inputs, vanilla) in partition_inputs.iter().zip(&vanilla) {
+                circuit(&pp, inputs, vanilla, engine_params()).synthesize(&mut synthesis)?;
+            }
+            Ok(())
+        })?;
+        let synthesis_time_ms = millis(start.elapsed());
+        eprintln!(
+            "synthesized {} constraints in {}ms",
+            synthesis.constraints, synthesis_time_ms
+        );
+
+        eprintln!("generating groth16 proofs");
+        let mut proving = Duration::default();
+        let proofs = bench::stage("prove circuits", || {
+            partition_inputs
+                .iter()
+                .zip(&vanilla)
+                .map(|(inputs, vanilla)| {
+                    let circuit = circuit(&pp, inputs, vanilla, engine_params());
+                    let start = Instant::now();
+                    let proof = groth16::create_random_proof(circuit, &groth_params, &mut rng)?;
+                    proving += start.elapsed();
+
+                    let mut bytes = Vec::new();
+                    proof.write(&mut bytes)?;
+                    Ok(hex::encode(bytes))
+                })
+                .collect::<Result<Vec<_>, failure::Error>>()
+        })?;
+        let proving_time_ms = millis(proving);
+        eprintln!("proved in {}ms", proving_time_ms);
+
+        Ok(proof::Response {
+            format_version: proof::FORMAT_VERSION,
+            prover,
+            seed,
+            proof_params: params,
+            proof: proof::Proof::ZigzagSnark(proof::SnarkProof {
+                proofs,
+                synthesis_time_ms,
+                proving_time_ms,
+            }),
+            comm_r_star: Some(PedersenHasher::commitment(tau.comm_r_star)),
+            tau: PedersenHasher::tau(tau.simplify()),
+            replicas: Vec::new(),
+        })
+    }
+
+    fn verify(
+        &self,
+        res: &proof::ReplicaRef,
+        graph_seed: [u32; 7],
+    ) -> Result<bool, failure::Error> {
+        let snark = match res.proof {
+            proof::Proof::ZigzagSnark(ref snark) => snark,
+            _ => return Err(format_err!("not a zigzag-snark proof")),
+        };
+
+        // Only the tracks have circuit parameters.
+        self.check(&res.proof_params)?;
+        let name = track(&res.proof_params).expect("the params were checked");
+
+        let partitions = res.proof_params.partitions();
+        ensure!(
+            snark.proofs.len() == partitions,
+            "expected {} partition proofs, got {}",
+            partitions,
+            snark.proofs.len()
+        );
+
+        let replica_id = id_from_str::<Domain>(&res.seed.seed)?;
+        let layers = res
+            .proof_params
+            .as_zigzag_params()
+            .ok_or_else(|| format_err!("missing zigzag params"))?;
+        let comm_r_star = res
+            .comm_r_star
+            .ok_or_else(|| format_err!("missing comm_r_star"))?
+            .domain()?;
+
+        let pp = setup::<PedersenHasher>(&res.proof_params, layers, graph_seed)?;
+        let pvk = groth16::prepare_verifying_key(&verifying_key(name)?);
+
+        let pub_inputs = layered_drgporep::PublicInputs::<Domain> {
+            replica_id,
+            tau: Some(PedersenHasher::domain_tau(&res.tau)?),
+            comm_r_star,
+            k: None,
+        };
+
+        for (k, proof) in snark.proofs.iter().enumerate() {
+            let bytes = hex::decode(proof)?;
+            let proof = groth16::Proof::<Bls12>::read(&bytes[..])?;
+            let inputs = public_inputs(
+                &pp,
+                &Vanilla::with_partition(pub_inputs.clone(), Some(k)),
+                k,
+            );
+
+            if !groth16::verify_proof(&pvk, &proof, &inputs)? {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
@@ -0,0 +1,284 @@
+use failure::format_err;
+use memmap::MmapOptions;
+use storage_proofs::drgporep::DrgParams;
+use storage_proofs::drgraph::Graph;
+use storage_proofs::hasher::Hasher;
+use storage_proofs::layered_drgporep::{self, LayerChallenges, Layers};
+use storage_proofs::porep::PoRep;
+use storage_proofs::proof::ProofScheme;
+use storage_proofs::vde;
+use storage_proofs::zigzag_drgporep::*;
+
+use super::{audit_proofs, GameHasher, GameScheme};
+use crate::bench;
+use crate::extraction::{ExtractedRange, Extraction, NodeRange};
+use crate::models::proof::{self, ProofType};
+use crate::models::seed::Seed;
+use crate::proofs::id_from_str;
+
+/// Layered DRG PoRep with zigzag expansion.
+pub struct ZigZagScheme;
+
+pub(super) type PublicParams<H> =
+    <ZigZagDrgPoRep<'static, H> as ProofScheme<'static>>::PublicParams;
+
+pub(super) fn setup<H: GameHasher>(
+    params: &proof::Params,
+    layers: (usize, LayerChallenges),
+    graph_seed: [u32; 7],
+) -> Result<PublicParams<H>, failure::Error> {
+    let (expansion_degree, layer_challenges) = layers;
+
+    let sp = layered_drgporep::SetupParams {
+        drg: DrgParams {
+            nodes: params.size / 32,
+            degree: params.degree,
+            expansion_degree,
+            seed: graph_seed,
+        },
+        sloth_iter: params.vde,
+        layer_challenges,
+    };
+
+    Ok(ZigZagDrgPoRep::<H>::setup(&sp)?)
+}
+
+/// Decodes `ranges` from a replica of the seed data, and proves their
+/// inclusion in the original data.
+fn unseal<H: GameHasher>(
+    params: &proof::Params,
+    seed: &Seed,
+    replica: &[u8],
+    ranges: &[NodeRange],
+    graph_seed: [u32; 7],
+) -> Result<Extraction, failure::Error> {
+    let replica_id = id_from_str::<H::Domain>(&seed.seed)?;
+    let layers = params
+        .as_zigzag_params()
+        .ok_or_else(|| format_err!("missing zigzag params"))?;
+
+    let pp = setup::<H>(params, layers, graph_seed)?;
+
+    eprintln!("running extract");
+    let original = ZigZagDrgPoRep::<H>::extract_all(&pp, &replica_id, replica)?;
+
+    let mut nodes = Vec::new();
+    let ranges = ranges
+        .iter()
+        .map(|range| {
+            let bytes = original
+                .get(range.start * 32..(range.start + range.len) * 32)
+                .ok_or_else(|| {
+                    format_err!("range {}+{} is out of bounds", range.start, range.len)
+                })?;
+            nodes.extend(range.start..range.start + range.len);
+
+            Ok(ExtractedRange {
+                start: range.start,
+                len: range.len,
+                data: hex::encode(bytes),
+            })
+        })
+        .collect::<Result<Vec<_>, failure::Error>>()?;
+
+    eprintln!("proving {} nodes", nodes.len());
+    let proofs = audit_proofs(&original, params.hasher, &nodes)?;
+
+    Ok(Extraction { ranges, proofs })
+}
+
+/// Encodes a copy of `data` one layer at a time, the way `replicate` does,
+/// to time each layer on its own. `replicate` only reports the total, so this
+/// is an extra pass, left out of the total of the benchmark.
+fn replicate_layers<H: GameHasher>(
+    pp: &PublicParams<H>,
+    replica_id: &H::Domain,
+    data: &[u8],
+) -> Result<(), failure::Error> {
+    let file = tempfile::tempfile()?;
+    file.set_len(data.len() as u64)?;
+    let mut copy = unsafe { MmapOptions::new().map_mut(&file)? };
+    copy.copy_from_slice(data);
+
+    let mut graph = pp.graph.clone();
+    for layer in 0..pp.layer_challenges.layers() {
+        bench::separate_stage(&format!("replicate layer {}", layer + 1), || {
+            graph.merkle_tree(&copy)?;
+            vde::encode(&graph, pp.sloth_iter, replica_id, &mut copy)
+        })?;
+        graph = ZigZagDrgPoRep::<H>::transform(&graph);
+    }
+
+    Ok(())
+}
+
+/// Replicates `data` for `seed` and proves it, leaving the replica in `data`.
+fn seal<H: GameHasher>(
+    prover: String,
+    params: proof::Params,
+    seed: Seed,
+    data: &mut [u8],
+    graph_seed: [u32; 7],
+) -> Result<(proof::Response, PublicParams<H>), failure::Error> {
+    let replica_id = id_from_str::<H::Domain>(&seed.seed)?;
+
+    let layers = params
+        .as_zigzag_params()
+        .ok_or_else(|| format_err!("missing zigzag params"))?;
+
+    eprintln!("running setup");
+    let pp = bench::stage("setup", || setup::<H>(&params, layers, graph_seed))?;
+
+    if bench::recording() {
+        eprintln!("timing layers");
+        replicate_layers::<H>(&pp, &replica_id, data)?;
+    }
+
+    eprintln!("running replicate");
+
+    let (tau, aux) = bench::stage("replicate", || {
+        ZigZagDrgPoRep::<H>::replicate(&pp, &replica_id, data, None)
+    })?;
+
+    let pub_inputs = layered_drgporep::PublicInputs::<<H as Hasher>::Domain> {
+        replica_id,
+        tau: Some(tau.simplify()),
+        comm_r_star: tau.comm_r_star,
+        k: None,
+    };
+
+    let priv_inputs = layered_drgporep::PrivateInputs {
+        aux,
+        tau: tau.layer_taus.clone(),
+    };
+
+    // Every partition is proven and verified with its own `k`.
+    let partitions = params.partitions();
+    eprintln!("generating {} partition proofs", partitions);
+
+    let pr = bench::stage("prove", || {
+        ZigZagDrgPoRep::<H>::prove_all_partitions(&pp, &pub_inputs, &priv_inputs, partitions)
+    })?;
+
+    let verified = bench::stage("verify", || {
+        ZigZagDrgPoRep::<H>::verify_all_partitions(&pp, &pub_inputs, &pr)
+    })?;
+    if !verified {
+        return Err(format_err!("verification failed"));
+    }
+
+    let res = proof::Response {
+        format_version: proof::FORMAT_VERSION,
+        prover,
+        seed,
+        proof_params: params,
+        proof: H::zigzag_proof(pr),
+        comm_r_star: Some(H::commitment(tau.comm_r_star)),
+        tau: H::tau(tau.simplify()),
+        replicas: Vec::new(),
+    };
+
+    Ok(res)
+}
+
+fn verify<H: GameHasher>(
+    res: &proof::ReplicaRef,
+    graph_seed: [u32; 7],
+) -> Result<bool, failure::Error> {
+    let proofs = H::as_zigzag(&res.proof).ok_or_else(|| format_err!("not a zigzag proof"))?;
+    let partitions = res.proof_params.partitions();
+    if proofs.len() != partitions {
+        return Err(format_err!(
+            "expected {} partition proofs, got {}",
+            partitions,
+            proofs.len()
+        ));
+    }
+
+    let replica_id = id_from_str::<H::Domain>(&res.seed.seed)?;
+    let layers = res
+        .proof_params
+        .as_zigzag_params()
+        .ok_or_else(|| format_err!("missing zigzag params"))?;
+    let comm_r_star = res
+        .comm_r_star
+        .ok_or_else(|| format_err!("missing comm_r_star"))?
+        .domain()?;
+
+    let pp = setup::<H>(&res.proof_params, layers, graph_seed)?;
+
+    let tau = H::domain_tau(&res.tau)?;
+
+    for (k, proof) in proofs.iter().enumerate() {
+        let pub_inputs = layered_drgporep::PublicInputs::<<H as Hasher>::Domain> {
+            replica_id,
+            tau: Some(tau),
+            comm_r_star,
+            k: Some(k),
+        };
+
+        if !ZigZagDrgPoRep::<H>::verify(&pp, &pub_inputs, proof)? {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+impl GameScheme for ZigZagScheme {
+    fn typ(&self) -> ProofType {
+        ProofType::Zigzag
+    }
+
+    fn name(&self) -> &'static str {
+        "zigzag"
+    }
+
+    fn params(&self, params: proof::Params, zigzag: proof::ZigZagParams) -> proof::Params {
+        proof::Params {
+            zigzag: Some(zigzag),
+            ..params
+        }
+    }
+
+    fn check(&self, params: &proof::Params) -> Result<(), failure::Error> {
+        match params.zigzag {
+            Some(ref zigzag) => zigzag.check(),
+            None => Err(format_err!("zigzag params are missing")),
+        }
+    }
+
+    fn seal(
+        &self,
+        prover: String,
+        params: proof::Params,
+        seed: Seed,
+        data: &mut [u8],
+        graph_seed: [u32; 7],
+    ) -> Result<proof::Response, failure::Error> {
+        let hasher = params.hasher;
+        with_hasher!(hasher, seal(prover, params, seed, data, graph_seed))
+    }
+
+    fn verify(
+        &self,
+        res: &proof::ReplicaRef,
+        graph_seed: [u32; 7],
+    ) -> Result<bool, failure::Error> {
+        with_hasher!(res.proof.hasher(), verify(res, graph_seed))
+    }
+
+    fn unseal(
+        &self,
+        params: &proof::Params,
+        seed: &Seed,
+        replica: &[u8],
+        ranges: &[NodeRange],
+        graph_seed: [u32; 7],
+    ) -> Result<Extraction, failure::Error> {
+        with_hasher!(
+            params.hasher,
+            unseal(params, seed, replica, ranges, graph_seed)
+        )
+    }
+}
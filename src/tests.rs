@@ -1,9 +1,16 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
 use lazy_static::lazy_static;
 use parking_lot::Mutex;
 use rand::{thread_rng, Rng};
-use rocket::http::{ContentType, Status};
+use rocket::http::{ContentType, Header, Status};
 use rocket::local::Client;
 
+use crate::client::{rewind, Body, Reply, Transport};
+use crate::compact;
+use crate::config::{GameConfig, DEFAULT_GRAPH_SEED};
+use crate::encoding::{self, Encoding};
 use crate::models::leaderboard::{Entry, PrintableEntry};
 use crate::models::proof;
 use crate::models::seed::Seed;
@@ -16,6 +23,76 @@ lazy_static! {
     static ref DB_LOCK: Mutex<()> = Mutex::new(());
 }
 
+/// Tracks the current and the peak number of bytes allocated by each
+/// thread, so tests can report how much memory an operation needs without
+/// counting the tests running next to it.
+struct PeakAlloc;
+
+thread_local! {
+    // Signed, as memory allocated by one thread may be freed by another.
+    static ALLOCATED: Cell<isize> = Cell::new(0);
+    static PEAK_ALLOCATED: Cell<isize> = Cell::new(0);
+}
+
+impl PeakAlloc {
+    fn add(size: usize) {
+        // Not available while the thread is torn down, when nothing is
+        // measured anymore.
+        let _ = ALLOCATED.try_with(|allocated| {
+            let current = allocated.get() + size as isize;
+            allocated.set(current);
+            let _ = PEAK_ALLOCATED.try_with(|peak| {
+                if current > peak.get() {
+                    peak.set(current);
+                }
+            });
+        });
+    }
+
+    fn sub(size: usize) {
+        let _ = ALLOCATED.try_with(|allocated| allocated.set(allocated.get() - size as isize));
+    }
+
+    /// Resets the peak of this thread to its current allocation and returns
+    /// it.
+    fn reset_peak() -> isize {
+        let current = ALLOCATED.with(Cell::get);
+        PEAK_ALLOCATED.with(|peak| peak.set(current));
+        current
+    }
+
+    fn peak() -> isize {
+        PEAK_ALLOCATED.with(Cell::get)
+    }
+}
+
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            PeakAlloc::add(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        PeakAlloc::sub(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            PeakAlloc::add(new_size);
+            PeakAlloc::sub(layout.size());
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static GLOBAL: PeakAlloc = PeakAlloc;
+
 macro_rules! run_test {
     (|$client:ident, $conn:ident| $block:expr) => {{
         let _lock = DB_LOCK.lock();
@@ -44,7 +121,7 @@ fn test_cache_headers() {
 fn test_insertion() {
     run_test!(|client, conn| {
         // Get the tasks before making changes.
-        let init_leaderboard = Entry::all(&conn).unwrap();
+        let init_leaderboard = Entry::all(None, None, &conn).unwrap();
         // Get a seed
         let mut response = client.get("/api/seed").dispatch();
         assert_eq!(response.status(), Status::Ok);
@@ -58,14 +135,17 @@ fn test_insertion() {
 
         let params = proof::Params {
             typ: proof::ProofType::DrgPoRep,
+            hasher: proof::HasherType::Pedersen,
             size: 1024,
             challenge_count: 1,
             vde: 1,
             degree: 3,
+            replicas: 1,
             zigzag: None,
         };
 
-        let proof_value = proofs::porep_work(id.clone(), params, seed.clone());
+        let proof_value =
+            serde_json::to_string(&proofs::porep_work(id.clone(), params, seed.clone())).unwrap();
 
         // Issue a request to insert a result
         let response = client
@@ -77,7 +157,7 @@ fn test_insertion() {
         assert_eq!(response.status(), Status::Ok);
 
         // Ensure we have one more entry the database.
-        let new_leaderboard = Entry::all(&conn).unwrap();
+        let new_leaderboard = Entry::all(None, None, &conn).unwrap();
         assert_eq!(new_leaderboard.len(), init_leaderboard.len() + 1);
 
         // Ensure the entry exists
@@ -99,160 +179,2412 @@ fn test_insertion() {
 }
 
 #[test]
-fn test_many_insertions() {
+fn test_hasher_tracks() {
     run_test!(|client, conn| {
-        let mut rng = thread_rng();
-
-        // Get the tasks before making changes.
-        let init_leaderboard = Entry::all(&conn).unwrap();
-
-        let mut prev_len = init_leaderboard.len();
+        let mut response = client.get("/api/seed").dispatch();
+        let seed: Seed = serde_json::from_str(&response.body_string().unwrap()).unwrap();
 
-        for _ in 0..2 {
-            // Get a seed
-            let mut response = client.get("/api/seed").dispatch();
-            assert_eq!(response.status(), Status::Ok);
-            let body = response.body_string().unwrap();
-            let seed: Seed = serde_json::from_str(&body).unwrap();
+        // Pedersen params serialize as they did before hashers were
+        // selectable, so that their id in the params table stays the same.
+        let pedersen = small_porep(&seed);
+        let json = serde_json::to_value(&pedersen).unwrap();
+        assert!(json["proof_params"].get("hasher").is_none());
 
-            let id: String = rng.gen_ascii_chars().take(12).collect();
+        let mut rng = thread_rng();
+        let id: String = rng.gen_ascii_chars().take(12).collect();
 
-            let params1 = proof::Params {
+        for hasher in &[proof::HasherType::Blake2s, proof::HasherType::Sha256] {
+            let params = proof::Params {
                 typ: proof::ProofType::DrgPoRep,
+                hasher: *hasher,
                 size: 1024,
                 challenge_count: 1,
                 vde: 1,
                 degree: 3,
+                replicas: 1,
                 zigzag: None,
             };
-            let params2 = proof::Params {
-                typ: proof::ProofType::DrgPoRep,
-                size: 1024,
-                challenge_count: 2,
-                vde: 1,
-                degree: 3,
-                zigzag: None,
-            };
+            let res = proofs::porep_work(id.clone(), params, seed.clone());
+            assert_eq!(res.proof.hasher(), *hasher);
 
-            let params3 = proof::Params {
-                typ: proof::ProofType::Zigzag,
-                size: 1024,
-                challenge_count: 1,
-                vde: 1,
-                degree: 3,
-                zigzag: Some(proof::ZigZagParams {
-                    expansion_degree: 8,
-                    layers: 2,
-                    is_tapered: true,
-                    taper_layers: 2,
-                    taper: 1.2,
-                }),
-            };
+            let response = client
+                .post("/api/proof")
+                .header(ContentType::JSON)
+                .body(serde_json::to_string(&res).unwrap())
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
 
-            let proof_value1 = proofs::porep_work(id.clone(), params1.clone(), seed.clone());
-            let proof_value2 = proofs::porep_work(id.clone(), params2.clone(), seed.clone());
-            let proof_value3 = proofs::porep_work(id.clone(), params3.clone(), seed.clone());
+            // A proof claiming another hasher than it was made with.
+            let mut forged = res.clone();
+            forged.proof_params.hasher = proof::HasherType::Pedersen;
+            let mut response = client
+                .post("/api/proof")
+                .header(ContentType::JSON)
+                .body(serde_json::to_string(&forged).unwrap())
+                .dispatch();
+            assert_eq!(response.status(), Status::UnprocessableEntity);
+            let body: serde_json::Value =
+                serde_json::from_str(&response.body_string().unwrap()).unwrap();
+            assert_eq!(body["code"], "invalid_proof");
+        }
 
-            // First params
-            let old_repl_time = {
-                // slower proof
-                use std::{thread, time};
-                thread::sleep(time::Duration::from_millis(2000));
+        // A proof claiming another scheme than it was made with.
+        let mut forged = pedersen.clone();
+        forged.prover = id.clone();
+        forged.proof_params.typ = proof::ProofType::Zigzag;
+        forged.proof_params.zigzag = Some(proof::ZigZagParams {
+            expansion_degree: 8,
+            layers: 2,
+            is_tapered: false,
+            taper_layers: 0,
+            taper: 0.,
+            partitions: 1,
+        });
+        let mut response = client
+            .post("/api/proof")
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&forged).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let body: serde_json::Value =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(body["code"], "invalid_proof");
 
-                let response = client
-                    .post("/api/proof")
-                    .header(ContentType::JSON)
-                    .body(proof_value1)
-                    .dispatch();
-                assert_eq!(response.status(), Status::Ok);
+        // One entry per track.
+        let entries = Entry::all(None, None, &conn).unwrap();
+        assert_eq!(entries.iter().filter(|e| e.prover == id).count(), 2);
 
-                // Ensure we have one more entry the database.
-                let new_leaderboard = Entry::all(&conn).unwrap();
-                assert_eq!(new_leaderboard.len(), prev_len + 1);
-                prev_len = new_leaderboard.len();
-                println!("{:?}", new_leaderboard);
-                new_leaderboard
-                    .iter()
-                    .find(|entry| &entry.prover == &id)
-                    .unwrap()
-                    .repl_time
-            };
+        let mut response = client.get("/api/leaderboard?hasher=sha256").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let entries: Vec<PrintableEntry> =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert!(entries
+            .iter()
+            .all(|e| e.params.hasher == proof::HasherType::Sha256));
+        assert_eq!(entries.iter().filter(|e| e.prover == id).count(), 1);
 
-            // First params, same prover, but faster
-            {
-                let mut response = client.get("/api/seed").dispatch();
-                assert_eq!(response.status(), Status::Ok);
-                let body = response.body_string().unwrap();
-                let seed: Seed = serde_json::from_str(&body).unwrap();
+        let response = client.get("/api/leaderboard?hasher=md5").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    })
+}
 
-                let proof_value = proofs::porep_work(id.clone(), params1, seed.clone());
-                let response = client
-                    .post("/api/proof")
-                    .header(ContentType::JSON)
-                    .body(proof_value)
-                    .dispatch();
-                assert_eq!(response.status(), Status::Ok);
+#[test]
+fn test_zigzag_partitions() {
+    use crate::schemes::{self, GameScheme};
 
-                // Ensure we don't have another entry
-                let new_leaderboard = Entry::all(&conn).unwrap();
-                assert_eq!(new_leaderboard.len(), prev_len);
+    run_test!(|client, _conn| {
+        let mut response = client.get("/api/seed").dispatch();
+        let seed: Seed = serde_json::from_str(&response.body_string().unwrap()).unwrap();
 
-                // check that the entry was updated
-                let repl_time = new_leaderboard
+        let mut rng = thread_rng();
+        let id: String = rng.gen_ascii_chars().take(12).collect();
+
+        let params = proof::Params {
+            typ: proof::ProofType::Zigzag,
+            hasher: proof::HasherType::Pedersen,
+            size: 1024,
+            challenge_count: 1,
+            vde: 1,
+            degree: 3,
+            replicas: 1,
+            zigzag: Some(proof::ZigZagParams {
+                expansion_degree: 8,
+                layers: 2,
+                is_tapered: false,
+                taper_layers: 0,
+                taper: 0.,
+                partitions: 2,
+            }),
+        };
+        // Zigzag params are never made up, a prover without them is refused.
+        let bare = proof::Params {
+            zigzag: None,
+            ..params.clone()
+        };
+        assert!(schemes::ZigZagScheme
+            .prove(id.clone(), bare, seed.clone(), DEFAULT_GRAPH_SEED)
+            .is_err());
+
+        let res = proofs::zigzag_work(id, params, seed);
+        match res.proof {
+            proof::Proof::Zigzag(ref partitions) => assert_eq!(partitions.len(), 2),
+            _ => panic!("expected a zigzag proof"),
+        }
+
+        let json = serde_json::to_value(&res).unwrap();
+        assert_eq!(json["proof_params"]["zigzag"]["partitions"], 2);
+
+        // Dropping a partition, or claiming one more than was proven.
+        let mut missing = res.clone();
+        if let proof::Proof::Zigzag(ref mut partitions) = missing.proof {
+            partitions.pop();
+        }
+        let mut extra = res.clone();
+        extra.proof_params.zigzag.as_mut().unwrap().partitions = 3;
+
+        for forged in &[missing, extra] {
+            let mut response = client
+                .post("/api/proof/verify")
+                .header(ContentType::JSON)
+                .body(serde_json::to_string(forged).unwrap())
+                .dispatch();
+            let verdict: serde_json::Value =
+                serde_json::from_str(&response.body_string().unwrap()).unwrap();
+            assert_eq!(verdict["valid"], false);
+            assert_eq!(verdict["code"], "invalid_proof");
+        }
+
+        let response = client
+            .post("/api/proof")
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&res).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+    })
+}
+
+#[test]
+fn test_snark_track() {
+    use crate::schemes;
+
+    run_test!(|client, _conn| {
+        let mut response = client.get("/api/seed").dispatch();
+        let seed: Seed = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+
+        let mut rng = thread_rng();
+        let id: String = rng.gen_ascii_chars().take(12).collect();
+
+        let params = proof::Params {
+            typ: proof::ProofType::ZigzagSnark,
+            hasher: proof::HasherType::Pedersen,
+            size: 1024,
+            challenge_count: 1,
+            vde: 1,
+            degree: 3,
+            replicas: 1,
+            zigzag: Some(proof::ZigZagParams {
+                expansion_degree: 8,
+                layers: 2,
+                is_tapered: false,
+                taper_layers: 0,
+                taper: 0.,
+                partitions: 1,
+            }),
+        };
+        // The parameters of a setup of this test, the server reads them from
+        // the same directory.
+        let dir = tempfile::tempdir().unwrap();
+        schemes::setup_snark_parameters(dir.path(), "zigzag-snark-1kib", DEFAULT_GRAPH_SEED)
+            .unwrap();
+        schemes::use_snark_parameters(dir.path());
+
+        let res = proofs::work(id, params, seed, DEFAULT_GRAPH_SEED);
+
+        let verify = |res: &proof::Response| {
+            let mut response = client
+                .post("/api/proof/verify")
+                .header(ContentType::JSON)
+                .body(serde_json::to_string(res).unwrap())
+                .dispatch();
+            serde_json::from_str::<serde_json::Value>(&response.body_string().unwrap()).unwrap()
+        };
+
+        let verdict = verify(&res);
+        assert_eq!(verdict["valid"], true);
+        assert!(verdict["synthesis_time_ms"].is_u64());
+        assert!(verdict["proving_time_ms"].is_u64());
+
+        // The reported times are kept with the leaderboard entry.
+        let response = client
+            .post("/api/proof")
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&res).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let entries: Vec<PrintableEntry> = serde_json::from_str(
+            &client
+                .get("/api/leaderboard")
+                .dispatch()
+                .body_string()
+                .unwrap(),
+        )
+        .unwrap();
+        let entry = entries.iter().find(|e| e.prover == res.prover).unwrap();
+        if let proof::Proof::ZigzagSnark(ref snark) = res.proof {
+            assert_eq!(
+                entry.synthesis_time_ms,
+                Some(snark.synthesis_time_ms as i64)
+            );
+            assert_eq!(entry.proving_time_ms, Some(snark.proving_time_ms as i64));
+        }
+
+        // Flipping a byte of the groth16 proof.
+        let mut tampered = res.clone();
+        if let proof::Proof::ZigzagSnark(ref mut snark) = tampered.proof {
+            let mut bytes = hex::decode(&snark.proofs[0]).unwrap();
+            bytes[0] ^= 1;
+            snark.proofs[0] = hex::encode(bytes);
+        }
+        let verdict = verify(&tampered);
+        assert_eq!(verdict["valid"], false);
+        assert_eq!(verdict["code"], "invalid_proof");
+
+        // Only the params of the tracks have circuit parameters.
+        let mut large = res.clone();
+        large.proof_params.size = 128 * 1024;
+        let mut other = res.clone();
+        other.proof_params.challenge_count = 2;
+        for untracked in &[large, other] {
+            let verdict = verify(untracked);
+            assert_eq!(verdict["valid"], false);
+            assert_eq!(verdict["code"], "params_not_allowed");
+        }
+    })
+}
+
+#[test]
+fn test_extraction_race() {
+    use crate::extraction::{self, Challenge};
+    use crate::models::extraction::ExtractionEntry;
+
+    run_test!(|client, conn| {
+        let mut response = client.get("/api/seed").dispatch();
+        let seed: Seed = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+
+        let mut rng = thread_rng();
+        let id: String = rng.gen_ascii_chars().take(12).collect();
+
+        let params = proof::Params {
+            typ: proof::ProofType::Zigzag,
+            hasher: proof::HasherType::Pedersen,
+            size: 1024,
+            challenge_count: 1,
+            vde: 1,
+            degree: 3,
+            replicas: 1,
+            zigzag: Some(proof::ZigZagParams {
+                expansion_degree: 8,
+                layers: 2,
+                is_tapered: false,
+                taper_layers: 0,
+                taper: 0.,
+                partitions: 1,
+            }),
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let replica = dir.path().join("replica");
+        let res = proofs::seed_seal_work(id.clone(), params, seed, DEFAULT_GRAPH_SEED, &replica);
+
+        let post = |path: &str, body: String| {
+            client
+                .post(path.to_string())
+                .header(ContentType::JSON)
+                .body(body)
+                .dispatch()
+        };
+        let code = |response: &mut rocket::local::LocalResponse| {
+            let body: serde_json::Value =
+                serde_json::from_str(&response.body_string().unwrap()).unwrap();
+            body["code"].as_str().unwrap().to_string()
+        };
+
+        // The ranges are only issued once the replica is accepted.
+        let mut response = post("/api/extraction", serde_json::to_string(&res).unwrap());
+        assert_eq!(response.status(), Status::Ok);
+        let challenge: Challenge = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(challenge.ranges.len(), extraction::RANGE_COUNT);
+        assert!(challenge.ranges.iter().all(|r| r.start + r.len <= 32));
+
+        let answer = proofs::extraction_work(&res, &challenge, DEFAULT_GRAPH_SEED, &replica);
+        let path = format!("/api/extraction/{}?token={}", challenge.id, challenge.token);
+
+        // A range that was not decoded, a range that was not issued, and the
+        // proofs of another range.
+        let mut encoded = answer.clone();
+        encoded.ranges[0].data = "00".repeat(encoded.ranges[0].len * 32);
+        let mut moved = answer.clone();
+        moved.ranges[0].start += 1;
+        let mut swapped = answer.clone();
+        swapped.proofs = proofs::extraction_work(
+            &res,
+            &Challenge {
+                id: challenge.id,
+                ranges: challenge
+                    .ranges
                     .iter()
-                    .find(|entry| &entry.prover == &id)
-                    .unwrap()
-                    .repl_time;
-                assert!(
-                    repl_time < old_repl_time,
-                    "replication time was not updated: {} >= {}",
-                    repl_time,
-                    old_repl_time
-                );
-            }
+                    .map(|r| extraction::NodeRange {
+                        start: (r.start + 1) % (32 - r.len + 1),
+                        len: r.len,
+                    })
+                    .collect(),
+                token: challenge.token.clone(),
+            },
+            DEFAULT_GRAPH_SEED,
+            &replica,
+        )
+        .proofs;
 
-            // Second params
-            {
-                let response = client
-                    .post("/api/proof")
-                    .header(ContentType::JSON)
-                    .body(&proof_value2)
-                    .dispatch();
-                assert_eq!(response.status(), Status::Ok);
+        // Only the prover of the replica holds the token.
+        let other = extraction::token("not the key", challenge.id, &id);
+        for path in &[
+            format!("/api/extraction/{}", challenge.id),
+            format!("/api/extraction/{}?token={}", challenge.id, other),
+        ] {
+            let mut response = post(path, serde_json::to_string(&answer).unwrap());
+            assert_eq!(response.status(), Status::Unauthorized);
+            assert_eq!(code(&mut response), "unauthorized");
+        }
 
-                // Ensure we have one more entry the database.
-                let new_leaderboard = Entry::all(&conn).unwrap();
-                assert_eq!(new_leaderboard.len(), prev_len + 1);
-                prev_len = new_leaderboard.len();
-            }
+        for forged in &[encoded, moved, swapped] {
+            let mut response = post(&path, serde_json::to_string(forged).unwrap());
+            assert_eq!(response.status(), Status::UnprocessableEntity);
+            assert_eq!(code(&mut response), "invalid_proof");
+        }
 
-            // Third params
-            {
-                let response = client
-                    .post("/api/proof")
-                    .header(ContentType::JSON)
-                    .body(&proof_value3)
-                    .dispatch();
-                assert_eq!(response.status(), Status::Ok);
+        let response = post(&path, serde_json::to_string(&answer).unwrap());
+        assert_eq!(response.status(), Status::Ok);
 
-                // Ensure we have one more entry the database.
-                let new_leaderboard = Entry::all(&conn).unwrap();
-                assert_eq!(new_leaderboard.len(), prev_len + 1);
-                prev_len = new_leaderboard.len();
-            }
+        let entries = ExtractionEntry::all(&conn).unwrap();
+        let entry = entries.iter().find(|e| e.prover == id).unwrap();
+        let time = entry.extraction_time_ms;
 
-            // Clone of second params
-            {
-                let response = client
-                    .post("/api/proof")
-                    .header(ContentType::JSON)
-                    .body(&proof_value2)
-                    .dispatch();
-                assert_eq!(response.status(), Status::Ok);
+        // Answering again changes nothing.
+        let response = post(&path, serde_json::to_string(&answer).unwrap());
+        assert_eq!(response.status(), Status::Ok);
+        let entries = ExtractionEntry::all(&conn).unwrap();
+        let entry = entries.iter().find(|e| e.prover == id).unwrap();
+        assert_eq!(entry.extraction_time_ms, time);
 
-                let new_leaderboard = Entry::all(&conn).unwrap();
-                assert_eq!(new_leaderboard.len(), prev_len);
-            }
+        let mut response = post(
+            &format!("/api/extraction/0?token={}", challenge.token),
+            serde_json::to_string(&answer).unwrap(),
+        );
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(code(&mut response), "not_found");
+    })
+}
+
+#[test]
+fn test_audits() {
+    use crate::config::Audits;
+    use crate::models::audit::{Audit, AuditChallenge};
+    use crate::models::leaderboard::Params;
+    use crate::routes::proof::now;
+
+    run_test!(|client, conn| {
+        let mut response = client.get("/api/seed").dispatch();
+        let seed: Seed = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+
+        let mut rng = thread_rng();
+        let id: String = rng.gen_ascii_chars().take(12).collect();
+
+        let dir = tempfile::tempdir().unwrap();
+        let replica = dir.path().join("replica");
+        let params = proof::Params {
+            typ: proof::ProofType::DrgPoRep,
+            hasher: proof::HasherType::Pedersen,
+            size: 1024,
+            challenge_count: 1,
+            vde: 1,
+            degree: 3,
+            replicas: 1,
+            zigzag: None,
+        };
+        let res = proofs::seal_work(id.clone(), params, seed, DEFAULT_GRAPH_SEED, &replica);
+
+        let response = client
+            .post("/api/proof")
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&res).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        // Audits are off in Rocket.toml, schedule them by hand.
+        let config = Audits {
+            enabled: true,
+            count: 2,
+            window: 1,
+            deadline: 60,
+            challenges: 4,
+        };
+        let params_id = Params::insert(&res.proof_params, &conn).unwrap();
+        let start = now() - 1;
+        Audit::schedule(&res, params_id, &config, start, &conn).unwrap();
+
+        let mut response = client.get(format!("/api/audits?prover={}", id)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let challenges: Vec<AuditChallenge> =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(challenges.len(), 2);
+
+        let answer = |challenge: &AuditChallenge, replica: &std::path::Path| {
+            client
+                .post(format!("/api/audits/{}", challenge.id))
+                .header(ContentType::JSON)
+                .body(serde_json::to_string(&proofs::audit_work(challenge, replica)).unwrap())
+                .dispatch()
+        };
+
+        let response = answer(&challenges[0], &replica);
+        assert_eq!(response.status(), Status::Ok);
+
+        // Proofs of other data don't answer an audit.
+        let other = dir.path().join("other");
+        std::fs::write(&other, vec![0u8; 1024]).unwrap();
+        let mut response = answer(&challenges[1], &other);
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let body: serde_json::Value =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(body["code"], "invalid_proof");
+
+        // The second audit runs out, which demotes the entry.
+        Audit::expire(start + 1 + config.deadline, &conn).unwrap();
+
+        let mut response = answer(&challenges[1], &replica);
+        assert_eq!(response.status(), Status::Conflict);
+        let body: serde_json::Value =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(body["code"], "missed_audit");
+
+        let entries = Entry::all(None, None, &conn).unwrap();
+        let position = entries.iter().position(|e| e.prover == id).unwrap();
+        assert_eq!(entries[position].missed_audits, 1);
+        assert!(entries[..position].iter().all(|e| e.missed_audits == 0));
+    })
+}
+
+#[test]
+fn test_superseded_audits() {
+    use crate::config::Audits;
+    use crate::models::audit::Audit;
+    use crate::routes::proof::submit_at;
+
+    run_test!(|client, conn| {
+        let mut response = client.get("/api/seed").dispatch();
+        let seed: Seed = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        let issued = seed.timestamp as u64;
+
+        let mut rng = thread_rng();
+        let id: String = rng.gen_ascii_chars().take(12).collect();
+        let params = proof::Params {
+            typ: proof::ProofType::DrgPoRep,
+            hasher: proof::HasherType::Pedersen,
+            size: 1024,
+            challenge_count: 1,
+            vde: 1,
+            degree: 3,
+            replicas: 1,
+            zigzag: None,
+        };
+        let config = GameConfig {
+            audits: Audits {
+                enabled: true,
+                count: 2,
+                window: 1,
+                deadline: 3600,
+                challenges: 4,
+            },
+            ..GameConfig::default()
+        };
+        let comm_r = |res: &proof::Response| serde_json::to_string(&res.tau.comm_r).unwrap();
+
+        // A slow proof, then a faster one that replaces it on the leaderboard.
+        let slow = proofs::porep_work(id.clone(), params.clone(), seed.clone());
+        submit_at(&conn, &config, &slow, issued + 100).unwrap();
+        let fast = proofs::porep_work(id.clone(), params, seed);
+        submit_at(&conn, &config, &fast, issued + 50).unwrap();
+
+        // Only the audits of the replica on the leaderboard are left, and
+        // the dropped ones are not counted as missed.
+        let open = Audit::open(&id, issued + 200, &conn).unwrap();
+        assert_eq!(open.len(), 2);
+        assert!(open.iter().all(|(audit, _)| audit.comm_r == comm_r(&fast)));
+
+        let entries = Entry::all(None, None, &conn).unwrap();
+        let entry = entries.iter().find(|e| e.prover == id).unwrap();
+        assert_eq!(entry.repl_time, 50);
+        assert_eq!(entry.missed_audits, 0);
+    })
+}
+
+#[test]
+fn test_multi_replica_track() {
+    use crate::config::Audits;
+    use crate::models::audit::{Audit, AuditChallenge};
+    use crate::models::leaderboard;
+    use crate::routes::proof::now;
+
+    run_test!(|client, conn| {
+        let mut response = client.get("/api/seed").dispatch();
+        let seed: Seed = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+
+        // Single replica params serialize as they did before, so that their
+        // id in the params table stays the same.
+        let single = serde_json::to_value(&small_porep(&seed)).unwrap();
+        assert!(single["proof_params"].get("replicas").is_none());
+        assert!(single.get("replicas").is_none());
+
+        let mut rng = thread_rng();
+        let id: String = rng.gen_ascii_chars().take(12).collect();
+
+        let params = proof::Params {
+            typ: proof::ProofType::DrgPoRep,
+            hasher: proof::HasherType::Pedersen,
+            size: 1024,
+            challenge_count: 1,
+            vde: 1,
+            degree: 3,
+            replicas: 3,
+            zigzag: None,
+        };
+        let res = proofs::work(id.clone(), params, seed.clone(), DEFAULT_GRAPH_SEED);
+        assert_eq!(res.replicas.len(), 2);
+        assert_eq!(res.replica(0).unwrap().seed.seed, seed.seed);
+        assert_ne!(res.replica(1).unwrap().seed.seed, seed.seed);
+
+        // Dropping a replica, or swapping two of them.
+        let mut missing = res.clone();
+        missing.replicas.pop();
+        let mut swapped = res.clone();
+        swapped.replicas.swap(0, 1);
+
+        for forged in &[missing, swapped] {
+            let mut response = client
+                .post("/api/proof/verify")
+                .header(ContentType::JSON)
+                .body(serde_json::to_string(forged).unwrap())
+                .dispatch();
+            let verdict: serde_json::Value =
+                serde_json::from_str(&response.body_string().unwrap()).unwrap();
+            assert_eq!(verdict["valid"], false);
+            assert_eq!(verdict["code"], "invalid_proof");
+        }
+
+        let response = client
+            .post("/api/proof")
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&res).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let entries = Entry::all(None, None, &conn).unwrap();
+        let entry = entries.iter().find(|e| e.prover == id).unwrap();
+        assert_eq!(entry.params.replicas, 3);
+        let expected = 3. * 1024. / f64::from(std::cmp::max(entry.repl_time, 1));
+        assert!((entry.throughput - expected).abs() < 1e-9);
+
+        // Every replica is audited.
+        let config = Audits {
+            enabled: true,
+            count: 1,
+            window: 1,
+            deadline: 60,
+            challenges: 4,
+        };
+        let params_id = leaderboard::Params::insert(&res.proof_params, &conn).unwrap();
+        Audit::schedule(&res, params_id, &config, now() - 1, &conn).unwrap();
+
+        let mut response = client.get(format!("/api/audits?prover={}", id)).dispatch();
+        let challenges: Vec<AuditChallenge> =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(challenges.len(), 3);
+        for k in 0..3 {
+            let replica = res.replica(k).unwrap();
+            let comm_r = serde_json::to_value(&replica.tau.comm_r).unwrap();
+            assert!(challenges
+                .iter()
+                .any(|c| serde_json::to_value(&c.comm_r).unwrap() == comm_r));
+        }
+
+        // Multi-replica entries are ranked by throughput, so sealing more
+        // replicas in a longer time can rank first.
+        let insert = |name: &str, size: usize, replicas: usize, repl_time: i32| {
+            let mut params = res.proof_params.clone();
+            params.size = size;
+            params.replicas = replicas;
+            let params_id = leaderboard::Params::insert(&params, &conn).unwrap();
+            Entry::insert(
+                &format!("{}-{}", id, name),
+                repl_time,
+                params_id,
+                None,
+                &conn,
+            )
+            .unwrap()
+        };
+        assert!(insert("many", 1024, 16, 10));
+        assert!(insert("few", 1024, 2, 5));
+        // Single replicas are still ranked by replication time, whatever
+        // their throughput.
+        assert!(insert("small", 1024, 1, 5));
+        assert!(insert("large", 2048, 1, 7));
+
+        // Only better times are recorded.
+        assert!(!insert("many", 1024, 16, 10));
+        assert!(insert("many", 1024, 16, 9));
+
+        let entries = Entry::all(None, None, &conn).unwrap();
+        let rank = |name: &str| {
+            let prover = format!("{}-{}", id, name);
+            entries.iter().position(|e| e.prover == prover).unwrap()
+        };
+        assert!(rank("many") < rank("few"));
+        assert!(rank("small") < rank("large"));
+    })
+}
+
+#[test]
+fn test_proof_format_versions() {
+    run_test!(|client, _conn| {
+        let mut response = client.get("/api/seed").dispatch();
+        let seed: Seed = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+
+        let res = small_porep(&seed);
+        assert_eq!(res.format_version, proof::FORMAT_VERSION);
+
+        // Responses from before the format was versioned are of the first one.
+        let mut unversioned = serde_json::to_value(&res).unwrap();
+        unversioned
+            .as_object_mut()
+            .unwrap()
+            .remove("format_version");
+        let unversioned: proof::Response = serde_json::from_value(unversioned).unwrap();
+        assert_eq!(unversioned.format_version, 1);
+
+        // Proofs of a later format are recognized as such, even when their
+        // shape is unknown to this version.
+        let mut future = serde_json::to_value(&res).unwrap();
+        future["format_version"] = (proof::FORMAT_VERSION + 1).into();
+        future["proof"] = serde_json::json!({ "Zigzag": { "layers": "unknown" } });
+
+        for path in &["/api/proof/verify", "/api/proof"] {
+            let mut response = client
+                .post(*path)
+                .header(ContentType::JSON)
+                .body(future.to_string())
+                .dispatch();
+            let body: serde_json::Value =
+                serde_json::from_str(&response.body_string().unwrap()).unwrap();
+            assert_eq!(body["code"], "unsupported_format");
+            assert!(body["message"].as_str().unwrap().contains(&format!(
+                "unsupported proof format v{}",
+                proof::FORMAT_VERSION + 1
+            )));
+        }
+
+        let mut response = client
+            .post("/api/proof/verify")
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&unversioned).unwrap())
+            .dispatch();
+        let verdict: serde_json::Value =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(verdict["valid"], true);
+    })
+}
+
+#[test]
+fn test_sizes_with_units() {
+    use crate::presets;
+    use crate::units::{parse_cli_size, parse_size, Size};
+
+    run_test!(|client, _conn| {
+        assert_eq!(parse_size("16GiB", 1).unwrap(), 16 << 30);
+        assert_eq!(parse_size("2 kb", 1).unwrap(), 2000);
+        assert_eq!(parse_size("1024", 1024).unwrap(), 1 << 20);
+        assert!(parse_size("GiB", 1).is_err());
+        assert!(parse_size("1PiB", 1).is_err());
+        assert_eq!(parse_cli_size("1024").unwrap(), 1 << 20);
+        assert_eq!(parse_cli_size("16GiB").unwrap(), 16 << 30);
+        assert!(parse_cli_size("1024kB").is_err());
+        assert!(parse_cli_size("1 GB").is_err());
+        assert_eq!(Size(1 << 30).to_string(), "1GiB");
+
+        // Presets are the command line defaults, on the tracks of the board.
+        let preset = presets::get("zigzag-1gib").unwrap();
+        preset.check().unwrap();
+        assert_eq!(preset.size, 1 << 30);
+        assert!(presets::get("zigzag-1pib").is_none());
+        for name in presets::names() {
+            let params = presets::get(name).unwrap();
+            GameConfig::default().check_params(&params).unwrap();
+        }
+
+        let json = serde_json::to_value(&preset).unwrap();
+        let mut with_unit = json.clone();
+        with_unit["size"] = "1GiB".into();
+        let parsed: proof::Params = serde_json::from_value(with_unit).unwrap();
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), json);
+
+        let mut response = client.get("/api/seed").dispatch();
+        let seed: Seed = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        let res = small_porep(&seed);
+        let response = client
+            .post("/api/proof")
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&res).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let mut response = client.get("/api/leaderboard?size=1KiB").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let entries: Vec<PrintableEntry> =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert!(entries.iter().any(|e| e.prover == res.prover));
+        assert!(entries.iter().all(|e| e.params.size == 1024));
+
+        let mut response = client.get("/api/leaderboard?size=1MiB").dispatch();
+        let entries: Vec<PrintableEntry> =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert!(entries.iter().all(|e| e.prover != res.prover));
+
+        let response = client.get("/api/leaderboard?size=lots").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    })
+}
+
+#[test]
+fn test_bench_stages() {
+    use crate::bench;
+
+    // Holding the lock keeps other tests from sealing into the recording.
+    run_test!(|_client, _conn| {
+        let seed = Seed {
+            timestamp: 0,
+            seed: "00".repeat(32),
+        };
+        let params = proof::Params {
+            typ: proof::ProofType::Zigzag,
+            hasher: proof::HasherType::Pedersen,
+            size: 1024,
+            challenge_count: 1,
+            vde: 1,
+            degree: 3,
+            replicas: 1,
+            zigzag: Some(proof::ZigZagParams {
+                expansion_degree: 8,
+                layers: 2,
+                is_tapered: false,
+                taper_layers: 0,
+                taper: 0.,
+                partitions: 1,
+            }),
+        };
+
+        assert!(!bench::recording());
+        let (res, stages) = bench::record(|| proofs::zigzag_work("bench".into(), params, seed));
+        assert!(!bench::recording());
+        assert!(proofs::validate(&res, &GameConfig::default()).is_ok());
+
+        let names: Vec<_> = stages.iter().map(|stage| stage.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "generate data",
+                "setup",
+                "replicate layer 1",
+                "replicate layer 2",
+                "replicate",
+                "prove",
+                "verify",
+                "total",
+            ]
+        );
+
+        // The layers are timed in a pass of their own, outside the total.
+        let total = stages.last().unwrap();
+        for stage in &stages {
+            assert_eq!(stage.separate, stage.name.starts_with("replicate layer"));
+        }
+        let sealing: u64 = stages[..stages.len() - 1]
+            .iter()
+            .filter(|stage| !stage.separate)
+            .map(|stage| stage.wall_ms)
+            .sum();
+        assert!(sealing <= total.wall_ms);
+
+        let table = bench::table(&stages);
+        assert_eq!(table.lines().count(), stages.len() + 2);
+        assert!(table.contains("replicate layer 2 *"));
+    })
+}
+
+fn client_reply(mut response: rocket::local::LocalResponse) -> Reply {
+    Reply {
+        status: response.status().code,
+        retry_after: response
+            .headers()
+            .get_one("Retry-After")
+            .and_then(|value| value.parse().ok()),
+        body: response.body_string().unwrap_or_default(),
+    }
+}
+
+impl Transport for Client {
+    fn get(&self, path: &str) -> Result<Reply, failure::Error> {
+        Ok(client_reply(Client::get(self, path.to_string()).dispatch()))
+    }
+
+    fn post(
+        &self,
+        path: &str,
+        headers: &[(&'static str, &'static str)],
+        body: Body,
+    ) -> Result<Reply, failure::Error> {
+        let body = match body {
+            Body::Bytes(bytes) => bytes.to_vec(),
+            Body::File(file) => {
+                let mut bytes = Vec::new();
+                std::io::Read::read_to_end(&mut rewind(file)?.0, &mut bytes)?;
+                bytes
+            }
+        };
+
+        let mut request = Client::post(self, path.to_string()).body(body);
+        for &(name, value) in headers {
+            request.add_header(Header::new(name, value));
+        }
+
+        Ok(client_reply(request.dispatch()))
+    }
+}
+
+/// Fails the first few requests, like a dropped connection or a server
+/// that is briefly down would.
+struct Flaky {
+    client: Client,
+    failures: std::cell::Cell<usize>,
+}
+
+impl Flaky {
+    fn fail(&self) -> Option<Result<Reply, failure::Error>> {
+        let failures = self.failures.get();
+        if failures == 0 {
+            return None;
+        }
+        self.failures.set(failures - 1);
+
+        Some(if failures % 2 == 0 {
+            Err(failure::format_err!("connection reset"))
+        } else {
+            Ok(Reply {
+                status: 503,
+                retry_after: None,
+                body: String::new(),
+            })
+        })
+    }
+}
+
+impl Transport for Flaky {
+    fn get(&self, path: &str) -> Result<Reply, failure::Error> {
+        self.fail()
+            .unwrap_or_else(|| Transport::get(&self.client, path))
+    }
+
+    fn post(
+        &self,
+        path: &str,
+        headers: &[(&'static str, &'static str)],
+        body: Body,
+    ) -> Result<Reply, failure::Error> {
+        self.fail()
+            .unwrap_or_else(|| Transport::post(&self.client, path, headers, body))
+    }
+}
+
+#[test]
+fn test_play_client() {
+    use std::time::Duration;
+
+    use crate::client::{Game, Rejection};
+
+    run_test!(|client, _conn| {
+        let mut rng = thread_rng();
+        let id: String = rng.gen_ascii_chars().take(12).collect();
+
+        let params = proof::Params {
+            typ: proof::ProofType::DrgPoRep,
+            hasher: proof::HasherType::Pedersen,
+            size: 1024,
+            challenge_count: 1,
+            vde: 1,
+            degree: 3,
+            replicas: 1,
+            zigzag: None,
+        };
+
+        // Fetching the graph seed runs into a dropped connection and a server
+        // error before it gets through.
+        let game = Game::new(Flaky {
+            client,
+            failures: std::cell::Cell::new(2),
+        })
+        .with_retries(2, Duration::from_millis(0));
+
+        let outcome = game.play(&id, params).unwrap();
+        assert_eq!(outcome.entry.prover, id);
+        assert!(outcome.rank >= 1 && outcome.rank <= outcome.of);
+
+        // Proofs are built on the graphs of the server.
+        assert_eq!(game.graph_seed().unwrap(), GameConfig::default().graph_seed);
+
+        // Refused proofs are not retried, and come back with the error code.
+        let seed = game.seed().unwrap();
+        let mut forged = small_porep(&seed);
+        forged.seed.timestamp += 1;
+
+        let err = game.submit(&forged).unwrap_err();
+        let rejection = err.downcast_ref::<Rejection>().unwrap();
+        assert_eq!(rejection.code, "invalid_seed");
+        assert_eq!(rejection.status, 401);
+    })
+}
+
+#[test]
+fn test_many_insertions() {
+    run_test!(|client, conn| {
+        let mut rng = thread_rng();
+
+        // Get the tasks before making changes.
+        let init_leaderboard = Entry::all(None, None, &conn).unwrap();
+
+        let mut prev_len = init_leaderboard.len();
+
+        for _ in 0..2 {
+            // Get a seed
+            let mut response = client.get("/api/seed").dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            let body = response.body_string().unwrap();
+            let seed: Seed = serde_json::from_str(&body).unwrap();
+
+            let id: String = rng.gen_ascii_chars().take(12).collect();
+
+            let params1 = proof::Params {
+                typ: proof::ProofType::DrgPoRep,
+                hasher: proof::HasherType::Pedersen,
+                size: 1024,
+                challenge_count: 1,
+                vde: 1,
+                degree: 3,
+                replicas: 1,
+                zigzag: None,
+            };
+            let params2 = proof::Params {
+                typ: proof::ProofType::DrgPoRep,
+                hasher: proof::HasherType::Pedersen,
+                size: 1024,
+                challenge_count: 2,
+                vde: 1,
+                degree: 3,
+                replicas: 1,
+                zigzag: None,
+            };
+
+            let params3 = proof::Params {
+                typ: proof::ProofType::Zigzag,
+                hasher: proof::HasherType::Pedersen,
+                size: 1024,
+                challenge_count: 1,
+                vde: 1,
+                degree: 3,
+                replicas: 1,
+                zigzag: Some(proof::ZigZagParams {
+                    expansion_degree: 8,
+                    layers: 2,
+                    is_tapered: true,
+                    taper_layers: 2,
+                    taper: 1.2,
+                    partitions: 1,
+                }),
+            };
+
+            let proof_value1 = serde_json::to_string(&proofs::porep_work(
+                id.clone(),
+                params1.clone(),
+                seed.clone(),
+            ))
+            .unwrap();
+            let proof_value2 = serde_json::to_string(&proofs::porep_work(
+                id.clone(),
+                params2.clone(),
+                seed.clone(),
+            ))
+            .unwrap();
+            let proof_value3 = serde_json::to_string(&proofs::zigzag_work(
+                id.clone(),
+                params3.clone(),
+                seed.clone(),
+            ))
+            .unwrap();
+
+            // First params
+            let old_repl_time = {
+                // slower proof
+                use std::{thread, time};
+                thread::sleep(time::Duration::from_millis(2000));
+
+                let response = client
+                    .post("/api/proof")
+                    .header(ContentType::JSON)
+                    .body(proof_value1)
+                    .dispatch();
+                assert_eq!(response.status(), Status::Ok);
+
+                // Ensure we have one more entry the database.
+                let new_leaderboard = Entry::all(None, None, &conn).unwrap();
+                assert_eq!(new_leaderboard.len(), prev_len + 1);
+                prev_len = new_leaderboard.len();
+                println!("{:?}", new_leaderboard);
+                new_leaderboard
+                    .iter()
+                    .find(|entry| &entry.prover == &id)
+                    .unwrap()
+                    .repl_time
+            };
+
+            // First params, same prover, but faster
+            {
+                let mut response = client.get("/api/seed").dispatch();
+                assert_eq!(response.status(), Status::Ok);
+                let body = response.body_string().unwrap();
+                let seed: Seed = serde_json::from_str(&body).unwrap();
+
+                let proof_value =
+                    serde_json::to_string(&proofs::porep_work(id.clone(), params1, seed.clone()))
+                        .unwrap();
+                let response = client
+                    .post("/api/proof")
+                    .header(ContentType::JSON)
+                    .body(proof_value)
+                    .dispatch();
+                assert_eq!(response.status(), Status::Ok);
+
+                // Ensure we don't have another entry
+                let new_leaderboard = Entry::all(None, None, &conn).unwrap();
+                assert_eq!(new_leaderboard.len(), prev_len);
+
+                // check that the entry was updated
+                let repl_time = new_leaderboard
+                    .iter()
+                    .find(|entry| &entry.prover == &id)
+                    .unwrap()
+                    .repl_time;
+                assert!(
+                    repl_time < old_repl_time,
+                    "replication time was not updated: {} >= {}",
+                    repl_time,
+                    old_repl_time
+                );
+            }
+
+            // Second params
+            {
+                let response = client
+                    .post("/api/proof")
+                    .header(ContentType::JSON)
+                    .body(&proof_value2)
+                    .dispatch();
+                assert_eq!(response.status(), Status::Ok);
+
+                // Ensure we have one more entry the database.
+                let new_leaderboard = Entry::all(None, None, &conn).unwrap();
+                assert_eq!(new_leaderboard.len(), prev_len + 1);
+                prev_len = new_leaderboard.len();
+            }
+
+            // Third params
+            {
+                let response = client
+                    .post("/api/proof")
+                    .header(ContentType::JSON)
+                    .body(&proof_value3)
+                    .dispatch();
+                assert_eq!(response.status(), Status::Ok);
+
+                // Ensure we have one more entry the database.
+                let new_leaderboard = Entry::all(None, None, &conn).unwrap();
+                assert_eq!(new_leaderboard.len(), prev_len + 1);
+                prev_len = new_leaderboard.len();
+            }
+
+            // Clone of second params
+            {
+                let response = client
+                    .post("/api/proof")
+                    .header(ContentType::JSON)
+                    .body(&proof_value2)
+                    .dispatch();
+                assert_eq!(response.status(), Status::Ok);
+
+                let new_leaderboard = Entry::all(None, None, &conn).unwrap();
+                assert_eq!(new_leaderboard.len(), prev_len);
+            }
+        }
+    })
+}
+
+#[test]
+fn test_error_codes() {
+    run_test!(|client, _conn| {
+        let mut response = client.get("/api/does-not-exist").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        let body: serde_json::Value =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(body["code"], "not_found");
+
+        // A seed that was not issued by the server
+        let seed = Seed {
+            timestamp: 1,
+            seed: "00112233445566778899aabbccddeeff".into(),
+        };
+        let params = proof::Params {
+            typ: proof::ProofType::DrgPoRep,
+            hasher: proof::HasherType::Pedersen,
+            size: 1024,
+            challenge_count: 1,
+            vde: 1,
+            degree: 3,
+            replicas: 1,
+            zigzag: None,
+        };
+        let proof_value =
+            serde_json::to_string(&proofs::porep_work("forger".into(), params, seed)).unwrap();
+
+        let mut response = client
+            .post("/api/proof")
+            .header(ContentType::JSON)
+            .body(proof_value)
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        let body: serde_json::Value =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(body["status"], "failure");
+        assert_eq!(body["code"], "invalid_seed");
+
+        // Rocket's JSON guard doesn't say what it failed on.
+        let mut response = client
+            .post("/api/proof")
+            .header(ContentType::JSON)
+            .body(r#"{"format_version": 1}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let body: serde_json::Value =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(body["code"], "unprocessable_entity");
+
+        // Ours do, and the catcher answers with their error.
+        let mut response = client
+            .post("/api/proof")
+            .header(ContentType::JSON)
+            .header(Header::new("Content-Encoding", "gzip"))
+            .body(gzip_bytes(br#"{"format_version": 1}"#))
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let body: serde_json::Value =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(body["code"], "invalid_proof");
+        assert!(body["message"].as_str().unwrap().contains("proof"));
+    })
+}
+
+#[test]
+fn test_invalid_params() {
+    run_test!(|client, _conn| {
+        let mut response = client.get("/api/seed").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let seed: Seed = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+
+        let params = proof::Params {
+            typ: proof::ProofType::DrgPoRep,
+            hasher: proof::HasherType::Pedersen,
+            size: 1024,
+            challenge_count: 1,
+            vde: 1,
+            degree: 3,
+            replicas: 1,
+            zigzag: None,
+        };
+        let proof_value =
+            serde_json::to_string(&proofs::porep_work("tiny".into(), params, seed)).unwrap();
+
+        // The last one is within the protocol's bounds, but over the default
+        // verification budget.
+        for (field, value) in &[
+            ("size", 16),
+            ("degree", 0),
+            ("challenge_count", 0),
+            ("vde", 11),
+        ] {
+            let mut res: serde_json::Value = serde_json::from_str(&proof_value).unwrap();
+            res["proof_params"][field] = (*value).into();
+
+            let mut response = client
+                .post("/api/proof")
+                .header(ContentType::JSON)
+                .body(res.to_string())
+                .dispatch();
+            assert_eq!(response.status(), Status::UnprocessableEntity);
+            let body: serde_json::Value =
+                serde_json::from_str(&response.body_string().unwrap()).unwrap();
+            assert_eq!(body["code"], "params_not_allowed", "{} = {}", field, value);
+        }
+    })
+}
+
+#[test]
+fn test_scheme_registry() {
+    use crate::schemes;
+
+    for scheme in schemes::all() {
+        assert_eq!(schemes::get(&scheme.typ()).name(), scheme.name());
+        assert_eq!(schemes::by_name(scheme.name()).unwrap().typ(), scheme.typ());
+    }
+    assert!(schemes::by_name("unknown").is_none());
+
+    let seed = Seed {
+        timestamp: 0,
+        seed: "00".repeat(32),
+    };
+    let res = small_porep(&seed);
+    assert_eq!(res.proof.typ(), proof::ProofType::DrgPoRep);
+    assert_eq!(
+        schemes::for_proof(&res.proof).typ(),
+        proof::ProofType::DrgPoRep
+    );
+}
+
+#[test]
+fn test_game_config() {
+    use rocket::config::{Config, Environment, Value};
+
+    let load = |game: &str| {
+        let table: Value = game.parse().unwrap();
+        let config = Config::build(Environment::Development)
+            .extra("game", table)
+            .limits(rocket::config::Limits::new().limit("chunk", 1024))
+            .finalize()
+            .unwrap();
+        GameConfig::from_config(&config)
+    };
+
+    let config = load(
+        r#"
+        seed_ttl = 60
+        batch_max_items = 5
+
+        [budget]
+        max_layers = 4
+
+        [policy]
+        proof_types = ["Zigzag"]
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.seed_ttl, 60);
+    assert_eq!(config.batch_max_items, 5);
+    assert_eq!(config.max_ratio, GameConfig::default().max_ratio);
+    assert_eq!(config.budget.max_layers, 4);
+    assert_eq!(config.policy.proof_types, vec![proof::ProofType::Zigzag]);
+    assert_eq!(config.limits.chunk, 1024);
+
+    let params = proof::Params {
+        typ: proof::ProofType::DrgPoRep,
+        hasher: proof::HasherType::Pedersen,
+        size: 1024,
+        challenge_count: 1,
+        vde: 1,
+        degree: 3,
+        replicas: 1,
+        zigzag: None,
+    };
+    assert!(config.check_params(&params).is_err());
+    assert!(GameConfig::default().check_params(&params).is_ok());
+
+    for game in &[
+        "seed_ttl = 0",
+        "max_ratio = 0",
+        "key = \"\"",
+        "sed_ttl = 60",
+        "seed_ttl = \"1 week\"",
+        "[policy]\nproof_types = []",
+        "[policy]\nproof_types = [\"Snark\"]",
+        "[budget]\nmax_size = 16",
+    ] {
+        assert!(load(game).is_err(), "{:?} should be rejected", game);
+    }
+}
+
+#[test]
+fn test_gzip_streaming_peak_memory() {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    let seed = Seed {
+        timestamp: 0,
+        seed: "00".repeat(32),
+    };
+    let params = proof::Params {
+        typ: proof::ProofType::Zigzag,
+        hasher: proof::HasherType::Pedersen,
+        size: 1024,
+        challenge_count: 2,
+        vde: 1,
+        degree: 3,
+        replicas: 1,
+        zigzag: Some(proof::ZigZagParams {
+            expansion_degree: 8,
+            layers: 4,
+            is_tapered: false,
+            taper_layers: 0,
+            taper: 0.,
+            partitions: 1,
+        }),
+    };
+
+    // Build a large synthetic proof, by repeating the partition proof of a
+    // small one, and compress it the way clients do.
+    let (compressed, json_len) = {
+        let mut res: serde_json::Value =
+            serde_json::to_value(&proofs::zigzag_work("large".into(), params, seed)).unwrap();
+        let partition = res["proof"]["Zigzag"][0].clone();
+        let partition_len = serde_json::to_vec(&partition).unwrap().len();
+        let partitions = res["proof"]["Zigzag"].as_array_mut().unwrap();
+        while partitions.len() * partition_len < 64 * 1024 * 1024 {
+            partitions.push(partition.clone());
+        }
+
+        let json = serde_json::to_vec(&res).unwrap();
+        let mut gz = GzEncoder::new(Vec::new(), Compression::fast());
+        gz.write_all(&json).unwrap();
+        (gz.finish().unwrap(), json.len())
+    };
+
+    // Measured the way the data guards decode a proof, through its envelope.
+    let baseline = PeakAlloc::reset_peak();
+    let envelope: proof::Envelope = encoding::decode(
+        Encoding::Gzip,
+        &compressed[..],
+        u64::max_value(),
+        u64::max_value(),
+    )
+    .unwrap();
+    let res = envelope.open().unwrap();
+    // Decoding happens on this thread, so the peak of its allocations is the
+    // memory decoding needed.
+    let peak = (PeakAlloc::peak() - baseline) as usize;
+
+    println!(
+        "decoded {} bytes of json from {} bytes of gzip, peak memory: {} bytes ({:.2}x the json)",
+        json_len,
+        compressed.len(),
+        peak,
+        peak as f64 / json_len as f64
+    );
+
+    match res.proof {
+        proof::Proof::Zigzag(ref partitions) => assert!(partitions.len() > 1),
+        _ => panic!("expected a zigzag proof"),
+    }
+
+    // The old implementation held the full decompressed body as a string,
+    // next to the decoded proof.
+    assert!(
+        peak < json_len,
+        "decoding needed {} bytes for a {} byte document",
+        peak,
+        json_len
+    );
+}
+
+fn gzip_bytes(data: &[u8]) -> Vec<u8> {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    let mut gz = GzEncoder::new(Vec::new(), Compression::best());
+    gz.write_all(data).unwrap();
+    gz.finish().unwrap()
+}
+
+#[test]
+fn test_gzip_bomb() {
+    run_test!(|client, _conn| {
+        // 256MiB of whitespace is valid JSON padding, and compresses about
+        // a thousand times.
+        let mut bomb = vec![b' '; 256 * 1024 * 1024];
+        bomb.extend_from_slice(b"{}");
+        let bomb = gzip_bytes(&bomb);
+
+        let mut response = client
+            .post("/api/proof")
+            .header(ContentType::JSON)
+            .header(Header::new("Content-Encoding", "gzip"))
+            .body(bomb)
+            .dispatch();
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+        let body: serde_json::Value =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(body["code"], "payload_too_large");
+    })
+}
+
+#[test]
+fn test_gzip_decompressed_limit() {
+    let mut rng = thread_rng();
+
+    // Random hex compresses about two times, well within the ratio.
+    let raw: String = rng.gen_ascii_chars().take(64 * 1024).collect();
+    let json = serde_json::to_vec(&raw).unwrap();
+    let compressed = gzip_bytes(&json);
+
+    let res = encoding::decode::<String, _>(
+        Encoding::Gzip,
+        &compressed[..],
+        16 * 1024,
+        GameConfig::default().max_ratio,
+    );
+    match res {
+        Err(encoding::EncodingError::TooLarge(_)) => {}
+        other => panic!("expected the body to be too large: {:?}", other),
+    }
+
+    let res = encoding::decode::<String, _>(
+        Encoding::Gzip,
+        &compressed[..],
+        1024 * 1024,
+        GameConfig::default().max_ratio,
+    );
+    assert_eq!(res.unwrap(), raw);
+
+    // Ratio check on a body that stays within the size limit.
+    let bomb = gzip_bytes(&vec![b' '; 8 * 1024 * 1024]);
+    let res = encoding::decode::<String, _>(
+        Encoding::Gzip,
+        &bomb[..],
+        u64::max_value(),
+        GameConfig::default().max_ratio,
+    );
+    match res {
+        Err(encoding::EncodingError::TooLarge(_)) => {}
+        other => panic!("expected the body to be too large: {:?}", other),
+    }
+}
+
+#[test]
+fn test_compact_encoding() {
+    run_test!(|client, conn| {
+        let mut response = client.get("/api/seed").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let seed: Seed = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+
+        let mut rng = thread_rng();
+        let id: String = rng.gen_ascii_chars().take(12).collect();
+
+        let params = proof::Params {
+            typ: proof::ProofType::Zigzag,
+            hasher: proof::HasherType::Pedersen,
+            size: 32 * 1024,
+            challenge_count: 20,
+            vde: 1,
+            degree: 5,
+            replicas: 1,
+            zigzag: Some(proof::ZigZagParams {
+                expansion_degree: 8,
+                layers: 4,
+                is_tapered: false,
+                taper_layers: 0,
+                taper: 0.,
+                partitions: 1,
+            }),
+        };
+        let res = proofs::zigzag_work(id.clone(), params, seed);
+
+        let json = serde_json::to_vec(&res).unwrap();
+        let encoded = compact::encode(&res).unwrap();
+        println!(
+            "json: {} bytes, compact: {} bytes ({:.1}%)",
+            json.len(),
+            encoded.len(),
+            100. * encoded.len() as f64 / json.len() as f64
+        );
+        assert!(encoded.len() < json.len());
+
+        let decoded = compact::decode(&encoded[..]).unwrap().open().unwrap();
+        assert_eq!(
+            serde_json::to_value(&decoded).unwrap(),
+            serde_json::to_value(&res).unwrap()
+        );
+
+        // References are resolved while reading, so the node table has to
+        // come first.
+        let reordered =
+            serde_cbor::to_vec(&serde_json::json!({ "body": {}, "nodes": [] })).unwrap();
+        assert!(compact::decode(&reordered[..]).is_err());
+
+        // Well formed, but with fields of the wrong type.
+        #[derive(serde::Serialize)]
+        struct Encoded {
+            nodes: Vec<Vec<u64>>,
+            body: serde_json::Value,
+        }
+        let mistyped = serde_cbor::to_vec(&Encoded {
+            nodes: Vec::new(),
+            body: serde_json::json!({ "format_version": 1, "prover": 5 }),
+        })
+        .unwrap();
+        let mut response = client
+            .post("/api/proof")
+            .header(compact::content_type())
+            .body(mistyped)
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        assert!(response
+            .body_string()
+            .unwrap()
+            .contains("\"code\":\"invalid_proof\""));
+
+        let response = client
+            .post("/api/proof")
+            .header(compact::content_type())
+            .body(encoded)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let leaderboard = Entry::all(None, None, &conn).unwrap();
+        assert!(leaderboard.iter().any(|entry| entry.prover == id));
+    })
+}
+
+#[test]
+fn test_negotiate_encoding() {
+    let cases = [
+        ("", None),
+        ("identity", None),
+        ("gzip", Some(Encoding::Gzip)),
+        ("gzip, deflate, br", Some(Encoding::Brotli)),
+        ("gzip;q=0.5, br;q=0.8", Some(Encoding::Brotli)),
+        ("gzip;q=1.0, br;q=0.8", Some(Encoding::Gzip)),
+        ("br;q=0, gzip", Some(Encoding::Gzip)),
+        ("deflate, zstd;q=0.9", Some(Encoding::Deflate)),
+        ("*", Some(Encoding::Brotli)),
+        ("*;q=0.1, br;q=0, zstd;q=0", Some(Encoding::Gzip)),
+        ("x-unknown, gzip;q=0", None),
+    ];
+
+    for (accept, expected) in cases.iter() {
+        assert_eq!(Encoding::negotiate(accept), *expected, "{}", accept);
+    }
+}
+
+#[test]
+fn test_request_encodings() {
+    run_test!(|client, conn| {
+        let mut rng = thread_rng();
+
+        for encoding in &[
+            Encoding::Gzip,
+            Encoding::Deflate,
+            Encoding::Zstd,
+            Encoding::Brotli,
+        ] {
+            let mut response = client.get("/api/seed").dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            let seed: Seed = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+
+            let id: String = rng.gen_ascii_chars().take(12).collect();
+            let params = proof::Params {
+                typ: proof::ProofType::DrgPoRep,
+                hasher: proof::HasherType::Pedersen,
+                size: 1024,
+                challenge_count: 1,
+                vde: 1,
+                degree: 3,
+                replicas: 1,
+                zigzag: None,
+            };
+            let res = proofs::porep_work(id.clone(), params, seed);
+            let body = encoding.encode(&serde_json::to_vec(&res).unwrap()).unwrap();
+
+            let response = client
+                .post("/api/proof")
+                .header(ContentType::JSON)
+                .header(Header::new("Content-Encoding", encoding.as_str()))
+                .body(body)
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok, "{}", encoding);
+
+            let leaderboard = Entry::all(None, None, &conn).unwrap();
+            assert!(leaderboard.iter().any(|entry| entry.prover == id));
+        }
+
+        let response = client
+            .post("/api/proof")
+            .header(ContentType::JSON)
+            .header(Header::new("Content-Encoding", "lzma"))
+            .body("{}")
+            .dispatch();
+        assert_eq!(response.status(), Status::UnsupportedMediaType);
+    })
+}
+
+#[test]
+fn test_response_encodings() {
+    use std::io::Read;
+
+    use crate::models::leaderboard::Params;
+
+    run_test!(|client, conn| {
+        // Enough entries for the leaderboard to be worth compressing.
+        let params = proof::Params {
+            typ: proof::ProofType::DrgPoRep,
+            hasher: proof::HasherType::Pedersen,
+            size: 1024,
+            challenge_count: 1,
+            vde: 1,
+            degree: 3,
+            replicas: 1,
+            zigzag: None,
+        };
+        let params_id = Params::insert(&params, &conn).unwrap();
+        let mut rng = thread_rng();
+        for repl_time in 1..10 {
+            let prover: String = rng.gen_ascii_chars().take(12).collect();
+            Entry::insert(&prover, repl_time, params_id, None, &conn).unwrap();
+        }
+
+        let mut plain = client.get("/api/leaderboard").dispatch();
+        assert_eq!(plain.status(), Status::Ok);
+        let plain = plain.body_bytes().unwrap();
+        assert!(plain.len() >= encoding::MIN_COMPRESS_SIZE);
+
+        for (accept, encoding) in &[
+            ("gzip;q=0.5, br", Encoding::Brotli),
+            ("zstd, gzip;q=0.9", Encoding::Zstd),
+            ("deflate", Encoding::Deflate),
+        ] {
+            let mut response = client
+                .get("/api/leaderboard")
+                .header(Header::new("Accept-Encoding", *accept))
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(
+                response.headers().get_one("Content-Encoding"),
+                Some(encoding.as_str())
+            );
+
+            let body = response.body_bytes().unwrap();
+            let mut decoded = Vec::new();
+            encoding
+                .decoder(&body[..])
+                .unwrap()
+                .read_to_end(&mut decoded)
+                .unwrap();
+            assert_eq!(decoded, plain);
+        }
+    })
+}
+
+#[test]
+fn test_static_etag() {
+    run_test!(|client, _conn| {
+        let mut response = client.get("/index.html").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let etag = response.headers().get_one("ETag").unwrap().to_string();
+        assert!(!response.body_bytes().unwrap().is_empty());
+
+        let response = client
+            .get("/index.html")
+            .header(Header::new("If-None-Match", etag.clone()))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotModified);
+        assert_eq!(response.headers().get_one("ETag"), Some(etag.as_str()));
+
+        let response = client
+            .get("/")
+            .header(Header::new("If-None-Match", format!("\"other\", {}", etag)))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotModified);
+
+        let response = client
+            .get("/index.html")
+            .header(Header::new("If-None-Match", "\"stale\""))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+    })
+}
+
+#[test]
+fn test_static_precompressed() {
+    use std::io::Read;
+
+    run_test!(|client, _conn| {
+        // Any text asset large enough to have sidecars.
+        let mut paths: Vec<_> = std::fs::read_dir("static")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                let text = path
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .map(|ext| ["css", "html", "js", "json", "svg"].contains(&ext))
+                    .unwrap_or(false);
+                let len = std::fs::metadata(path).unwrap().len();
+                text && len >= encoding::MIN_COMPRESS_SIZE as u64
+            })
+            .collect();
+        paths.sort();
+        let path = paths.first().expect("a compressible asset in static/");
+        let plain = std::fs::read(path).unwrap();
+        let uri = format!("/{}", path.strip_prefix("static").unwrap().display());
+
+        let mut etags = Vec::new();
+        for (accept, encoding) in &[
+            ("br, gzip", Some(Encoding::Brotli)),
+            ("gzip", Some(Encoding::Gzip)),
+            ("identity", None),
+        ] {
+            let mut response = client
+                .get(uri.clone())
+                .header(Header::new("Accept-Encoding", *accept))
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(
+                response.headers().get_one("Content-Encoding"),
+                encoding.map(Encoding::as_str)
+            );
+            assert_eq!(response.headers().get_one("Vary"), Some("Accept-Encoding"));
+            etags.push(response.headers().get_one("ETag").unwrap().to_string());
+
+            let body = response.body_bytes().unwrap();
+            let mut decoded = Vec::new();
+            encoding
+                .unwrap_or(Encoding::Identity)
+                .decoder(&body[..])
+                .unwrap()
+                .read_to_end(&mut decoded)
+                .unwrap();
+            assert_eq!(decoded, plain);
+        }
+
+        // Every representation has its own tag.
+        etags.dedup();
+        assert_eq!(etags.len(), 3);
+    })
+}
+
+#[test]
+fn test_skip_compression() {
+    run_test!(|client, _conn| {
+        // Already compressed.
+        let response = client
+            .get("/android-chrome-512x512.png")
+            .header(Header::new("Accept-Encoding", "br, gzip, zstd, deflate"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::PNG));
+        assert_eq!(response.headers().get_one("Content-Encoding"), None);
+
+        // Too small to bother.
+        let mut response = client
+            .get("/api/seed")
+            .header(Header::new("Accept-Encoding", "br, gzip, zstd, deflate"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Content-Encoding"), None);
+        assert!(response.body_string().unwrap().len() < encoding::MIN_COMPRESS_SIZE);
+    })
+}
+
+#[test]
+fn test_chunked_upload() {
+    use blake2::{Blake2b, Digest};
+
+    use crate::upload::SessionStatus;
+
+    run_test!(|client, conn| {
+        let mut response = client.get("/api/seed").dispatch();
+        let seed: Seed = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+
+        let mut rng = thread_rng();
+        let id: String = rng.gen_ascii_chars().take(12).collect();
+
+        let params = proof::Params {
+            typ: proof::ProofType::DrgPoRep,
+            hasher: proof::HasherType::Pedersen,
+            size: 1024,
+            challenge_count: 1,
+            vde: 1,
+            degree: 3,
+            replicas: 1,
+            zigzag: None,
+        };
+        let res = proofs::porep_work(id.clone(), params, seed.clone());
+        let body = gzip_bytes(serde_json::to_string(&res).unwrap().as_bytes());
+        let chunks: Vec<&[u8]> = body.chunks(body.len() / 4 + 1).collect();
+        assert_eq!(chunks.len(), 4);
+
+        let mut response = client
+            .post("/api/uploads")
+            .header(ContentType::JSON)
+            .body(serde_json::json!({ "seed": seed, "encoding": "gzip" }).to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        let session: SessionStatus =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        let uri = format!("/api/uploads/{}", session.id);
+
+        let put = |i: usize, data: &[u8]| {
+            client
+                .put(format!("{}/{}", uri, i))
+                .header(Header::new(
+                    "Upload-Checksum",
+                    hex::encode(Blake2b::digest(data)),
+                ))
+                .body(data)
+                .dispatch()
+        };
+        let finalize = || {
+            client
+                .post(format!("{}/finalize", uri))
+                .header(ContentType::JSON)
+                .body(r#"{"chunks": 4}"#)
+                .dispatch()
+        };
+
+        assert_eq!(put(0, chunks[0]).status(), Status::Ok);
+        assert_eq!(put(1, chunks[1]).status(), Status::Ok);
+        assert_eq!(put(3, chunks[3]).status(), Status::Ok);
+
+        let mut response = client.get(uri.clone()).dispatch();
+        let status: SessionStatus = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(status.received, vec![(0, 1), (3, 3)]);
+
+        let mut response = finalize();
+        assert_eq!(response.status(), Status::Conflict);
+        assert!(response
+            .body_string()
+            .unwrap()
+            .contains("\"code\":\"incomplete_upload\""));
+
+        // Corrupted in transit.
+        let mut response = client
+            .put(format!("{}/2", uri))
+            .header(Header::new(
+                "Upload-Checksum",
+                hex::encode(Blake2b::digest(chunks[1])),
+            ))
+            .body(chunks[2])
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        assert!(response
+            .body_string()
+            .unwrap()
+            .contains("\"code\":\"checksum_mismatch\""));
+
+        // Resending a chunk is harmless.
+        assert_eq!(put(1, chunks[1]).status(), Status::Ok);
+        assert_eq!(put(2, chunks[2]).status(), Status::Ok);
+
+        let init_leaderboard = Entry::all(None, None, &conn).unwrap();
+        assert_eq!(finalize().status(), Status::Ok);
+        let new_leaderboard = Entry::all(None, None, &conn).unwrap();
+        assert_eq!(new_leaderboard.len(), init_leaderboard.len() + 1);
+        assert!(new_leaderboard.iter().any(|entry| entry.prover == id));
+
+        // The session is gone.
+        assert_eq!(
+            client.get(uri.clone()).dispatch().status(),
+            Status::NotFound
+        );
+    })
+}
+
+#[test]
+fn test_upload_limits() {
+    use blake2::{Blake2b, Digest};
+
+    use crate::error::ErrorCode;
+    use crate::upload::{Format, NewSession, Uploads};
+
+    let dir = tempfile::tempdir().unwrap();
+    let new = || NewSession {
+        seed: Seed {
+            timestamp: 0,
+            seed: "00".repeat(32),
+        },
+        format: Format::Json,
+        encoding: None,
+    };
+    let put = |uploads: &Uploads, id: &str, index: u32, data: &[u8]| {
+        uploads.put_chunk(id, index, data, Blake2b::digest(data).as_slice(), 1 << 20)
+    };
+
+    let config = GameConfig {
+        upload_max_sessions: 2,
+        upload_max_bytes: 10,
+        ..GameConfig::default()
+    };
+    let uploads = Uploads::new(dir.path(), &config).unwrap();
+
+    // Sessions are counted per client address.
+    let a = uploads.create(new(), "10.0.0.1").unwrap();
+    let b = uploads.create(new(), "10.0.0.1").unwrap();
+    let err = uploads.create(new(), "10.0.0.1").unwrap_err();
+    assert_eq!(err.code(), ErrorCode::RateLimited);
+    uploads.create(new(), "10.0.0.2").unwrap();
+
+    // Chunks are counted over all sessions.
+    put(&uploads, &a.id, 0, b"123456").unwrap();
+    let err = put(&uploads, &b.id, 0, b"123456").unwrap_err();
+    assert_eq!(err.code(), ErrorCode::RateLimited);
+    put(&uploads, &b.id, 0, b"1234").unwrap();
+
+    // Expired sessions are removed as soon as a chunk arrives.
+    let config = GameConfig {
+        upload_ttl: 0,
+        ..GameConfig::default()
+    };
+    let uploads = Uploads::new(dir.path(), &config).unwrap();
+    let c = uploads.create(new(), "10.0.0.1").unwrap();
+    let err = put(&uploads, &c.id, 0, b"1234").unwrap_err();
+    assert_eq!(err.code(), ErrorCode::NotFound);
+    assert!(!dir.path().join(&c.id).exists());
+}
+
+fn small_porep(seed: &Seed) -> proof::Response {
+    let mut rng = thread_rng();
+    let id: String = rng.gen_ascii_chars().take(12).collect();
+
+    let params = proof::Params {
+        typ: proof::ProofType::DrgPoRep,
+        hasher: proof::HasherType::Pedersen,
+        size: 1024,
+        challenge_count: 1,
+        vde: 1,
+        degree: 3,
+        replicas: 1,
+        zigzag: None,
+    };
+
+    proofs::porep_work(id, params, seed.clone())
+}
+
+#[test]
+fn test_batch_ndjson() {
+    use crate::batch::{self, ItemResult};
+
+    run_test!(|client, conn| {
+        let mut response = client.get("/api/seed").dispatch();
+        let seed: Seed = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+
+        let good = small_porep(&seed);
+        let mut forged = small_porep(&seed);
+        forged.seed.timestamp += 1;
+
+        let lines = vec![
+            serde_json::to_string(&good).unwrap(),
+            "{ not json".to_string(),
+            String::new(),
+            serde_json::to_string(&forged).unwrap(),
+        ];
+        let body = gzip_bytes(lines.join("\n").as_bytes());
+
+        let init_leaderboard = Entry::all(None, None, &conn).unwrap();
+
+        let mut response = client
+            .post("/api/proofs/batch")
+            .header(batch::ndjson_type())
+            .header(Header::new("Content-Encoding", "gzip"))
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let results: Vec<ItemResult> =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        let codes: Vec<_> = results
+            .iter()
+            .map(|r| {
+                (
+                    r.index,
+                    r.result["status"].clone(),
+                    r.result["code"].clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            codes,
+            vec![
+                (0, "success".into(), serde_json::Value::Null),
+                (1, "failure".into(), "malformed_request".into()),
+                (2, "failure".into(), "invalid_seed".into()),
+            ]
+        );
+        assert_eq!(results[0].prover.as_ref(), Some(&good.prover));
+
+        let new_leaderboard = Entry::all(None, None, &conn).unwrap();
+        assert_eq!(new_leaderboard.len(), init_leaderboard.len() + 1);
+        assert!(new_leaderboard.iter().any(|e| e.prover == good.prover));
+        assert!(!new_leaderboard.iter().any(|e| e.prover == forged.prover));
+
+        // Unknown batch formats are refused as a whole.
+        let mut response = client
+            .post("/api/proofs/batch")
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&good).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::UnsupportedMediaType);
+        assert!(response
+            .body_string()
+            .unwrap()
+            .contains("\"code\":\"unsupported_media_type\""));
+
+        // Items are verified while the batch is read, so a batch that breaks
+        // off keeps the items before the break.
+        let seen = small_porep(&seed);
+        let mut body =
+            gzip_bytes(format!("{}\n", serde_json::to_string(&seen).unwrap()).as_bytes());
+        let len = body.len();
+        body.truncate(len - 4);
+
+        let mut response = client
+            .post("/api/proofs/batch")
+            .header(batch::ndjson_type())
+            .header(Header::new("Content-Encoding", "gzip"))
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let results: Vec<ItemResult> =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].result["status"], "success");
+        assert_eq!(results[1].result["code"], "malformed_request");
+        assert!(Entry::all(None, None, &conn)
+            .unwrap()
+            .iter()
+            .any(|e| e.prover == seen.prover));
+    })
+}
+
+#[test]
+fn test_batch_tar() {
+    use crate::batch::{self, ItemResult};
+
+    run_test!(|client, _conn| {
+        let mut response = client.get("/api/seed").dispatch();
+        let seed: Seed = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+
+        let json = serde_json::to_vec(&small_porep(&seed)).unwrap();
+        let cbor = compact::encode(&small_porep(&seed)).unwrap();
+
+        let mut archive = tar::Builder::new(Vec::new());
+        for (name, data) in &[("a.json", &json), ("b.cbor", &cbor)] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            archive.append_data(&mut header, name, &data[..]).unwrap();
+        }
+        let body = archive.into_inner().unwrap();
+
+        let mut response = client
+            .post("/api/proofs/batch")
+            .header(batch::tar_type())
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let results: Vec<ItemResult> =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| r.result["status"] == "success"));
+    })
+}
+
+#[test]
+fn test_batch_prover_limit() {
+    use crate::batch::{self, ItemResult};
+
+    run_test!(|client, _conn| {
+        let mut response = client.get("/api/seed").dispatch();
+        let seed: Seed = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+
+        // More proofs of one prover than the burst of the prover limit of
+        // batches, which takes a single token for all of them.
+        let mut rng = thread_rng();
+        let id: String = rng.gen_ascii_chars().take(12).collect();
+        let params = proof::Params {
+            typ: proof::ProofType::DrgPoRep,
+            hasher: proof::HasherType::Pedersen,
+            size: 1024,
+            challenge_count: 1,
+            vde: 1,
+            degree: 3,
+            replicas: 1,
+            zigzag: None,
+        };
+        let lines: Vec<_> = (0..12)
+            .map(|_| {
+                let res = proofs::porep_work(id.clone(), params.clone(), seed.clone());
+                serde_json::to_string(&res).unwrap()
+            })
+            .collect();
+
+        let mut response = client
+            .post("/api/proofs/batch")
+            .header(batch::ndjson_type())
+            .body(lines.join("\n"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let results: Vec<ItemResult> =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(results.len(), 12);
+        assert!(results.iter().all(|r| r.result["status"] == "success"));
+    })
+}
+
+#[test]
+fn test_dry_run() {
+    use crate::ratelimit::RateLimits;
+    use crate::routes::proof::Verdict;
+
+    run_test!(|client, conn| {
+        let mut response = client.get("/api/seed").dispatch();
+        let seed: Seed = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+
+        let good = small_porep(&seed);
+        let mut forged = small_porep(&seed);
+        forged.seed.timestamp += 1;
+
+        let init_leaderboard = Entry::all(None, None, &conn).unwrap();
+
+        let verify = |res: &proof::Response| {
+            client
+                .post("/api/proof/verify")
+                .header(ContentType::JSON)
+                .body(serde_json::to_string(res).unwrap())
+                .dispatch()
+        };
+
+        let mut response = verify(&good);
+        assert_eq!(response.status(), Status::Ok);
+        let verdict: Verdict = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert!(verdict.valid);
+        assert!(verdict.repl_time.is_some());
+        assert_eq!(verdict.code, None);
+
+        let mut response = verify(&forged);
+        assert_eq!(response.status(), Status::Ok);
+        let verdict: Verdict = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert!(!verdict.valid);
+        assert_eq!(verdict.repl_time, None);
+        assert_eq!(
+            verdict.code.as_ref().map(String::as_str),
+            Some("invalid_seed")
+        );
+
+        // Nothing made it to the leaderboard.
+        let new_leaderboard = Entry::all(None, None, &conn).unwrap();
+        assert_eq!(new_leaderboard.len(), init_leaderboard.len());
+        assert!(!new_leaderboard.iter().any(|e| e.prover == good.prover));
+
+        let burst = client
+            .rocket()
+            .state::<RateLimits>()
+            .and_then(|limits| limits.rule("verify"))
+            .and_then(|rule| rule.ip)
+            .expect("dry runs are limited")
+            .burst;
+        for _ in 2..burst {
+            assert_eq!(verify(&good).status(), Status::Ok);
+        }
+
+        let mut response = verify(&good);
+        assert_eq!(response.status(), Status::TooManyRequests);
+        assert!(response.headers().get_one("Retry-After").is_some());
+        assert!(response
+            .body_string()
+            .unwrap()
+            .contains("\"code\":\"rate_limited\""));
+
+        // Real submissions are limited separately.
+        let response = client
+            .post("/api/proof")
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&good).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+    })
+}
+
+#[test]
+fn test_duplicate_proofs() {
+    use crate::models::submission::{proof_hash, Submission};
+
+    run_test!(|client, conn| {
+        let mut response = client.get("/api/seed").dispatch();
+        let seed: Seed = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+
+        let res = small_porep(&seed);
+        let post = |res: &proof::Response| {
+            client
+                .post("/api/proof")
+                .header(ContentType::JSON)
+                .body(serde_json::to_string(res).unwrap())
+                .dispatch()
+        };
+
+        assert_eq!(post(&res).status(), Status::Ok);
+
+        let hash = proof_hash(&res);
+        let seen = Submission::find(&hash, &conn).unwrap();
+        assert_eq!(seen.len(), 1);
+        assert!(seen[0].valid);
+
+        // The same proof again gets the cached verdict.
+        assert_eq!(post(&res).status(), Status::Ok);
+        assert_eq!(Submission::find(&hash, &conn).unwrap().len(), 1);
+
+        // The same proof under another name is refused, and flagged.
+        let mut copy = res.clone();
+        copy.prover = format!("{}-copy", res.prover);
+
+        let mut response = post(&copy);
+        assert_eq!(response.status(), Status::Conflict);
+        assert!(response
+            .body_string()
+            .unwrap()
+            .contains("\"code\":\"duplicate_proof\""));
+
+        let flagged = Submission::flagged(&conn).unwrap();
+        assert!(flagged
+            .iter()
+            .any(|s| s.hash == hash && s.prover == copy.prover));
+        assert!(!Entry::all(None, None, &conn)
+            .unwrap()
+            .iter()
+            .any(|e| e.prover == copy.prover));
+
+        // Invalid proofs are remembered as such.
+        let mut bad = small_porep(&seed);
+        bad.proof_params.challenge_count = 2;
+        for _ in 0..2 {
+            let mut response = post(&bad);
+            assert_eq!(response.status(), Status::UnprocessableEntity);
+            assert!(response
+                .body_string()
+                .unwrap()
+                .contains("\"code\":\"invalid_proof\""));
+        }
+        let seen = Submission::find(&proof_hash(&bad), &conn).unwrap();
+        assert_eq!(seen.len(), 1);
+        assert!(!seen[0].valid);
+    })
+}
+
+#[test]
+fn test_rate_limits() {
+    use crate::ratelimit::{forwarded_address, Limiter, Rate, RateLimits};
+
+    // Buckets refill at their rate.
+    let limiter = Limiter::default();
+    let rate = Rate {
+        per_minute: 60,
+        burst: 2,
+    };
+    assert!(limiter.check("a", rate).is_ok());
+    assert!(limiter.check("a", rate).is_ok());
+    let wait = limiter.check("a", rate).unwrap_err();
+    assert!(wait <= std::time::Duration::from_secs(1));
+    assert!(limiter.check("b", rate).is_ok());
+    std::thread::sleep(wait);
+    assert!(limiter.check("a", rate).is_ok());
+
+    // Evicting full buckets refills each at its own rate, not at the rate of
+    // the request that triggered it.
+    let slow = Rate {
+        per_minute: 1,
+        burst: 1,
+    };
+    let fast = Rate {
+        per_minute: u32::max_value(),
+        burst: 1,
+    };
+    let limiter = Limiter::default();
+    for i in 0..10_000 {
+        assert!(limiter.check(&format!("slow{}", i), slow).is_ok());
+    }
+    assert!(limiter.check("fast", fast).is_ok());
+    assert!(limiter.check("slow0", slow).is_err());
+
+    // Only the address appended by the outermost trusted proxy counts.
+    let client = "10.0.0.1".parse().ok();
+    assert_eq!(forwarded_address("1.2.3.4, 10.0.0.1", 1), client);
+    assert_eq!(forwarded_address("1.2.3.4, 10.0.0.1, 10.0.0.2", 2), client);
+    assert_eq!(forwarded_address("10.0.0.1", 2), None);
+    assert_eq!(forwarded_address("", 1), None);
+
+    run_test!(|client, _conn| {
+        let limits = client.rocket().state::<RateLimits>().unwrap();
+        let seed_burst = limits.rule("seed").and_then(|r| r.ip).unwrap().burst;
+        let prover_burst = limits.rule("proof").and_then(|r| r.prover).unwrap().burst;
+
+        let mut response = client.get("/api/seed").dispatch();
+        let seed: Seed = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+
+        for _ in 1..seed_burst {
+            assert_eq!(client.get("/api/seed").dispatch().status(), Status::Ok);
+        }
+        let mut response = client.get("/api/seed").dispatch();
+        assert_eq!(response.status(), Status::TooManyRequests);
+        let retry_after: u64 = response
+            .headers()
+            .get_one("Retry-After")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(retry_after >= 1);
+        assert!(response
+            .body_string()
+            .unwrap()
+            .contains("\"code\":\"rate_limited\""));
+
+        // Without a configured proxy, address headers are ignored.
+        let response = client
+            .get("/api/seed")
+            .header(Header::new("X-Real-IP", "10.0.0.1"))
+            .header(Header::new("X-Forwarded-For", "10.0.0.1"))
+            .dispatch();
+        assert_eq!(response.status(), Status::TooManyRequests);
+
+        // Other routes have their own buckets.
+        assert_eq!(
+            client.get("/api/leaderboard").dispatch().status(),
+            Status::Ok
+        );
+
+        // Prover limits apply once the prover is known, before any
+        // verification happens.
+        let mut forged = small_porep(&seed);
+        forged.seed.timestamp += 1;
+        let body = serde_json::to_string(&forged).unwrap();
+        let post = || {
+            client
+                .post("/api/proof")
+                .header(ContentType::JSON)
+                .body(&body)
+                .dispatch()
+        };
+
+        for _ in 0..prover_burst {
+            assert_eq!(post().status(), Status::Unauthorized);
         }
+        let response = post();
+        assert_eq!(response.status(), Status::TooManyRequests);
+        assert!(response.headers().get_one("Retry-After").is_some());
     })
 }
@@ -0,0 +1,130 @@
+//! Sizes with units, like `16GiB`.
+//!
+//! `KiB`, `MiB`, `GiB` and `TiB` are powers of 1024, `kB`, `MB`, `GB` and
+//! `TB` powers of 1000. Units are matched case-insensitively. A plain number
+//! is counted in the unit the caller picks, which is bytes everywhere except
+//! on the command line, where sizes have always been given in KiB, and where
+//! decimal units are refused.
+
+use std::fmt;
+
+use failure::{ensure, format_err};
+use rocket::http::RawStr;
+use rocket::request::FromFormValue;
+use serde::{Deserialize, Deserializer};
+
+const UNITS: [(&str, u64); 9] = [
+    ("b", 1),
+    ("kib", 1 << 10),
+    ("mib", 1 << 20),
+    ("gib", 1 << 30),
+    ("tib", 1 << 40),
+    ("kb", 1_000),
+    ("mb", 1_000_000),
+    ("gb", 1_000_000_000),
+    ("tb", 1_000_000_000_000),
+];
+
+/// Splits a size into its number and its lowercase unit.
+fn split(raw: &str) -> (&str, String) {
+    let raw = raw.trim();
+    let split = raw
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or_else(|| raw.len());
+    let (number, suffix) = raw.split_at(split);
+
+    (number, suffix.trim().to_ascii_lowercase())
+}
+
+/// Parses a size like `16GiB`, or a plain number of `unit` bytes.
+pub fn parse_size(raw: &str, unit: u64) -> Result<u64, failure::Error> {
+    let raw = raw.trim();
+    let (number, suffix) = split(raw);
+    ensure!(!number.is_empty(), "invalid size {:?}", raw);
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format_err!("invalid size {:?}", raw))?;
+    let unit = if suffix.is_empty() {
+        unit
+    } else {
+        UNITS
+            .iter()
+            .find(|(name, _)| *name == suffix)
+            .map(|&(_, bytes)| bytes)
+            .ok_or_else(|| format_err!("unknown unit {:?} in size {:?}", suffix, raw))?
+    };
+
+    number
+        .checked_mul(unit)
+        .ok_or_else(|| format_err!("size {:?} is too large", raw))
+}
+
+/// Parses a size given on the command line, where plain numbers are in KiB.
+/// Decimal units are refused: `1024kB` is not `1MiB`, and sizes that aren't
+/// powers of two are on no track.
+pub fn parse_cli_size(raw: &str) -> Result<u64, failure::Error> {
+    let (_, suffix) = split(raw);
+    if let Some(&(_, bytes)) = UNITS[5..].iter().find(|(name, _)| *name == suffix) {
+        return Err(format_err!(
+            "{:?} uses a decimal unit of {} bytes, use KiB, MiB, GiB or TiB",
+            raw.trim(),
+            bytes
+        ));
+    }
+
+    parse_size(raw, 1024)
+}
+
+/// A size in bytes, formatted with the largest binary unit it is a whole
+/// multiple of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Size(pub u64);
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unit = ["TiB", "GiB", "MiB", "KiB"]
+            .iter()
+            .zip(&[1u64 << 40, 1 << 30, 1 << 20, 1 << 10])
+            .find(|(_, &bytes)| self.0 != 0 && self.0 % bytes == 0);
+
+        match unit {
+            Some((name, bytes)) => write!(f, "{}{}", self.0 / bytes, name),
+            None => write!(f, "{}B", self.0),
+        }
+    }
+}
+
+/// Query parameters count plain numbers in bytes.
+impl<'v> FromFormValue<'v> for Size {
+    type Error = &'v RawStr;
+
+    fn from_form_value(value: &'v RawStr) -> Result<Size, &'v RawStr> {
+        let decoded = value.url_decode().map_err(|_| value)?;
+        parse_size(&decoded, 1).map(Size).map_err(|_| value)
+    }
+}
+
+/// Deserializes a number of bytes, or a string with a unit like `"16GiB"`.
+pub fn deserialize_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Bytes(u64),
+        Text(String),
+    }
+
+    let bytes = match Raw::deserialize(deserializer)? {
+        Raw::Bytes(bytes) => bytes,
+        Raw::Text(text) => parse_size(&text, 1).map_err(serde::de::Error::custom)?,
+    };
+
+    if bytes > usize::max_value() as u64 {
+        return Err(serde::de::Error::custom(format!(
+            "size {} is too large",
+            bytes
+        )));
+    }
+
+    Ok(bytes as usize)
+}
@@ -0,0 +1,489 @@
+//! Resumable, chunked uploads of proof responses.
+//!
+//! A response that is too large to be sent reliably in one request is split
+//! into numbered chunks, each sent with its checksum to an upload session
+//! tied to a seed. Chunks are stored in a temporary directory until the
+//! session is finalized, at which point the response is decoded from their
+//! concatenation. Sessions without activity for the configured
+//! `upload_ttl` are removed, together with their chunks, whenever a chunk
+//! arrives or a session is opened. Each client address may only keep a few
+//! sessions open, and the chunks of all sessions together are capped, so
+//! abandoned uploads can't fill the disk.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use blake2::{Blake2b, Digest};
+use failure::format_err;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::compact;
+use crate::config::GameConfig;
+use crate::encoding::{self, Body, Encoding};
+use crate::error::{ApiError, ApiResult, ErrorCode};
+use crate::models::proof;
+use crate::models::seed::Seed;
+use crate::routes::proof::now;
+
+/// The serialization of the uploaded response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Json,
+    Cbor,
+}
+
+impl Default for Format {
+    fn default() -> Format {
+        Format::Json
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewSession {
+    pub seed: Seed,
+    #[serde(default)]
+    pub format: Format,
+    /// The `Content-Encoding` of the complete upload, if any.
+    #[serde(default)]
+    pub encoding: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Finalize {
+    /// The total number of chunks, numbered from `0`.
+    pub chunks: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionStatus {
+    pub id: String,
+    /// Inclusive ranges of the chunk numbers received so far.
+    pub received: Vec<(u32, u32)>,
+    pub bytes: u64,
+}
+
+#[derive(Debug)]
+struct Chunk {
+    size: u64,
+    checksum: Vec<u8>,
+    /// Unix time at which the chunk arrived.
+    received_at: u64,
+}
+
+#[derive(Debug)]
+struct Session {
+    /// The address of the client that opened the session.
+    client: String,
+    seed: Seed,
+    format: Format,
+    encoding: Encoding,
+    chunks: BTreeMap<u32, Chunk>,
+    touched: Instant,
+}
+
+impl Session {
+    fn bytes(&self) -> u64 {
+        self.chunks.values().map(|c| c.size).sum()
+    }
+
+    fn status(&self, id: &str) -> SessionStatus {
+        SessionStatus {
+            id: id.to_string(),
+            received: ranges(self.chunks.keys().cloned()),
+            bytes: self.bytes(),
+        }
+    }
+}
+
+/// Collapses increasing numbers into inclusive ranges.
+fn ranges<I: Iterator<Item = u32>>(indices: I) -> Vec<(u32, u32)> {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for i in indices {
+        match ranges.last_mut() {
+            Some(last) if last.1.checked_add(1) == Some(i) => last.1 = i,
+            _ => ranges.push((i, i)),
+        }
+    }
+    ranges
+}
+
+/// All open upload sessions.
+#[derive(Debug)]
+pub struct Uploads {
+    dir: PathBuf,
+    /// How long a session is kept without receiving anything.
+    ttl: Duration,
+    /// The most sessions a client address may keep open.
+    max_sessions: usize,
+    /// The most bytes kept for all sessions together.
+    max_stored: u64,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl Uploads {
+    pub fn new<P: Into<PathBuf>>(dir: P, config: &GameConfig) -> io::Result<Uploads> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        Ok(Uploads {
+            dir,
+            ttl: Duration::from_secs(config.upload_ttl),
+            max_sessions: config.upload_max_sessions,
+            max_stored: config.upload_max_bytes,
+            sessions: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn session_dir(&self, id: &str) -> PathBuf {
+        self.dir.join(id)
+    }
+
+    fn chunk_path(&self, id: &str, index: u32) -> PathBuf {
+        self.session_dir(id).join(format!("{}.chunk", index))
+    }
+
+    /// Opens a session for the client at address `client`.
+    pub fn create(&self, new: NewSession, client: &str) -> ApiResult<SessionStatus> {
+        let encoding = match new.encoding {
+            Some(ref name) => Encoding::from_name(name).ok_or_else(|| {
+                ApiError::new(
+                    ErrorCode::UnsupportedEncoding,
+                    format_err!("Unsupported encoding: {}", name),
+                )
+            })?,
+            None => Encoding::Identity,
+        };
+
+        self.sweep();
+
+        let mut sessions = self.sessions.lock().unwrap();
+        let open = sessions.values().filter(|s| s.client == client).count();
+        if open >= self.max_sessions {
+            return Err(ApiError::new(
+                ErrorCode::RateLimited,
+                format_err!(
+                    "Only {} upload sessions can be open at once, finalize one or let it expire",
+                    self.max_sessions
+                ),
+            )
+            .with_retry_after(self.ttl.as_secs()));
+        }
+
+        let id = hex::encode(thread_rng().gen::<[u8; 16]>());
+        fs::create_dir(self.session_dir(&id))?;
+
+        let session = Session {
+            client: client.to_string(),
+            seed: new.seed,
+            format: new.format,
+            encoding,
+            chunks: BTreeMap::new(),
+            touched: Instant::now(),
+        };
+        let status = session.status(&id);
+        sessions.insert(id, session);
+
+        Ok(status)
+    }
+
+    pub fn status(&self, id: &str) -> ApiResult<SessionStatus> {
+        let sessions = self.sessions.lock().unwrap();
+        let session = sessions.get(id).ok_or_else(|| not_found(id))?;
+
+        Ok(session.status(id))
+    }
+
+    /// Stores a chunk, after checking it against its Blake2b `checksum`.
+    /// Sending a chunk again is fine, as long as it did not change.
+    pub fn put_chunk(
+        &self,
+        id: &str,
+        index: u32,
+        data: &[u8],
+        checksum: &[u8],
+        max_bytes: u64,
+    ) -> ApiResult<SessionStatus> {
+        if Blake2b::digest(data).as_slice() != checksum {
+            return Err(ApiError::new(
+                ErrorCode::ChecksumMismatch,
+                format_err!("Checksum of chunk {} does not match its contents", index),
+            ));
+        }
+
+        self.expire();
+
+        let check = |sessions: &HashMap<String, Session>, session: &Session| -> ApiResult<bool> {
+            if let Some(chunk) = session.chunks.get(&index) {
+                if chunk.checksum == checksum {
+                    return Ok(true);
+                }
+                return Err(ApiError::new(
+                    ErrorCode::ChecksumMismatch,
+                    format_err!("Chunk {} was already received with other contents", index),
+                ));
+            }
+            if session.bytes() + data.len() as u64 > max_bytes {
+                return Err(ApiError::new(
+                    ErrorCode::PayloadTooLarge,
+                    format_err!("Upload is larger than {} bytes", max_bytes),
+                ));
+            }
+            let stored: u64 = sessions.values().map(Session::bytes).sum();
+            if stored + data.len() as u64 > self.max_stored {
+                return Err(ApiError::new(
+                    ErrorCode::RateLimited,
+                    format_err!("The server is holding too many uploads, try again later"),
+                )
+                .with_retry_after(self.ttl.as_secs()));
+            }
+            Ok(false)
+        };
+
+        {
+            let mut sessions = self.sessions.lock().unwrap();
+            let received = {
+                let session = sessions.get(id).ok_or_else(|| not_found(id))?;
+                check(&sessions, session)?
+            };
+            let session = sessions.get_mut(id).expect("checked above");
+            session.touched = Instant::now();
+            if received {
+                return Ok(session.status(id));
+            }
+        }
+
+        // Write outside of the lock, then move the chunk in place once the
+        // session is known to still want it.
+        let tmp = self.session_dir(id).join(format!(
+            "{}.{}.tmp",
+            index,
+            hex::encode(thread_rng().gen::<[u8; 8]>())
+        ));
+        fs::write(&tmp, data)?;
+
+        let mut sessions = self.sessions.lock().unwrap();
+        let checked = match sessions.get(id) {
+            Some(session) => check(&sessions, session),
+            None => Err(not_found(id)),
+        };
+
+        match checked {
+            Ok(false) => {}
+            other => {
+                let _ = fs::remove_file(&tmp);
+                other?;
+                return Ok(sessions[id].status(id));
+            }
+        }
+
+        let session = sessions.get_mut(id).expect("checked above");
+        fs::rename(&tmp, self.chunk_path(id, index))?;
+        session.chunks.insert(
+            index,
+            Chunk {
+                size: data.len() as u64,
+                checksum: checksum.to_vec(),
+                received_at: now(),
+            },
+        );
+        session.touched = Instant::now();
+
+        Ok(session.status(id))
+    }
+
+    /// Closes a session that received exactly the chunks `0..chunks`, and
+    /// hands out its contents.
+    pub fn finalize(&self, id: &str, chunks: u32) -> ApiResult<Upload> {
+        let mut sessions = self.sessions.lock().unwrap();
+        {
+            let session = sessions.get_mut(id).ok_or_else(|| not_found(id))?;
+            session.touched = Instant::now();
+
+            if chunks == 0 {
+                return Err(ApiError::new(
+                    ErrorCode::MalformedRequest,
+                    format_err!("An upload has at least one chunk"),
+                ));
+            }
+
+            let missing = ranges((0..chunks).filter(|i| !session.chunks.contains_key(i)));
+            if !missing.is_empty() {
+                return Err(ApiError::new(
+                    ErrorCode::IncompleteUpload,
+                    format_err!("Missing chunks {:?}", missing),
+                ));
+            }
+
+            if let Some(last) = session.chunks.keys().next_back() {
+                if *last >= chunks {
+                    return Err(ApiError::new(
+                        ErrorCode::MalformedRequest,
+                        format_err!("Received chunk {}, of only {} chunks", last, chunks),
+                    ));
+                }
+            }
+        }
+
+        let session = sessions.remove(id).expect("checked above");
+
+        Ok(Upload {
+            dir: self.session_dir(id),
+            files: (0..chunks).map(|i| self.chunk_path(id, i)).collect(),
+            completion_time: session
+                .chunks
+                .values()
+                .map(|c| c.received_at)
+                .max()
+                .unwrap_or(0),
+            seed: session.seed,
+            format: session.format,
+            encoding: session.encoding,
+        })
+    }
+
+    /// Removes sessions that saw no activity for the session TTL.
+    pub fn expire(&self) {
+        let mut sessions = self.sessions.lock().unwrap();
+
+        let expired: Vec<String> = sessions
+            .iter()
+            .filter(|(_, session)| session.touched.elapsed() >= self.ttl)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &expired {
+            sessions.remove(id);
+            remove_dir(&self.session_dir(id));
+        }
+    }
+
+    /// Removes expired sessions, and leftover directories of earlier runs.
+    pub fn sweep(&self) {
+        self.expire();
+
+        let sessions = self.sessions.lock().unwrap();
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                println!("Warning: could not list {}: {}", self.dir.display(), err);
+                return;
+            }
+        };
+
+        for entry in entries.filter_map(Result::ok) {
+            let open = entry
+                .file_name()
+                .to_str()
+                .map(|id| sessions.contains_key(id))
+                .unwrap_or(false);
+            let stale = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|t| SystemTime::now().duration_since(t).ok())
+                .map(|age| age >= self.ttl)
+                .unwrap_or(false);
+
+            if !open && stale {
+                remove_dir(&entry.path());
+            }
+        }
+    }
+}
+
+fn remove_dir(dir: &Path) {
+    if let Err(err) = fs::remove_dir_all(dir) {
+        println!("Warning: could not remove {}: {}", dir.display(), err);
+    }
+}
+
+fn not_found(id: &str) -> ApiError {
+    ApiError::new(
+        ErrorCode::NotFound,
+        format_err!("Upload session {} does not exist", id),
+    )
+}
+
+/// The contents of a finalized session. The chunks are removed when this is
+/// dropped.
+#[derive(Debug)]
+pub struct Upload {
+    dir: PathBuf,
+    files: VecDeque<PathBuf>,
+    pub seed: Seed,
+    pub format: Format,
+    pub encoding: Encoding,
+    /// Unix time at which the last chunk arrived.
+    pub completion_time: u64,
+}
+
+impl Upload {
+    /// Decodes the response from the concatenated chunks.
+    pub fn decode(&mut self, size_limit: u64, max_ratio: u64) -> ApiResult<proof::Response> {
+        let input = Chunks {
+            files: self.files.clone(),
+            current: None,
+        };
+
+        let envelope: proof::Envelope = match self.format {
+            Format::Json => encoding::decode(self.encoding, input, size_limit, max_ratio)
+                .map_err(|e| ApiError::new(e.code(), format_err!("{}", e)))?,
+            Format::Cbor => {
+                let mut body = Body::new(self.encoding, input, size_limit, max_ratio)?;
+                compact::decode(&mut body).map_err(|e| {
+                    let code = if body.exceeded() {
+                        ErrorCode::PayloadTooLarge
+                    } else {
+                        e.code()
+                    };
+                    ApiError::new(code, format_err!("{}", e))
+                })?
+            }
+        };
+        let res = envelope.open()?;
+
+        if res.seed.seed != self.seed.seed || res.seed.timestamp != self.seed.timestamp {
+            return Err(ApiError::new(
+                ErrorCode::InvalidSeed,
+                format_err!("The response was not made for the seed of its upload session"),
+            ));
+        }
+
+        Ok(res)
+    }
+}
+
+impl Drop for Upload {
+    fn drop(&mut self) {
+        remove_dir(&self.dir);
+    }
+}
+
+/// Reads a list of files, one after the other.
+struct Chunks {
+    files: VecDeque<PathBuf>,
+    current: Option<File>,
+}
+
+impl Read for Chunks {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(ref mut file) = self.current {
+                let n = file.read(buf)?;
+                if n > 0 || buf.is_empty() {
+                    return Ok(n);
+                }
+            }
+
+            match self.files.pop_front() {
+                Some(path) => self.current = Some(File::open(path)?),
+                None => return Ok(0),
+            }
+        }
+    }
+}